@@ -1,88 +1,221 @@
-use std::path::PathBuf;
+use std::io::Read;
+use std::num::NonZeroUsize;
 
-use anyhow::Context;
-use payment_engine::{Engine, Event, errors::EngineError, ledger::Ledger};
+use payment_engine::{
+    Engine, ShardReport, ShardedEngine, TimedEvent,
+    errors::EngineError,
+    event_store::EventStore,
+    ledger::{Ledger, in_memory::InMemoryLedger},
+    policy::Policies,
+    reconciliation::Reconciliation,
+};
 
-use crate::app::models::{InputRow, OutputRow};
+use crate::app::models::{DiscrepancyRow, InputRow, OutputRow};
+use crate::app::rejects::{Rejects, Source};
 
+pub mod config;
+pub mod input;
 pub mod models;
+pub mod rejects;
 
 #[derive(Debug, Default)]
-pub struct App {}
+pub struct App {
+    rejects: Rejects,
+    policies: Policies,
+    /// Reconcile the balances with the ledger after processing, see [App::with_reconciliation].
+    reconcile: bool,
+}
 
 impl App {
     pub fn new() -> Self {
-        App {}
+        App::default()
     }
 
-    pub async fn process(&self, ledger: impl Ledger, input: PathBuf) -> anyhow::Result<()> {
-        // csv crate also supports reading for a reader (that impls io::Read)
-        // So this could be used to stream large data from network or any other sources.
-        let csv_reader = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All) // allow whitespaces in csv header and fields
-            .flexible(true) // dispute/resolve/chargeback won't have the amount field
-            .from_path(input)
-            .context("Read the provided csv file")?;
+    /// Rejected rows are reported to `rejects` instead of stderr only.
+    pub fn with_rejects(rejects: Rejects) -> Self {
+        App {
+            rejects,
+            ..App::default()
+        }
+    }
 
-        let mut engine = Engine::new(ledger);
-        process_transactions_from_csv(&mut engine, csv_reader).await?;
+    /// Apply `policies` instead of the built-in rules, see [crate::app::config::Config].
+    pub fn with_policies(self, policies: Policies) -> Self {
+        App { policies, ..self }
+    }
 
-        let accounts = engine.accounts();
-        print_accounts(accounts)?;
+    /// Check the balances against the ledger once the input is processed (see [App::reconcile]).
+    /// Processing fails with the discrepancies on stderr if any, the accounts aren't written.
+    pub fn with_reconciliation(self) -> Self {
+        App {
+            reconcile: true,
+            ..self
+        }
+    }
+
+    pub async fn process(
+        &self,
+        ledger: impl Ledger,
+        events: impl EventStore,
+        input: impl Read,
+    ) -> anyhow::Result<()> {
+        let csv_reader = csv_reader(input);
+
+        let mut engine = Engine::with_event_store(ledger, events)
+            .await?
+            .with_policies(self.policies.clone());
+        engine.recover().await?;
+        process_transactions_from_csv(&mut engine, csv_reader, &self.rejects).await?;
+        self.rejects.finish()?;
+        if self.reconcile {
+            check(engine.reconcile().await?, std::io::stderr())?;
+        }
+
+        let accounts = engine.accounts_ordered();
+        print_accounts(accounts.into_iter())?;
 
         Ok(())
     }
+
+    /// Recompute the balances of every client from the transactions stored in `ledger` and write those
+    /// that don't match the stored accounts as csv (see [DiscrepancyRow]). Fails if any don't match.
+    pub async fn reconcile(&self, ledger: impl Ledger) -> anyhow::Result<()> {
+        let engine = Engine::new(ledger).await?;
+        check(engine.reconcile().await?, std::io::stdout())
+    }
+
+    /// Process the clients concurrently on `shards` in-memory engines.
+    /// The output is identical to [App::process].
+    pub async fn process_sharded(
+        &self,
+        shards: NonZeroUsize,
+        input: impl Read,
+    ) -> anyhow::Result<()> {
+        let csv_reader = csv_reader(input);
+
+        let rejects = self.rejects.clone();
+        let mut engine = ShardedEngine::with_policies(
+            shards,
+            InMemoryLedger::new,
+            self.policies.clone(),
+            move |event, source, report| match report {
+                ShardReport::Rejected(err) => rejects.rejected(source, &event, &err),
+                ShardReport::OutOfOrder => rejects.out_of_order(source, &event),
+            },
+        )
+        .await?;
+        for (event, source) in read_events(csv_reader, &self.rejects)? {
+            engine.apply(event, source).await?;
+        }
+
+        let finished = engine.finish().await?;
+        self.rejects.finish()?;
+        print_accounts(finished.accounts.iter())?;
+
+        Ok(())
+    }
+}
+
+/// Streams the csv rows from any reader (file, stdin, decompressor, ...), nothing is buffered in full.
+fn csv_reader<R: Read>(input: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All) // allow whitespaces in csv header and fields
+        .flexible(true) // dispute/resolve/chargeback won't have the amount field
+        .from_reader(input)
 }
 
 async fn process_transactions_from_csv(
-    engine: &mut Engine<impl Ledger>,
-    mut reader: csv::Reader<std::fs::File>,
+    engine: &mut Engine<impl Ledger, impl EventStore>,
+    reader: csv::Reader<impl Read>,
+    rejects: &Rejects,
 ) -> anyhow::Result<()> {
-    for entry in reader.deserialize::<InputRow>() {
-        match entry {
-            Ok(entry) => {
-                let event = match Event::try_from(entry) {
-                    Ok(event) => event,
-                    Err(err) => {
-                        eprintln!("Error parsing entry: {}", err);
-                        continue;
-                    }
+    for (event, source) in read_events(reader, rejects)? {
+        match engine.apply(event).await {
+            Ok(receipt) if receipt.out_of_order => rejects.out_of_order(source, &event.event),
+            Ok(_) => (),
+            Err(err) if err.is_partner_error() => rejects.rejected(source, &event.event, &err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    expire_disputes(engine).await?;
+
+    Ok(())
+}
+
+/// Settle the disputes that expired by the latest row of the batch, see [Engine::expire_disputes].
+///
+/// The engine settles the disputes of a client before its next row, those of a client without later rows
+/// are only settled here.
+pub async fn expire_disputes(
+    engine: &mut Engine<impl Ledger, impl EventStore>,
+) -> Result<(), EngineError> {
+    if let Some(latest) = engine.latest_timestamp() {
+        engine.expire_disputes(latest).await?;
+    }
+
+    Ok(())
+}
+
+/// Events of the csv file with their source row.
+/// Rows that can't be read or parsed are reported and skipped.
+fn read_events(
+    mut reader: csv::Reader<impl Read>,
+    rejects: &Rejects,
+) -> anyhow::Result<impl Iterator<Item = (TimedEvent, Source)>> {
+    let headers = reader.headers()?.clone();
+
+    Ok(reader.into_records().filter_map(move |record| {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let source = Source {
+                    line: err.position().map_or(0, |position| position.line()),
+                    row: String::new(),
                 };
+                rejects.malformed(source, &err);
+                return None;
+            }
+        };
 
-                match engine.apply(event).await {
-                    Ok(_) => (),
-                    Err(err) => match err {
-                        EngineError::InvalidAssociatedTransaction(_)
-                        | EngineError::InsufficientFunds
-                        | EngineError::InvalidTransactionStatus(_)
-                        | EngineError::DuplicateEvent
-                        | EngineError::AccountLocked(_)
-                        | EngineError::InvalidEvent(_) => {
-                            eprintln!(
-                                "Partner Data Error for TxId: {}, ClientId: {}: {}",
-                                entry.tx, entry.client, err
-                            )
-                        }
-                        EngineError::SystemError(error) => {
-                            anyhow::bail!("System Error: {error}");
-                        }
-                    },
+        let source = Source::from_record(&record);
+        match record.deserialize::<InputRow>(Some(&headers)) {
+            Ok(entry) => match TimedEvent::try_from(entry) {
+                Ok(event) => Some((event, source)),
+                Err(err) => {
+                    rejects.invalid(source, &err);
+                    None
                 }
+            },
+            Err(err) => {
+                rejects.malformed(source, &err);
+                None
             }
-            Err(err) => eprintln!("Error reading entry: {}", err),
         }
-    }
-
-    Ok(())
+    }))
 }
 
 fn print_accounts<'a>(
     accounts: impl Iterator<Item = &'a payment_engine::ClientAccount>,
 ) -> Result<(), anyhow::Error> {
-    let mut w = csv::Writer::from_writer(std::io::stdout());
-    for a in accounts {
-        w.serialize(OutputRow::from(a))?;
+    let rows = accounts.flat_map(OutputRow::of).collect::<Vec<_>>();
+    OutputRow::write_csv(&rows, std::io::stdout())
+}
+
+/// Write the discrepancies of the reconciliation to `writer`, fails if there are any.
+fn check(reconciliation: Reconciliation, writer: impl std::io::Write) -> anyhow::Result<()> {
+    if reconciliation.is_clean() {
+        return Ok(());
     }
-    w.flush()?;
-    Ok(())
+
+    let rows = reconciliation
+        .discrepancies
+        .iter()
+        .map(DiscrepancyRow::of)
+        .collect::<Vec<_>>();
+    DiscrepancyRow::write_csv(&rows, writer)?;
+    anyhow::bail!(
+        "{} of {} balances don't match the ledger",
+        rows.len(),
+        reconciliation.balances
+    )
 }
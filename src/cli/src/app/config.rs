@@ -0,0 +1,480 @@
+//! Engine configuration loaded from a TOML file (`--config`).
+//!
+//! ```toml
+//! # default policy, applies to every client without a partner
+//! negative_balance = "allow"
+//! timestamps = "flag"
+//!
+//! [deadlines]
+//! dispute_window = 7776000 # seconds, 90 days
+//! max_disputed = 2592000
+//! on_expiry = "chargeback"
+//!
+//! [fees]
+//! tiers = { gold = [1] }
+//! rules = [
+//!     { event = "withdrawal", percent = 1.5 },
+//!     { event = "chargeback", fixed = 15, currency = "USD" },
+//!     { event = "chargeback", fixed = 5, currency = "USD", tier = "gold" },
+//! ]
+//!
+//! [risk]
+//! max_amount = 10000
+//! withdrawal_velocity = { max_count = 3, window = 86400 }
+//! max_withdrawal_percent = 50
+//! review_threshold = 5000
+//!
+//! [transitions]
+//! redispute_resolved = true
+//! allow = [{ direction = "inbound", from = "settled", to = "chargedback" }]
+//!
+//! # policy of a partner, applies to its clients only
+//! [[partners]]
+//! clients = [2, 3]
+//! transitions.forbid = [{ direction = "outbound", from = "settled", to = "disputed" }]
+//! ```
+//!
+//! `allow` and `forbid` change the built-in transitions, `table` replaces them.
+//!
+//! A partner inherits what it doesn't set from the top level: the keys of `[deadlines]` and `[risk]`,
+//! `negative_balance`, `timestamps` and the whole `[fees]` schedule. Its `transitions` change the
+//! top-level ones. Unknown keys are rejected.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use payment_engine::fees::{Fee, FeeEvent, FeeRule, FeeSchedule};
+use payment_engine::ledger::transactions::{Direction, TransactionStatus};
+use payment_engine::policy::{
+    DisputeDeadlines, ExpiryAction, NegativeBalancePolicy, Policies, Policy, TimestampPolicy,
+    TransitionPolicy,
+};
+use payment_engine::risk::{
+    MaxAmount, MaxWithdrawalPercent, ReviewThreshold, RiskRules, WithdrawalVelocity,
+};
+use payment_engine::types::{ClientId, Currency};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(try_from = "RawConfig")]
+pub struct Config {
+    pub policy: PolicyConfig,
+    pub partners: Vec<PartnerConfig>,
+}
+
+/// `deny_unknown_fields` doesn't work along with `flatten`: the keys left over by the policy are
+/// collected in `unknown` instead, and rejected.
+#[derive(Debug, serde::Deserialize)]
+struct RawConfig {
+    #[serde(flatten)]
+    policy: PolicyConfig,
+    #[serde(default)]
+    partners: Vec<PartnerConfig>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl TryFrom<RawConfig> for Config {
+    type Error = String;
+
+    fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+        reject_unknown(&raw.unknown)?;
+        Ok(Self {
+            policy: raw.policy,
+            partners: raw.partners,
+        })
+    }
+}
+
+fn reject_unknown(unknown: &BTreeMap<String, toml::Value>) -> Result<(), String> {
+    match unknown.keys().next() {
+        Some(key) => Err(format!("unknown key `{key}`")),
+        None => Ok(()),
+    }
+}
+
+/// A policy, every key is optional: see [PolicyConfig::policy_over] for what is used instead.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub transitions: TransitionsConfig,
+    /// Whether disputes and chargebacks may drive balances negative, `reject` (default) or `allow`.
+    pub negative_balance: Option<NegativeBalanceConfig>,
+    /// What happens to events older than the latest event of their client, `reject` (default) or `flag`.
+    pub timestamps: Option<TimestampsConfig>,
+    #[serde(default)]
+    pub deadlines: DeadlinesConfig,
+    pub fees: Option<FeesConfig>,
+    #[serde(default)]
+    pub risk: RiskConfig,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NegativeBalanceConfig {
+    #[default]
+    Reject,
+    Allow,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampsConfig {
+    #[default]
+    Reject,
+    Flag,
+}
+
+/// Time limits of disputes in seconds, none by default.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeadlinesConfig {
+    pub dispute_window: Option<u64>,
+    pub max_disputed: Option<u64>,
+    /// How expired disputes are settled, `resolve` (default) or `chargeback`.
+    pub on_expiry: Option<ExpiryConfig>,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryConfig {
+    #[default]
+    Resolve,
+    Chargeback,
+}
+
+/// Fee schedule, nothing is charged by default.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(try_from = "RawFees")]
+pub struct FeesConfig {
+    pub schedule: FeeSchedule,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFees {
+    /// Clients of each tier.
+    #[serde(default)]
+    tiers: BTreeMap<String, Vec<u16>>,
+    #[serde(default)]
+    rules: Vec<RawFeeRule>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFeeRule {
+    event: FeeEventConfig,
+    tier: Option<String>,
+    currency: Option<String>,
+    /// In the currency of the event.
+    #[serde(default)]
+    fixed: Decimal,
+    /// Percentage of the amount of the event, eg. `1.5` for 1.5%.
+    #[serde(default)]
+    percent: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FeeEventConfig {
+    Deposit,
+    Withdrawal,
+    Chargeback,
+}
+
+impl TryFrom<RawFees> for FeesConfig {
+    type Error = String;
+
+    fn try_from(raw: RawFees) -> Result<Self, Self::Error> {
+        let mut schedule = FeeSchedule::new();
+        for (tier, clients) in &raw.tiers {
+            schedule =
+                schedule.with_tier(tier.as_str(), clients.iter().copied().map(ClientId::from));
+        }
+
+        for rule in raw.rules {
+            if let Some(tier) = &rule.tier
+                && !raw.tiers.contains_key(tier)
+            {
+                return Err(format!("unknown fee tier `{tier}`"));
+            }
+            if rule.fixed.is_sign_negative() || rule.percent.is_sign_negative() {
+                return Err("fees can't be negative".to_string());
+            }
+            let currency = match &rule.currency {
+                Some(code) => {
+                    Some(Currency::new(code).ok_or(format!("invalid currency code `{code}`"))?)
+                }
+                None => None,
+            };
+            schedule = schedule.with_rule(FeeRule {
+                event: match rule.event {
+                    FeeEventConfig::Deposit => FeeEvent::Deposit,
+                    FeeEventConfig::Withdrawal => FeeEvent::Withdrawal,
+                    FeeEventConfig::Chargeback => FeeEvent::Chargeback,
+                },
+                tier: rule.tier,
+                currency,
+                fee: Fee {
+                    fixed: rule.fixed.into(),
+                    percent: rule.percent,
+                },
+            });
+        }
+
+        Ok(Self { schedule })
+    }
+}
+
+/// Risk rules checked before deposits and withdrawals, in units of their currency. None by default.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskConfig {
+    pub max_amount: Option<Decimal>,
+    pub withdrawal_velocity: Option<VelocityConfig>,
+    /// Percentage of the available funds, eg. `50` for 50%.
+    pub max_withdrawal_percent: Option<Decimal>,
+    /// Deposits over this amount are held for review.
+    pub review_threshold: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityConfig {
+    pub max_count: usize,
+    /// In seconds.
+    pub window: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(try_from = "RawPartner")]
+pub struct PartnerConfig {
+    pub clients: Vec<u16>,
+    pub policy: PolicyConfig,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RawPartner {
+    clients: Vec<u16>,
+    #[serde(flatten)]
+    policy: PolicyConfig,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl TryFrom<RawPartner> for PartnerConfig {
+    type Error = String;
+
+    fn try_from(raw: RawPartner) -> Result<Self, Self::Error> {
+        reject_unknown(&raw.unknown)?;
+        Ok(Self {
+            clients: raw.clients,
+            policy: raw.policy,
+        })
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionsConfig {
+    /// Replaces the built-in transitions if given.
+    pub table: Option<Vec<TransitionConfig>>,
+    #[serde(default)]
+    pub allow: Vec<TransitionConfig>,
+    #[serde(default)]
+    pub forbid: Vec<TransitionConfig>,
+    pub redispute_resolved: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(try_from = "RawTransition")]
+pub struct TransitionConfig {
+    pub direction: Direction,
+    pub from: TransactionStatus,
+    pub to: TransactionStatus,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTransition {
+    direction: DirectionConfig,
+    from: StatusConfig,
+    to: StatusConfig,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DirectionConfig {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StatusConfig {
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Reversed,
+}
+
+impl TryFrom<RawTransition> for TransitionConfig {
+    type Error = String;
+
+    fn try_from(raw: RawTransition) -> Result<Self, Self::Error> {
+        let direction = match raw.direction {
+            DirectionConfig::Inbound => Direction::Inbound,
+            DirectionConfig::Outbound => Direction::Outbound,
+        };
+        let status = |status| match (status, direction) {
+            (StatusConfig::Settled, _) => Ok(TransactionStatus::Settled),
+            (StatusConfig::Disputed, _) => Ok(TransactionStatus::Disputed),
+            (StatusConfig::Resolved, _) => Ok(TransactionStatus::Resolved),
+            (StatusConfig::ChargedBack, Direction::Inbound) => Ok(TransactionStatus::ChargedBack),
+            (StatusConfig::Reversed, Direction::Outbound) => Ok(TransactionStatus::Reversed),
+            (status, direction) => Err(format!(
+                "{direction:?} transactions can't be {status:?}, chargedback is inbound and reversed outbound only"
+            )),
+        };
+
+        Ok(Self {
+            direction,
+            from: status(raw.from)?,
+            to: status(raw.to)?,
+        })
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Read the config file {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Parse the config file {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// The default policy and the policies of the partners, merged over the default one.
+    pub fn policies(&self) -> Policies {
+        self.partners
+            .iter()
+            .fold(Policies::new(self.policy.policy()), |policies, partner| {
+                policies.with_partner(
+                    partner.clients.iter().copied().map(ClientId::from),
+                    partner.policy.policy_over(&self.policy),
+                )
+            })
+    }
+}
+
+impl PolicyConfig {
+    /// The policy, with the built-in defaults for what isn't set.
+    pub fn policy(&self) -> Policy {
+        self.policy_over(&PolicyConfig::default())
+    }
+
+    /// The policy, with what isn't set taken from `base` (eg. the top level for a partner).
+    pub fn policy_over(&self, base: &PolicyConfig) -> Policy {
+        let deadlines = DeadlinesConfig {
+            dispute_window: self
+                .deadlines
+                .dispute_window
+                .or(base.deadlines.dispute_window),
+            max_disputed: self.deadlines.max_disputed.or(base.deadlines.max_disputed),
+            on_expiry: self.deadlines.on_expiry.or(base.deadlines.on_expiry),
+        };
+        Policy {
+            transitions: self.transitions.policy_over(base.transitions.policy()),
+            negative_balance: match self.negative_balance.or(base.negative_balance) {
+                None | Some(NegativeBalanceConfig::Reject) => NegativeBalancePolicy::Reject,
+                Some(NegativeBalanceConfig::Allow) => NegativeBalancePolicy::Allow,
+            },
+            timestamps: match self.timestamps.or(base.timestamps) {
+                None | Some(TimestampsConfig::Reject) => TimestampPolicy::Reject,
+                Some(TimestampsConfig::Flag) => TimestampPolicy::Flag,
+            },
+            deadlines: DisputeDeadlines {
+                dispute_window: deadlines.dispute_window,
+                max_disputed: deadlines.max_disputed,
+                on_expiry: match deadlines.on_expiry {
+                    None | Some(ExpiryConfig::Resolve) => ExpiryAction::Resolve,
+                    Some(ExpiryConfig::Chargeback) => ExpiryAction::Chargeback,
+                },
+            },
+            fees: self
+                .fees
+                .as_ref()
+                .or(base.fees.as_ref())
+                .map(|fees| fees.schedule.clone())
+                .unwrap_or_default(),
+            risk: self.risk.over(&base.risk).rules(),
+        }
+    }
+}
+
+impl RiskConfig {
+    /// The rules set here, and those of `base` that aren't.
+    pub fn over(&self, base: &RiskConfig) -> RiskConfig {
+        RiskConfig {
+            max_amount: self.max_amount.or(base.max_amount),
+            withdrawal_velocity: self.withdrawal_velocity.or(base.withdrawal_velocity),
+            max_withdrawal_percent: self.max_withdrawal_percent.or(base.max_withdrawal_percent),
+            review_threshold: self.review_threshold.or(base.review_threshold),
+        }
+    }
+
+    pub fn rules(&self) -> RiskRules {
+        let mut rules = RiskRules::new();
+        if let Some(max) = self.max_amount {
+            rules = rules.with_rule(MaxAmount { max: max.into() });
+        }
+        if let Some(velocity) = self.withdrawal_velocity {
+            rules = rules.with_rule(WithdrawalVelocity {
+                max_count: velocity.max_count,
+                window: velocity.window,
+            });
+        }
+        if let Some(percent) = self.max_withdrawal_percent {
+            rules = rules.with_rule(MaxWithdrawalPercent { percent });
+        }
+        if let Some(threshold) = self.review_threshold {
+            rules = rules.with_rule(ReviewThreshold {
+                threshold: threshold.into(),
+            });
+        }
+        rules
+    }
+}
+
+impl TransitionsConfig {
+    /// The transitions changed from the built-in ones.
+    pub fn policy(&self) -> TransitionPolicy {
+        self.policy_over(TransitionPolicy::default())
+    }
+
+    /// The transitions changed from `base`, unless a table replaces them.
+    pub fn policy_over(&self, base: TransitionPolicy) -> TransitionPolicy {
+        let mut policy = match &self.table {
+            Some(table) => allow(TransitionPolicy::empty(), table),
+            None => base,
+        };
+        policy = allow(policy, &self.allow);
+        for transition in &self.forbid {
+            policy = policy.forbid(transition.direction, transition.from, transition.to);
+        }
+        match self.redispute_resolved {
+            Some(redispute_resolved) => policy.with_redispute_resolved(redispute_resolved),
+            None => policy,
+        }
+    }
+}
+
+fn allow(policy: TransitionPolicy, transitions: &[TransitionConfig]) -> TransitionPolicy {
+    transitions.iter().fold(policy, |policy, transition| {
+        policy.allow(transition.direction, transition.from, transition.to)
+    })
+}
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Opens the input file, `-` reads from stdin.
+/// See [decompress] for the supported compressions.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    if path == Path::new("-") {
+        decompress(io::stdin().lock())
+    } else {
+        decompress(File::open(path)?)
+    }
+}
+
+/// Wraps the reader into a gzip or zstd decoder if the data starts with their magic bytes.
+/// Detecting by content (rather than by file extension) allows compressed data to be piped to stdin too.
+/// Other data is passed through as is.
+pub fn decompress(mut reader: impl Read + 'static) -> io::Result<Box<dyn Read>> {
+    // A pipe may hand out the magic bytes over several reads, a single `fill_buf` isn't enough.
+    let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+    reader
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let is_gzip = head.starts_with(GZIP_MAGIC);
+    let is_zstd = head.starts_with(ZSTD_MAGIC);
+    let reader = BufReader::new(io::Cursor::new(head).chain(reader));
+
+    if is_gzip {
+        // Partner exports are often concatenated gzip files, hence the multi member decoder.
+        Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if is_zstd {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
@@ -1,5 +1,7 @@
 use anyhow::Context;
-use payment_engine::{ClientAccount, Event};
+use payment_engine::reconciliation::Discrepancy;
+use payment_engine::types::{Amount, Currency, Money, Timestamp};
+use payment_engine::{Balance, ClientAccount, Event, ReasonCode, TimedEvent};
 use rust_decimal::Decimal;
 
 #[derive(Debug, serde::Deserialize, Clone, Copy)]
@@ -9,7 +11,45 @@ pub struct InputRow {
     pub ty: EntryType,
     pub client: u16,
     pub tx: u32,
+    /// Optional for dispute/resolve/chargeback: only this part of the transaction is disputed, resolved or charged back.
     pub amount: Option<Decimal>,
+    /// Optional column, amounts without currency use [Currency::NONE].
+    /// Ignored by dispute/resolve/chargeback, they act on the currency of the original transaction.
+    #[serde(default)]
+    pub currency: Option<CurrencyCode>,
+    /// Optional column, required by unlock/freeze/close (eg. `compliance`, see [ReasonCode]).
+    #[serde(default)]
+    pub reason: Option<Reason>,
+    /// Optional column, when the event happened in seconds since the Unix epoch.
+    /// Events without timestamp take the time they are processed at.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+/// A currency code of the input, eg. `EUR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrencyCode(pub Currency);
+
+impl<'de> serde::Deserialize<'de> for CurrencyCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Currency::new(&code)
+            .map(CurrencyCode)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid currency `{code}`")))
+    }
+}
+
+/// A reason code of the input, eg. `compliance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reason(pub ReasonCode);
+
+impl<'de> serde::Deserialize<'de> for Reason {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        ReasonCode::new(&code)
+            .map(Reason)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid reason `{code}`")))
+    }
 }
 
 #[derive(Debug, serde::Deserialize, Clone, Copy)]
@@ -20,63 +60,214 @@ pub enum EntryType {
     Dispute,
     Resolve,
     Chargeback,
+    Unlock,
+    Freeze,
+    Close,
+    /// Approve a transaction held for review, `tx` is the held transaction.
+    Approve,
 }
 
-#[derive(Debug, serde::Serialize, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
+/// Balance of a client, the output has one row per client and currency.
+///
+/// The currency column is only written if any client holds a currency (see [OutputRow::has_currency]),
+/// the deficit column only if any balance is negative (see [OutputRow::has_deficit]),
+/// so the output of single currency input without negative balances keeps its format.
+#[derive(Debug, Clone, Copy)]
 pub struct OutputRow {
     pub client: u16,
+    pub currency: Currency,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
+    /// What the client owes, see [payment_engine::Balance::deficit].
+    pub deficit: Decimal,
     pub locked: bool,
 }
 
+/// The optional columns are skipped for all rows alike, so they match the header.
+#[derive(Debug, serde::Serialize)]
+struct Row<'a> {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a str>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deficit: Option<Decimal>,
+    locked: bool,
+}
+
+/// A balance that doesn't match the ledger, the report of a reconciliation has one row per discrepancy.
+///
+/// The `expected_*` columns are recomputed from the ledger, the others are stored on the account
+/// (`held` is negative if the balance drifted below the available funds).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DiscrepancyRow {
+    pub client: u16,
+    pub currency: String,
+    pub expected_available: Decimal,
+    pub expected_held: Decimal,
+    pub expected_total: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
 impl TryFrom<InputRow> for Event {
     type Error = anyhow::Error;
 
     fn try_from(entry: InputRow) -> anyhow::Result<Self> {
+        let currency = entry
+            .currency
+            .map_or(Currency::NONE, |CurrencyCode(currency)| currency);
+        let money = |amount: Decimal| Money::new(amount, currency);
+        let reason = |ty| {
+            entry
+                .reason
+                .map(|Reason(reason)| reason)
+                .with_context(|| format!("Reason is required for {ty}"))
+        };
+
         Ok(match entry.ty {
             EntryType::Deposit => Event::Deposit {
                 client_id: entry.client.into(),
                 transaction_id: entry.tx.into(),
-                amount: entry
-                    .amount
-                    .context("Amount is required for Deposit")?
-                    .into(),
+                amount: money(entry.amount.context("Amount is required for Deposit")?),
             },
             EntryType::Withdrawal => Event::Withdraw {
                 client_id: entry.client.into(),
                 transaction_id: entry.tx.into(),
-                amount: entry
-                    .amount
-                    .context("Amount is required for Withdrawal")?
-                    .into(),
+                amount: money(entry.amount.context("Amount is required for Withdrawal")?),
             },
             EntryType::Dispute => Event::Dispute {
                 client_id: entry.client.into(),
                 transaction_id: entry.tx.into(),
+                amount: entry.amount.map(Amount::from),
             },
             EntryType::Resolve => Event::Resolve {
                 client_id: entry.client.into(),
                 transaction_id: entry.tx.into(),
+                amount: entry.amount.map(Amount::from),
             },
             EntryType::Chargeback => Event::Chargeback {
                 client_id: entry.client.into(),
                 transaction_id: entry.tx.into(),
+                amount: entry.amount.map(Amount::from),
+            },
+            EntryType::Unlock => Event::Unlock {
+                client_id: entry.client.into(),
+                transaction_id: entry.tx.into(),
+                reason: reason("Unlock")?,
+            },
+            EntryType::Freeze => Event::Freeze {
+                client_id: entry.client.into(),
+                transaction_id: entry.tx.into(),
+                reason: reason("Freeze")?,
+            },
+            EntryType::Close => Event::Close {
+                client_id: entry.client.into(),
+                transaction_id: entry.tx.into(),
+                reason: reason("Close")?,
+            },
+            EntryType::Approve => Event::Approve {
+                client_id: entry.client.into(),
+                transaction_id: entry.tx.into(),
             },
         })
     }
 }
 
-impl From<&ClientAccount> for OutputRow {
-    fn from(account: &ClientAccount) -> Self {
-        Self {
-            client: account.client_id.as_inner(),
-            available: account.available.as_decimal(),
-            held: account.held().as_decimal(),
-            total: account.total.as_decimal(),
-            locked: account.is_locked,
+impl TryFrom<InputRow> for TimedEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: InputRow) -> anyhow::Result<Self> {
+        Ok(TimedEvent {
+            event: Event::try_from(entry)?,
+            timestamp: entry.timestamp.map(Timestamp::from),
+        })
+    }
+}
+
+impl OutputRow {
+    /// One row per currency of the account, ordered by currency.
+    /// An account without balance (eg. frozen before its first deposit) has a zero row without currency.
+    pub fn of(account: &ClientAccount) -> impl Iterator<Item = OutputRow> + '_ {
+        let empty = account
+            .balances
+            .is_empty()
+            .then_some((Currency::NONE, Balance::default()));
+        account
+            .balances
+            .iter()
+            .map(|(currency, balance)| (*currency, *balance))
+            .chain(empty)
+            .map(|(currency, balance)| OutputRow {
+                client: account.client_id.as_inner(),
+                currency,
+                available: balance.available.as_decimal(),
+                held: balance.held().as_decimal(),
+                total: balance.total.as_decimal(),
+                deficit: balance.deficit().as_decimal(),
+                locked: account.is_locked(),
+            })
+    }
+
+    /// Whether the currency column is written for these rows.
+    pub fn has_currency(rows: &[OutputRow]) -> bool {
+        rows.iter().any(|row| row.currency != Currency::NONE)
+    }
+
+    /// Whether the deficit column is written for these rows.
+    pub fn has_deficit(rows: &[OutputRow]) -> bool {
+        rows.iter().any(|row| !row.deficit.is_zero())
+    }
+
+    /// Write the rows as csv, see [OutputRow] for the columns.
+    pub fn write_csv(rows: &[OutputRow], writer: impl std::io::Write) -> anyhow::Result<()> {
+        let mut w = csv::Writer::from_writer(writer);
+        let with_currency = Self::has_currency(rows);
+        let with_deficit = Self::has_deficit(rows);
+        for row in rows {
+            w.serialize(Row {
+                client: row.client,
+                currency: with_currency.then(|| row.currency.as_str()),
+                available: row.available,
+                held: row.held,
+                total: row.total,
+                deficit: with_deficit.then_some(row.deficit),
+                locked: row.locked,
+            })?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+impl DiscrepancyRow {
+    pub fn of(discrepancy: &Discrepancy) -> Self {
+        let Discrepancy {
+            expected, actual, ..
+        } = discrepancy;
+        DiscrepancyRow {
+            client: discrepancy.client_id.as_inner(),
+            currency: discrepancy.currency.as_str().to_string(),
+            expected_available: expected.available.as_decimal(),
+            expected_held: expected.held.as_decimal(),
+            expected_total: expected.total.as_decimal(),
+            available: actual.available.as_decimal(),
+            held: actual.held.as_decimal(),
+            total: actual.total.as_decimal(),
+        }
+    }
+
+    /// Write the rows as csv, see [DiscrepancyRow] for the columns.
+    pub fn write_csv(rows: &[DiscrepancyRow], writer: impl std::io::Write) -> anyhow::Result<()> {
+        let mut w = csv::Writer::from_writer(writer);
+        for row in rows {
+            w.serialize(row)?;
         }
+        w.flush()?;
+        Ok(())
     }
 }
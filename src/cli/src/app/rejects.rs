@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use payment_engine::{Event, errors::EngineError};
+
+/// Code of rows that can't be read or deserialized (bad csv, unknown type, non numeric field, ...).
+pub const MALFORMED_ROW: &str = "malformed_row";
+/// Code of rows that can't be converted into an [Event] (eg. deposit without amount).
+pub const INVALID_ROW: &str = "invalid_row";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RejectsFormat {
+    Csv,
+    Jsonl,
+}
+
+/// Position and content of an input row, kept with its event to report it if rejected.
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    /// 1-based line number in the input, the header is line 1.
+    pub line: u64,
+    /// The fields of the row as read, joined with `,`.
+    pub row: String,
+}
+
+impl Source {
+    pub fn from_record(record: &csv::StringRecord) -> Self {
+        Self {
+            line: record.position().map_or(0, |position| position.line()),
+            row: record.iter().collect::<Vec<_>>().join(","),
+        }
+    }
+}
+
+/// A rejected input row.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Reject {
+    pub line: u64,
+    pub row: String,
+    /// Stable error code, see [EngineError::code], [MALFORMED_ROW] and [INVALID_ROW].
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Reports rejected rows on stderr and, if configured, into a machine-readable rejects file.
+///
+/// Cloneable so it can be shared with the shards of a [payment_engine::ShardedEngine].
+#[derive(Debug, Clone, Default)]
+pub struct Rejects {
+    sink: Option<Arc<Mutex<RejectsSink>>>,
+}
+
+impl Rejects {
+    /// Reports on stderr only.
+    pub fn stderr() -> Self {
+        Self::default()
+    }
+
+    /// Also writes each reject as a record of `format` into the file at `path`.
+    pub fn to_file(path: &Path, format: RejectsFormat) -> anyhow::Result<Self> {
+        let file = File::create(path).context("Create the rejects file")?;
+        let writer = match format {
+            RejectsFormat::Csv => SinkWriter::Csv(Box::new(csv::Writer::from_writer(file))),
+            RejectsFormat::Jsonl => SinkWriter::Jsonl(BufWriter::new(file)),
+        };
+
+        Ok(Self {
+            sink: Some(Arc::new(Mutex::new(RejectsSink {
+                writer,
+                error: None,
+            }))),
+        })
+    }
+
+    /// Rows that can't be read or deserialized.
+    pub fn malformed(&self, source: Source, err: &csv::Error) {
+        eprintln!("Error reading entry: {}", err);
+        self.write(source, MALFORMED_ROW, err.to_string());
+    }
+
+    /// Rows that can't be converted into an event.
+    pub fn invalid(&self, source: Source, err: &anyhow::Error) {
+        eprintln!("Error parsing entry: {}", err);
+        self.write(source, INVALID_ROW, err.to_string());
+    }
+
+    /// Events rejected by the engine due to a partner error.
+    pub fn rejected(&self, source: Source, event: &Event, err: &EngineError) {
+        eprintln!(
+            "Partner Data Error for TxId: {}, ClientId: {}: {}",
+            event.transaction_id(),
+            event.client_id(),
+            err
+        );
+        self.write(source, err.code(), err.to_string());
+    }
+
+    /// Events applied although older than the latest event of their client,
+    /// see [payment_engine::policy::TimestampPolicy::Flag]. Reported on stderr only, they aren't rejects.
+    pub fn out_of_order(&self, source: Source, event: &Event) {
+        eprintln!(
+            "Out of order event on line {} for TxId: {}, ClientId: {}",
+            source.line,
+            event.transaction_id(),
+            event.client_id(),
+        );
+    }
+
+    /// Flushes the rejects file.
+    /// Returns the first error writing the file, if any reject couldn't be written.
+    pub fn finish(&self) -> anyhow::Result<()> {
+        let Some(sink) = &self.sink else {
+            return Ok(());
+        };
+        let mut sink = sink.lock().expect("rejects lock poisoned");
+        if let Some(err) = sink.error.take() {
+            return Err(err);
+        }
+
+        sink.writer.flush().context("Write the rejects file")
+    }
+
+    fn write(&self, source: Source, code: &'static str, message: String) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let reject = Reject {
+            line: source.line,
+            row: source.row,
+            code,
+            message,
+        };
+
+        let mut sink = sink.lock().expect("rejects lock poisoned");
+        // Rejects may be reported from the shards, the error is kept and returned by `finish`.
+        if sink.error.is_none()
+            && let Err(err) = sink.writer.write(&reject)
+        {
+            sink.error = Some(err.context("Write the rejects file"));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RejectsSink {
+    writer: SinkWriter,
+    error: Option<anyhow::Error>,
+}
+
+#[derive(Debug)]
+enum SinkWriter {
+    Csv(Box<csv::Writer<File>>),
+    Jsonl(BufWriter<File>),
+}
+
+impl SinkWriter {
+    fn write(&mut self, reject: &Reject) -> anyhow::Result<()> {
+        match self {
+            SinkWriter::Csv(writer) => writer.serialize(reject)?,
+            SinkWriter::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, reject)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            SinkWriter::Csv(writer) => writer.flush(),
+            SinkWriter::Jsonl(writer) => writer.flush(),
+        }
+    }
+}
@@ -1,23 +1,115 @@
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use payment_engine::ledger::in_memory::InMemoryLedger;
-use payment_engine_cli::app::App;
+use payment_engine::event_store::{file::FileEventStore, in_memory::InMemoryEventStore};
+use payment_engine::ledger::{file::FileLedger, in_memory::InMemoryLedger};
+use payment_engine_cli::app::config::Config;
+use payment_engine_cli::app::rejects::{Rejects, RejectsFormat};
+use payment_engine_cli::app::{App, input};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Payment Engine")]
+#[command(
+    author,
+    version,
+    about = "Payment Engine",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Input file with list of transactions
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input csv file with list of transactions, `-` reads from stdin.
+    /// gzip and zstd compressed input is decompressed on the fly.
+    #[arg(required = true)]
+    file: Option<PathBuf>,
+
+    /// Directory of a durable ledger and event store. The input is processed on top of the state stored there.
+    /// Transactions and events are kept in memory only if not provided.
+    #[arg(long)]
+    ledger_dir: Option<PathBuf>,
+
+    /// Process clients concurrently on this many shards.
+    /// The output is identical to the sequential processing.
+    /// The shards keep their ledgers and events in memory: nothing is persisted, so it can't be combined
+    /// with `--ledger-dir` (nor `--reconcile`, which checks a ledger).
+    #[arg(long, conflicts_with = "ledger_dir")]
+    shards: Option<NonZeroUsize>,
+
+    /// Write the rejected rows (line, row, error code and message) into this file.
+    /// Rejected rows are reported on stderr in any case.
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+
+    /// Format of the rejects file.
+    #[arg(long, value_enum, default_value = "csv", requires = "rejects")]
+    rejects_format: RejectsFormat,
+
+    /// TOML file with the policies of the engine (eg. transitions of disputes), per partner if needed.
+    /// The built-in rules apply if not provided.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Check the balances against the ledger once the input is processed, see the `reconcile` command.
+    /// Fails with the discrepancies on stderr instead of writing the accounts.
+    #[arg(long, conflicts_with = "shards")]
+    reconcile: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Recompute the balances of every client from the transactions of a durable ledger,
+    /// and write those that don't match the stored accounts as csv. Fails if any don't match.
+    Reconcile {
+        /// Directory of the durable ledger.
+        #[arg(long)]
+        ledger_dir: PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let app = App::new();
-    let ledger = InMemoryLedger::new();
+    if let Some(Command::Reconcile { ledger_dir }) = &args.command {
+        return App::new()
+            .reconcile(FileLedger::open_read_only(ledger_dir)?)
+            .await;
+    }
+
+    let app = match &args.rejects {
+        Some(path) => App::with_rejects(Rejects::to_file(path, args.rejects_format)?),
+        None => App::new(),
+    };
+    let app = match &args.config {
+        Some(path) => app.with_policies(Config::load(path)?.policies()),
+        None => app,
+    };
+    let app = match args.reconcile {
+        true => app.with_reconciliation(),
+        false => app,
+    };
+    let file = args.file.context("An input file is required")?;
+    let input = input::open(&file).context("Read the provided csv file")?;
+
+    if let Some(shards) = args.shards {
+        app.process_sharded(shards, input).await?;
+        return Ok(());
+    }
 
-    app.process(ledger, args.file).await?;
+    match args.ledger_dir {
+        Some(dir) => {
+            let ledger = FileLedger::open(&dir)?;
+            let events = FileEventStore::open(dir.join("events"))?;
+            app.process(ledger, events, input).await?;
+        }
+        None => {
+            let ledger = InMemoryLedger::new();
+            app.process(ledger, InMemoryEventStore::new(), input)
+                .await?;
+        }
+    }
 
     Ok(())
 }
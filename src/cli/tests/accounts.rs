@@ -0,0 +1,112 @@
+mod common;
+
+use common::Test;
+use payment_engine::ReasonCode;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{ClientId, Currency, Money};
+use rust_decimal::Decimal;
+
+/// an admin freezes an account with a reason, no further activity is allowed
+#[tokio::test]
+async fn freeze() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                deposit, 1, 1, 3.0
+                freeze, 1, 2, , , compliance
+                deposit, 1, 3, 1.0"#,
+    )
+    .expect_error(EngineError::AccountLocked {
+        client_id: ClientId::from(1),
+        reason: ReasonCode::Compliance,
+    })
+    .await;
+}
+
+/// a freeze before the first deposit opens the account locked, it is written without balance
+#[tokio::test]
+async fn freeze_before_first_deposit() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                freeze, 1, 1, , , compliance
+                deposit, 2, 2, 1.0
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0,0,0,true
+            2,1,0,1,false
+            "#,
+    )
+    .await;
+}
+
+/// an admin lifts the lock of a chargeback, the client can withdraw again
+#[tokio::test]
+async fn unlock_after_chargeback() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                deposit, 1, 1, 3.0
+                deposit, 1, 2, 2.0
+                dispute, 1, 1
+                chargeback, 1, 1
+                unlock, 1, 3, , , cleared
+                withdrawal, 1, 4, 1.5
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0.5,0.0,0.5,false
+            "#,
+    )
+    .await;
+}
+
+/// an account is closed once its balance is zero, it stays locked
+#[tokio::test]
+async fn close() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 3.0
+                close, 1, 3, , , client_request
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0,0,0,true
+            "#,
+    )
+    .await;
+}
+
+/// an account with a balance can't be closed
+#[tokio::test]
+async fn close_with_balance() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                deposit, 1, 1, 3.0
+                close, 1, 2, , , client_request"#,
+    )
+    .expect_error(EngineError::NonZeroBalance {
+        client_id: ClientId::from(1),
+        balance: Money::new(Decimal::new(30, 1), Currency::NONE),
+    })
+    .await;
+}
+
+/// a closed account can't be unlocked
+#[tokio::test]
+async fn unlock_closed() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 3.0
+                close, 1, 3, , , fraud
+                unlock, 1, 4, , , cleared"#,
+    )
+    .expect_error(EngineError::AccountClosed {
+        client_id: ClientId::from(1),
+        reason: ReasonCode::Fraud,
+    })
+    .await;
+}
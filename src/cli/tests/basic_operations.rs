@@ -1,6 +1,9 @@
 mod common;
 
 use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{Amount, ClientId, TransactionId};
+use rust_decimal::Decimal;
 
 #[tokio::test]
 async fn empty() {
@@ -81,6 +84,26 @@ async fn negative_amount() {
                 deposit, 1, 1, -3.0
                 "#,
     )
-    .expect_error("Invalid event: Amount must be positive")
+    .expect_error(EngineError::NonPositiveAmount {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        amount: Amount::from(Decimal::new(-30, 1)).into(),
+    })
+    .await;
+}
+
+/// zero amount isn't allowed either
+#[tokio::test]
+async fn zero_amount() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                withdrawal, 1, 1, 0.0
+                "#,
+    )
+    .expect_error(EngineError::NonPositiveAmount {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        amount: Amount::default().into(),
+    })
     .await;
 }
@@ -1,6 +1,10 @@
 mod common;
 
 use common::Test;
+use payment_engine::ReasonCode;
+use payment_engine::errors::EngineError;
+use payment_engine::ledger::transactions::TransactionStatus;
+use payment_engine::types::{ClientId, TransactionId};
 
 /// dispute is resulted in chargeback, fund is returned to client and account is locked
 #[tokio::test]
@@ -24,12 +28,17 @@ async fn dispute_chargeback() {
 #[tokio::test]
 async fn chargeback_without_dispute() {
     Test::for_input(
-            r#"type, client, tx, amount
+        r#"type, client, tx, amount
                 deposit, 1, 1, 3.0
-                chargeback, 1, 1"#).expect_error(
-            "Transaction is in invalid status: Operation doesn't apply to this transaction. Transition from Settled to ChargedBack",
-        )
-        .await;
+                chargeback, 1, 1"#,
+    )
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        current: TransactionStatus::Settled,
+        desired: TransactionStatus::ChargedBack,
+    })
+    .await;
 }
 
 /// once an account is locked, not further activity is allowed
@@ -43,6 +52,65 @@ async fn locked_account_activity() {
                 deposit, 1, 2, 20.0
             "#,
     )
-    .expect_error("Client 1 account is locked, no further activity is allowed")
+    .expect_error(EngineError::AccountLocked {
+        client_id: ClientId::from(1),
+        reason: ReasonCode::Chargeback,
+    })
+    .await;
+}
+
+/// a chargeback of a disputed withdrawal reverses it: the funds are credited back, the account stays unlocked
+#[tokio::test]
+async fn reverse_withdrawal() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 2.0
+                dispute, 1, 2
+                chargeback, 1, 2
+                deposit, 1, 3, 1.0
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,4,0,4,false
+            "#,
+    )
+    .await;
+}
+
+/// a withdrawal must be disputed before it is reversed
+#[tokio::test]
+async fn reverse_withdrawal_without_dispute() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 2.0
+                chargeback, 1, 2"#,
+    )
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(2),
+        current: TransactionStatus::Settled,
+        desired: TransactionStatus::Reversed,
+    })
+    .await;
+}
+
+/// a partial chargeback only takes back its amount, the rest of the dispute stays held
+#[tokio::test]
+async fn partial_chargeback() {
+    Test::for_input(
+        r#"type, client, tx, amount, timestamp
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 4.0, 110
+                chargeback, 1, 1, 1.5, 120
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,6,2.5,8.5,true
+            "#,
+    )
     .await;
 }
@@ -1,64 +1,94 @@
 #![allow(dead_code)]
 
 use payment_engine::{
-    Engine, Event,
+    Engine, TimedEvent,
+    errors::EngineError,
     ledger::in_memory::{self, InMemoryLedger},
 };
+use payment_engine_cli::app::config::Config;
+use payment_engine_cli::app::expire_disputes;
 use payment_engine_cli::app::models::{InputRow, OutputRow};
 use pretty_assertions::assert_eq;
 
 pub struct Test {
     input: &'static str,
+    config: Config,
 }
 
 impl Test {
     pub fn for_input(input: &'static str) -> Self {
-        Self { input }
+        Self {
+            input,
+            config: Config::default(),
+        }
+    }
+
+    /// Process the input with the given TOML config instead of the built-in rules.
+    pub fn with_config(self, config: &str) -> Self {
+        Self {
+            config: Config::parse(config).expect("valid config"),
+            ..self
+        }
     }
 
     pub async fn expect_output(self, output: &'static str) {
-        let engine = Self::process_csv(self.input).await.expect("process csv");
+        let engine = Self::process_csv(self.input, &self.config)
+            .await
+            .expect("process csv");
 
-        let accounts = engine.accounts_ordered();
-        let mut w = csv::Writer::from_writer(Vec::new());
-        for a in accounts {
-            w.serialize(OutputRow::from(a)).expect("process output row");
-        }
-        w.flush().expect("flush to output");
+        let rows = engine
+            .accounts_ordered()
+            .into_iter()
+            .flat_map(OutputRow::of)
+            .collect::<Vec<_>>();
+        let mut csv = Vec::new();
+        OutputRow::write_csv(&rows, &mut csv).expect("write output rows");
 
         let output = output
             .split('\n')
             .map(|line| line.trim())
             .collect::<Vec<_>>()
             .join("\n");
-        let csv = String::from_utf8(w.into_inner().expect("read the buffer"))
-            .expect("convert vec to utf8 string");
+        let csv = String::from_utf8(csv).expect("convert vec to utf8 string");
         assert_eq!(csv, output);
     }
 
-    pub async fn expect_error(self, error: &'static str) {
-        match Self::process_csv(self.input).await {
-            Err(err) => assert_eq!(err.to_string(), error),
+    /// Process the input and check that the balances match the ledger, see [Engine::reconcile].
+    pub async fn expect_reconciled(self) {
+        let engine = Self::process_csv(self.input, &self.config)
+            .await
+            .expect("process csv");
+
+        let reconciliation = engine.reconcile().await.expect("reconcile");
+        assert_eq!(reconciliation.discrepancies, vec![]);
+    }
+
+    pub async fn expect_error(self, error: EngineError) {
+        match Self::process_csv(self.input, &self.config).await {
+            Err(err) => assert_eq!(err.downcast_ref::<EngineError>(), Some(&error)),
             Ok(_) => panic!("Expected an error but got success"),
         }
     }
 
-    async fn process_csv(input: &str) -> anyhow::Result<Engine<InMemoryLedger>> {
+    async fn process_csv(input: &str, config: &Config) -> anyhow::Result<Engine<InMemoryLedger>> {
         let mut reader = csv::ReaderBuilder::new()
             .trim(csv::Trim::All)
             .flexible(true)
             .from_reader(input.trim().trim_end_matches(['\n', '\r']).as_bytes());
 
-        let mut engine = Engine::new(in_memory::InMemoryLedger::new());
+        let mut engine = Engine::new(in_memory::InMemoryLedger::new())
+            .await?
+            .with_policies(config.policies());
         for entry in reader.deserialize::<InputRow>() {
             let entry = entry?;
             engine
-                .apply(Event::try_from(entry).expect("valid csv data feed"))
+                .apply(TimedEvent::try_from(entry).expect("valid csv data feed"))
                 .await
                 .inspect_err(|e| {
                     eprintln!("Error processing {:?}: {e}", entry);
                 })?;
         }
+        expire_disputes(&mut engine).await?;
 
         Ok(engine)
     }
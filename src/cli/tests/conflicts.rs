@@ -1,6 +1,8 @@
 mod common;
 
 use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{ClientId, TransactionId};
 
 #[tokio::test]
 async fn duplicate_data() {
@@ -10,7 +12,10 @@ async fn duplicate_data() {
                 deposit, 1, 1, 3.5
                 "#,
     )
-    .expect_error("Invalid event: Transaction already exist but with different details")
+    .expect_error(EngineError::ConflictingTransaction {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+    })
     .await;
 }
 
@@ -23,6 +28,9 @@ async fn transaction_belong_to_another_client() {
                 deposit, 2, 1, 3.5
                 "#,
     )
-    .expect_error("Invalid event: Transaction belong to a different client")
+    .expect_error(EngineError::TransactionOfDifferentClient {
+        client_id: ClientId::from(2),
+        transaction_id: TransactionId::from(1),
+    })
     .await;
 }
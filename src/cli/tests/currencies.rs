@@ -0,0 +1,88 @@
+mod common;
+
+use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{Amount, ClientId, Currency, Money, TransactionId};
+use rust_decimal::Decimal;
+
+/// amounts are rounded to the minor units of their currency and reported per currency
+#[tokio::test]
+async fn balances_per_currency() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency
+                deposit, 1, 1, 4.005, EUR
+                deposit, 1, 2, 1000.4, jpy
+                deposit, 1, 3, 2.0
+                withdrawal, 1, 4, 1.0, EUR
+                deposit, 2, 5, 3.0, EUR
+                "#,
+    )
+    .expect_output(
+        r#"client,currency,available,held,total,locked
+            1,EUR,3.00,0.00,3.00,false
+            1,JPY,1000,0,1000,false
+            1,XXX,2,0,2,false
+            2,EUR,3,0,3,false
+            "#,
+    )
+    .await;
+}
+
+/// a dispute holds the funds in the currency of the disputed transaction
+#[tokio::test]
+async fn dispute_in_original_currency() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency
+                deposit, 1, 1, 5.0, EUR
+                deposit, 1, 2, 2.0, USD
+                dispute, 1, 1,,
+                "#,
+    )
+    .expect_output(
+        r#"client,currency,available,held,total,locked
+            1,EUR,0,5,5,false
+            1,USD,2,0,2,false
+            "#,
+    )
+    .await;
+}
+
+/// a partial dispute is rounded to the currency of the deposit, nothing is left of 0.4 JPY
+#[tokio::test]
+async fn partial_dispute_rounded_to_zero() {
+    let jpy = Currency::new("JPY").expect("valid currency");
+    Test::for_input(
+        r#"type, client, tx, amount, currency, timestamp
+                deposit, 1, 1, 1000, JPY, 100
+                dispute, 1, 1, 0.4, , 110
+                "#,
+    )
+    .expect_error(EngineError::NonPositiveAmount {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        amount: Money::new(Decimal::ZERO, jpy),
+    })
+    .await;
+}
+
+/// funds of another currency can't be withdrawn
+#[tokio::test]
+async fn withdrawal_from_other_currency() {
+    let usd = Currency::new("USD").expect("valid currency");
+    Test::for_input(
+        r#"type, client, tx, amount, currency
+                deposit, 1, 1, 5.0, EUR
+                withdrawal, 1, 2, 1.0, USD
+                "#,
+    )
+    .expect_error(EngineError::InsufficientFunds {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(2),
+        requested: Money::new(Decimal::ONE, usd),
+        available: Money {
+            amount: Amount::default(),
+            currency: usd,
+        },
+    })
+    .await;
+}
@@ -1,6 +1,8 @@
 mod common;
 
 use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{Amount, ClientId, TransactionId};
 
 /// client disputing their own transaction - should succeed
 #[tokio::test]
@@ -19,9 +21,8 @@ async fn dispute() {
     .await;
 }
 
-/// Similarly, disputing a transaction when account has insufficient funds is not allowed.
-/// In a real system the client balance could go negative, but for this exercise we
-/// will not allow that.
+/// Similarly, disputing a transaction when account has insufficient funds is not allowed
+/// by default, see `negative_balance` for the policy allowing it.
 #[tokio::test]
 async fn dispute_insufficient_funds() {
     Test::for_input(
@@ -30,7 +31,53 @@ async fn dispute_insufficient_funds() {
                 withdrawal, 1, 2, 2.0
                 dispute, 1, 1"#,
     )
-    .expect_error("Insufficient funds")
+    .expect_error(EngineError::InsufficientFunds {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        requested: Amount::from_minor(300).into(),
+        available: Amount::from_minor(100).into(),
+    })
+    .await;
+}
+
+/// with the `allow` policy, the dispute drives the available funds negative and the deficit is reported
+#[tokio::test]
+async fn negative_balance() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 2.0
+                dispute, 1, 1
+                deposit, 2, 3, 1.0
+            "#,
+    )
+    .with_config(r#"negative_balance = "allow""#)
+    .expect_output(
+        r#"client,available,held,total,deficit,locked
+            1,-2,3,1,2,false
+            2,1,0,1,0,false
+            "#,
+    )
+    .await;
+}
+
+/// the chargeback takes the total negative too, the client owes the withdrawn funds
+#[tokio::test]
+async fn negative_balance_chargeback() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 2.0
+                dispute, 1, 1
+                chargeback, 1, 1
+            "#,
+    )
+    .with_config(r#"negative_balance = "allow""#)
+    .expect_output(
+        r#"client,available,held,total,deficit,locked
+            1,-2,0,-2,2,true
+            "#,
+    )
     .await;
 }
 
@@ -42,19 +89,63 @@ async fn dispute_wrong_transaction() {
                 deposit, 1, 1, 3.0
                 dispute, 2, 1"#,
     )
-    .expect_error("Invalid event: transaction not found") // the system hides the existence of the transaction (belong to a different client)
+    // the system hides the existence of the transaction (belong to a different client)
+    .expect_error(EngineError::TransactionNotFound {
+        client_id: ClientId::from(2),
+        transaction_id: TransactionId::from(1),
+    })
     .await;
 }
 
-/// dispute does not apply to withdrawals
+/// dispute of a withdrawal holds the withdrawn amount until the dispute is settled
 #[tokio::test]
 async fn dispute_withdrawals() {
     Test::for_input(
         r#"type, client, tx, amount
                 deposit, 1, 1, 3.0
-                withdrawal, 1, 2, 3.0
+                withdrawal, 1, 2, 2.0
                 dispute, 1, 2"#,
     )
-    .expect_error("Invalid associated transaction: Dispute must be on a deposit")
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,1,2,3,false
+            "#,
+    )
+    .await;
+}
+
+/// several partial disputes of one deposit hold only their amounts
+#[tokio::test]
+async fn partial_disputes() {
+    Test::for_input(
+        r#"type, client, tx, amount, timestamp
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 3.0, 110
+                dispute, 1, 1, 2.5, 120
+                "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,4.5,5.5,10,false
+            "#,
+    )
+    .await;
+}
+
+/// partial disputes can't exceed the undisputed amount of the deposit
+#[tokio::test]
+async fn over_dispute() {
+    Test::for_input(
+        r#"type, client, tx, amount, timestamp
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 6.0, 110
+                dispute, 1, 1, 5.0, 120"#,
+    )
+    .expect_error(EngineError::OverDispute {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        requested: Amount::from_minor(500).into(),
+        undisputed: Amount::from_minor(400).into(),
+    })
     .await;
 }
@@ -0,0 +1,76 @@
+mod common;
+
+use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{Amount, ClientId, TransactionId};
+use payment_engine_cli::app::config::Config;
+
+const FEES: &str = r#"
+    [fees]
+    tiers = { gold = [2] }
+    rules = [
+        { event = "withdrawal", percent = 1.5 },
+        { event = "chargeback", fixed = 0.5 },
+        { event = "chargeback", fixed = 0.1, tier = "gold" },
+    ]
+    "#;
+
+/// the fees are withdrawn from the clients, gold clients pay a lower chargeback fee
+#[tokio::test]
+async fn fees_by_tier() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 10.0
+                deposit, 1, 2, 5.0
+                withdrawal, 1, 3, 2.0
+                dispute, 1, 2
+                chargeback, 1, 2
+                deposit, 2, 4, 10.0
+                deposit, 2, 5, 5.0
+                dispute, 2, 5
+                chargeback, 2, 5"#,
+    )
+    .with_config(FEES)
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,7.470,0.000,7.470,true
+            2,9.9,0.0,9.9,true
+            "#,
+    )
+    .await;
+}
+
+/// a withdrawal needs the funds for its fee too
+#[tokio::test]
+async fn withdrawal_fee_exceeds_funds() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 10.0
+                withdrawal, 1, 2, 10.0"#,
+    )
+    .with_config(FEES)
+    .expect_error(EngineError::InsufficientFunds {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(2),
+        requested: Amount::from_minor(1015).into(),
+        available: Amount::from_minor(1000).into(),
+    })
+    .await;
+}
+
+#[test]
+fn invalid_fees() {
+    let err = Config::parse(
+        r#"
+        [fees]
+        rules = [{ event = "withdrawal", fixed = 1, tier = "gold" }]
+        "#,
+    )
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("unknown fee tier `gold`"),
+        "{err:#}"
+    );
+
+    assert!(Config::parse("[fees]\nrules = [{ event = \"deposit\", percent = -1 }]").is_err());
+}
@@ -0,0 +1,67 @@
+use std::io::{Read, Write};
+
+use payment_engine_cli::app::input;
+use pretty_assertions::assert_eq;
+
+const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+fn read_all(reader: impl Read + 'static) -> String {
+    let mut output = String::new();
+    input::decompress(reader)
+        .expect("detect compression")
+        .read_to_string(&mut output)
+        .expect("read input");
+    output
+}
+
+#[test]
+fn plain_input_is_passed_through() {
+    assert_eq!(read_all(CSV.as_bytes()), CSV);
+}
+
+#[test]
+fn gzip_input_is_decompressed() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    assert_eq!(read_all(std::io::Cursor::new(compressed)), CSV);
+}
+
+#[test]
+fn zstd_input_is_decompressed() {
+    let compressed = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+
+    assert_eq!(read_all(std::io::Cursor::new(compressed)), CSV);
+}
+
+#[test]
+fn empty_input() {
+    assert_eq!(read_all(std::io::empty()), "");
+}
+
+/// Hands out the input one byte per read, like a slow pipe.
+struct Trickle(std::io::Cursor<Vec<u8>>);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+#[test]
+fn compressed_input_in_small_chunks() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(CSV.as_bytes()).unwrap();
+    let gzip = encoder.finish().unwrap();
+    let zstd = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+
+    for compressed in [gzip, zstd] {
+        assert_eq!(read_all(Trickle(std::io::Cursor::new(compressed))), CSV);
+    }
+    assert_eq!(
+        read_all(Trickle(std::io::Cursor::new(CSV.as_bytes().to_vec()))),
+        CSV
+    );
+}
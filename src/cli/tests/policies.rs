@@ -0,0 +1,205 @@
+mod common;
+
+use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::ledger::transactions::{Direction, TransactionStatus};
+use payment_engine::types::{ClientId, TransactionId};
+use payment_engine_cli::app::config::Config;
+
+/// without a config the built-in rules apply: a resolved deposit can't be charged back
+#[tokio::test]
+async fn default_policy() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                resolve, 1, 1
+                chargeback, 1, 1"#,
+    )
+    .with_config("")
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        current: TransactionStatus::Resolved,
+        desired: TransactionStatus::ChargedBack,
+    })
+    .await;
+}
+
+/// a chargeback straight from settled holds and takes back the funds at once
+#[tokio::test]
+async fn chargeback_from_settled() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                deposit, 1, 2, 1.0
+                chargeback, 1, 1
+            "#,
+    )
+    .with_config(
+        r#"
+        [transitions]
+        allow = [{ direction = "inbound", from = "settled", to = "chargedback" }]
+        "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,1,0,1,true
+            "#,
+    )
+    .await;
+}
+
+/// a resolved deposit is disputed again
+#[tokio::test]
+async fn redispute_resolved() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                resolve, 1, 1
+                dispute, 1, 1
+            "#,
+    )
+    .with_config(
+        r#"
+        [transitions]
+        redispute_resolved = true
+        "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0,3,3,false
+            "#,
+    )
+    .await;
+}
+
+/// a partner policy only applies to its clients
+#[tokio::test]
+async fn partner_policy() {
+    let config = r#"
+        [[partners]]
+        clients = [2]
+        transitions.forbid = [{ direction = "outbound", from = "settled", to = "disputed" }]
+        "#;
+
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 1.0
+                dispute, 1, 2
+                deposit, 2, 3, 3.0
+                withdrawal, 2, 4, 1.0
+            "#,
+    )
+    .with_config(config)
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,2,1,3,false
+            2,2,0,2,false
+            "#,
+    )
+    .await;
+
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 2, 3, 3.0
+                withdrawal, 2, 4, 1.0
+                dispute, 2, 4"#,
+    )
+    .with_config(config)
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(2),
+        transaction_id: TransactionId::from(4),
+        current: TransactionStatus::Settled,
+        desired: TransactionStatus::Disputed,
+    })
+    .await;
+}
+
+/// a partner inherits the top-level policy it doesn't override (here negative balances and fees)
+#[tokio::test]
+async fn partner_inherits_default_policy() {
+    let config = r#"
+        negative_balance = "allow"
+
+        [fees]
+        rules = [{ event = "withdrawal", fixed = 1 }]
+
+        [[partners]]
+        clients = [2]
+        transitions.forbid = [{ direction = "outbound", from = "settled", to = "disputed" }]
+        "#;
+
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 2, 1, 5.0
+                withdrawal, 2, 2, 2.0
+                dispute, 2, 1
+            "#,
+    )
+    .with_config(config)
+    .expect_output(
+        r#"client,available,held,total,deficit,locked
+            2,-3,5,2,3,false
+            "#,
+    )
+    .await;
+}
+
+/// a table replaces the built-in transitions, a direction without any can't be disputed
+#[tokio::test]
+async fn transition_table() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 1.0
+                dispute, 1, 2"#,
+    )
+    .with_config(
+        r#"
+        [transitions]
+        table = [
+            { direction = "inbound", from = "settled", to = "disputed" },
+            { direction = "inbound", from = "disputed", to = "resolved" },
+        ]
+        "#,
+    )
+    .expect_error(EngineError::InvalidAssociatedTransaction {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(2),
+        direction: Direction::Outbound,
+    })
+    .await;
+}
+
+/// chargedback only applies to inbound transactions, reversed to outbound ones
+#[test]
+fn invalid_transition() {
+    let err = Config::parse(
+        r#"
+        [transitions]
+        allow = [{ direction = "outbound", from = "settled", to = "chargedback" }]
+        "#,
+    )
+    .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("chargedback is inbound"),
+        "{err:#}"
+    );
+
+    assert!(Config::parse("[transitions]\nunknown = 1").is_err());
+}
+
+/// misspelled keys are rejected instead of being ignored, at the top level and in partners
+#[test]
+fn unknown_keys() {
+    let err = Config::parse(r#"negative_balanc = "allow""#).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("unknown key `negative_balanc`"),
+        "{err:#}"
+    );
+
+    assert!(Config::parse("[[partners]]\nclients = [1]\ntimestamp = \"flag\"").is_err());
+}
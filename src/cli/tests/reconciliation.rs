@@ -0,0 +1,35 @@
+mod common;
+
+use common::Test;
+
+/// the balances match the transactions and fees of the ledger, whatever happened to them
+#[tokio::test]
+async fn balances_match_ledger() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, timestamp
+                deposit, 1, 1, 10.0, ,
+                deposit, 1, 2, 5.0, EUR,
+                withdrawal, 1, 3, 2.0, ,
+                dispute, 1, 3, , ,
+                dispute, 1, 1, 4.0, , 100
+                resolve, 1, 1, 1.0, , 110
+                dispute, 1, 2, , ,
+                deposit, 2, 4, 10.0, ,
+                withdrawal, 2, 5, 3.0, ,
+                dispute, 2, 5, , ,
+                chargeback, 2, 5, , ,
+                dispute, 2, 4, 2.5, , 100
+                chargeback, 2, 4, 2.5, , 110"#,
+    )
+    .with_config(
+        r#"
+        [fees]
+        rules = [
+            { event = "withdrawal", percent = 1.5 },
+            { event = "chargeback", fixed = 0.5 },
+        ]
+        "#,
+    )
+    .expect_reconciled()
+    .await;
+}
@@ -0,0 +1,77 @@
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+use payment_engine::event_store::in_memory::InMemoryEventStore;
+use payment_engine::ledger::in_memory::InMemoryLedger;
+use payment_engine_cli::app::App;
+use payment_engine_cli::app::rejects::{Rejects, RejectsFormat};
+use pretty_assertions::assert_eq;
+
+const INPUT: &str = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2
+foo,1,3,1
+withdrawal,2,4,5.0
+resolve,1,1,
+";
+
+fn rejects_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "payment-engine-cli-rejects-{}-{name}",
+        std::process::id()
+    ))
+}
+
+#[tokio::test]
+async fn rejects_csv() {
+    let path = rejects_path("csv");
+    let app = App::with_rejects(Rejects::to_file(&path, RejectsFormat::Csv).unwrap());
+    app.process(
+        InMemoryLedger::new(),
+        InMemoryEventStore::new(),
+        INPUT.as_bytes(),
+    )
+    .await
+    .unwrap();
+
+    let rejects = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        rejects,
+        r#"line,row,code,message
+3,"deposit,1,2",invalid_row,Amount is required for Deposit
+4,"foo,1,3,1",malformed_row,"CSV deserialize error: record 3 (line: 4, byte: 50): unknown variant `foo`, expected one of `deposit`, `withdrawal`, `dispute`, `resolve`, `chargeback`, `unlock`, `freeze`, `close`, `approve`"
+5,"withdrawal,2,4,5.0",insufficient_funds,"Insufficient funds: requested 5.0000, available 0.0000"
+6,"resolve,1,1,",invalid_transaction_status,Transaction is in invalid status: Operation doesn't apply to this transaction. Transition from Settled to Resolved
+"#
+    );
+}
+
+#[tokio::test]
+async fn rejects_jsonl_of_sharded_processing() {
+    let path = rejects_path("jsonl");
+    let app = App::with_rejects(Rejects::to_file(&path, RejectsFormat::Jsonl).unwrap());
+    app.process_sharded(NonZeroUsize::new(2).unwrap(), INPUT.as_bytes())
+        .await
+        .unwrap();
+
+    let rejects = std::fs::read_to_string(&path).unwrap();
+    let mut codes = rejects
+        .lines()
+        .map(|line| {
+            let reject: serde_json::Value = serde_json::from_str(line).unwrap();
+            (reject["line"].as_u64().unwrap(), reject["code"].to_string())
+        })
+        .collect::<Vec<_>>();
+    // shards report concurrently
+    codes.sort();
+
+    assert_eq!(
+        codes,
+        vec![
+            (3, r#""invalid_row""#.to_string()),
+            (4, r#""malformed_row""#.to_string()),
+            (5, r#""insufficient_funds""#.to_string()),
+            (6, r#""invalid_transaction_status""#.to_string()),
+        ]
+    );
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::ledger::transactions::TransactionStatus;
+use payment_engine::types::{ClientId, TransactionId};
+
+/// partners resend files, replayed events are skipped without an error
+#[tokio::test]
+async fn replayed_file() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 1.0
+                deposit, 1, 1, 3.0
+                withdrawal, 1, 2, 1.0
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,2,0,2,false
+            "#,
+    )
+    .await;
+}
+
+/// partial disputes and resolves are recognised by their amount and timestamp when a file is resent,
+/// a further partial dispute of the same amount at another time is applied
+#[tokio::test]
+async fn replayed_partial_events() {
+    Test::for_input(
+        r#"type, client, tx, amount, timestamp
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 3.0, 110
+                dispute, 1, 1, 3.0, 120
+                resolve, 1, 1, 3.0, 130
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 3.0, 110
+                dispute, 1, 1, 3.0, 120
+                resolve, 1, 1, 3.0, 130
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,7,3,10,false
+            "#,
+    )
+    .await;
+}
+
+/// a partial dispute without timestamp couldn't be told apart from a resend, it is rejected
+#[tokio::test]
+async fn partial_event_without_timestamp() {
+    Test::for_input(
+        r#"type, client, tx, amount, timestamp
+                deposit, 1, 1, 10.0, 100
+                dispute, 1, 1, 3.0,
+            "#,
+    )
+    .expect_error(EngineError::PartialWithoutTimestamp {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+    })
+    .await;
+}
+
+/// a replayed deposit is recognised even after the deposit was disputed
+#[tokio::test]
+async fn replayed_dispute() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0,3,3,false
+            "#,
+    )
+    .await;
+}
+
+/// a late replay of the dispute after its resolution changes nothing
+#[tokio::test]
+async fn replayed_resolve() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                resolve, 1, 1
+                dispute, 1, 1
+                resolve, 1, 1
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,3,0,3,false
+            "#,
+    )
+    .await;
+}
+
+/// replays are recognised before the account lock is checked
+#[tokio::test]
+async fn replayed_chargeback() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                chargeback, 1, 1
+                chargeback, 1, 1
+                deposit, 1, 1, 3.0
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,0,0,0,true
+            "#,
+    )
+    .await;
+}
+
+/// a resolved dispute can't be charged back, it isn't a replay
+#[tokio::test]
+async fn conflicting_chargeback() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                dispute, 1, 1
+                resolve, 1, 1
+                chargeback, 1, 1"#,
+    )
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        current: TransactionStatus::Resolved,
+        desired: TransactionStatus::ChargedBack,
+    })
+    .await;
+}
@@ -1,6 +1,9 @@
 mod common;
 
 use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::ledger::transactions::TransactionStatus;
+use payment_engine::types::{ClientId, TransactionId};
 
 /// dispute is resolved
 #[tokio::test]
@@ -24,10 +27,34 @@ async fn dispute_resolve() {
 #[tokio::test]
 async fn resolve_without_dispute() {
     Test::for_input(
-            r#"type, client, tx, amount
+        r#"type, client, tx, amount
+                deposit, 1, 1, 3.0
+                resolve, 1, 1"#,
+    )
+    .expect_error(EngineError::InvalidTransactionStatus {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        current: TransactionStatus::Settled,
+        desired: TransactionStatus::Resolved,
+    })
+    .await;
+}
+
+/// resolving a disputed withdrawal keeps the withdrawal, the held amount is dropped
+#[tokio::test]
+async fn resolve_withdrawal_dispute() {
+    Test::for_input(
+        r#"type, client, tx, amount
                 deposit, 1, 1, 3.0
-                resolve, 1, 1"#).expect_error(
-            "Transaction is in invalid status: Operation doesn't apply to this transaction. Transition from Settled to Resolved",
-        )
-        .await;
+                withdrawal, 1, 2, 2.0
+                dispute, 1, 2
+                resolve, 1, 2
+            "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,1,0,1,false
+            "#,
+    )
+    .await;
 }
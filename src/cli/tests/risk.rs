@@ -0,0 +1,89 @@
+mod common;
+
+use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{ClientId, TransactionId};
+
+const RISK: &str = r#"
+    [risk]
+    max_amount = 100
+    withdrawal_velocity = { max_count = 2, window = 60 }
+    max_withdrawal_percent = 50
+    "#;
+
+/// rows within the limits are applied
+#[tokio::test]
+async fn within_limits() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 100.0, , , 1700000000
+                withdrawal, 1, 2, 50.0, , , 1700000010
+                withdrawal, 1, 3, 25.0, , , 1700000020
+                withdrawal, 1, 4, 10.0, , , 1700000070"#,
+    )
+    .with_config(RISK)
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,15,0,15,false
+            "#,
+    )
+    .await;
+}
+
+/// a third withdrawal within a minute is rejected
+#[tokio::test]
+async fn withdrawal_velocity() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 100.0, , , 1700000000
+                withdrawal, 1, 2, 10.0, , , 1700000010
+                withdrawal, 1, 3, 10.0, , , 1700000020
+                withdrawal, 1, 4, 10.0, , , 1700000030"#,
+    )
+    .with_config(RISK)
+    .expect_error(EngineError::RiskRejected {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(4),
+        rule: "withdrawal_velocity".to_string(),
+        reason: "2 withdrawals within the last 60 seconds already".to_string(),
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn max_amount() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                deposit, 1, 1, 100.5"#,
+    )
+    .with_config(RISK)
+    .expect_error(EngineError::RiskRejected {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        rule: "max_amount".to_string(),
+        reason: "100.5000 exceeds the maximum of 100.0000".to_string(),
+    })
+    .await;
+}
+
+/// a deposit over the review threshold is applied once approved
+#[tokio::test]
+async fn approved_deposit() {
+    Test::for_input(
+        r#"type, client, tx, amount
+                approve, 1, 1,
+                deposit, 1, 1, 150.0"#,
+    )
+    .with_config(
+        r#"
+        [risk]
+        review_threshold = 100
+        "#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,150,0,150,false
+            "#,
+    )
+    .await;
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::Test;
+use payment_engine::ReasonCode;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{ClientId, Timestamp, TransactionId};
+
+/// a row older than the latest row of its client is rejected
+#[tokio::test]
+async fn out_of_order_is_rejected() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000100
+                deposit, 2, 2, 2.0, , , 1700000000
+                withdrawal, 1, 3, 1.0, , , 1700000050"#,
+    )
+    .expect_error(EngineError::TimestampOutOfOrder {
+        client_id: ClientId::from(1),
+        timestamp: Timestamp::from(1700000050),
+        latest: Timestamp::from(1700000100),
+    })
+    .await;
+}
+
+/// rows without timestamp aren't checked
+#[tokio::test]
+async fn without_timestamp() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000100
+                withdrawal, 1, 2, 1.0"#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,2,0,2,false
+            "#,
+    )
+    .await;
+}
+
+/// with the `flag` policy, the row is applied anyway
+#[tokio::test]
+async fn out_of_order_is_flagged() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000100
+                withdrawal, 1, 2, 1.0, , , 1700000050"#,
+    )
+    .with_config(r#"timestamps = "flag""#)
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,2,0,2,false
+            "#,
+    )
+    .await;
+}
+
+/// a dispute after the window of its deposit is rejected
+#[tokio::test]
+async fn dispute_window_closed() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000000
+                dispute, 1, 1, , , , 1700000101"#,
+    )
+    .with_config(
+        r#"[deadlines]
+            dispute_window = 100"#,
+    )
+    .expect_error(EngineError::DisputeWindowClosed {
+        client_id: ClientId::from(1),
+        transaction_id: TransactionId::from(1),
+        closed_at: Timestamp::from(1700000100),
+    })
+    .await;
+}
+
+/// a dispute held too long is charged back before the next row of the client
+#[tokio::test]
+async fn expired_dispute() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000000
+                deposit, 1, 2, 2.0, , , 1700000000
+                dispute, 1, 1, , , , 1700000010
+                deposit, 2, 3, 1.0, , , 1700000100
+                withdrawal, 1, 4, 1.0, , , 1700000100"#,
+    )
+    .with_config(
+        r#"[deadlines]
+            max_disputed = 60
+            on_expiry = "chargeback""#,
+    )
+    .expect_error(EngineError::AccountLocked {
+        client_id: ClientId::from(1),
+        reason: ReasonCode::Chargeback,
+    })
+    .await;
+}
+
+/// a dispute of a client without later rows expires by the end of the batch
+#[tokio::test]
+async fn expired_dispute_without_later_rows() {
+    Test::for_input(
+        r#"type, client, tx, amount, currency, reason, timestamp
+                deposit, 1, 1, 3.0, , , 1700000000
+                dispute, 1, 1, , , , 1700000010
+                deposit, 2, 2, 1.0, , , 1700000070"#,
+    )
+    .with_config(
+        r#"[deadlines]
+            max_disputed = 60"#,
+    )
+    .expect_output(
+        r#"client,available,held,total,locked
+            1,3,0,3,false
+            2,1,0,1,false
+            "#,
+    )
+    .await;
+}
@@ -1,6 +1,8 @@
 mod common;
 
 use common::Test;
+use payment_engine::errors::EngineError;
+use payment_engine::types::{Amount, ClientId, TransactionId};
 
 #[tokio::test]
 async fn basic() {
@@ -26,6 +28,11 @@ async fn insufficient_funds() {
                 withdrawal, 2, 5, 3.0
                 "#,
     )
-    .expect_error("Insufficient funds")
+    .expect_error(EngineError::InsufficientFunds {
+        client_id: ClientId::from(2),
+        transaction_id: TransactionId::from(5),
+        requested: Amount::from_minor(300).into(),
+        available: Amount::default().into(),
+    })
     .await;
 }
@@ -1,8 +1,23 @@
-pub use {accounts::ClientAccount, core::Engine, events::Event};
+pub use {
+    accounts::{AccountStatus, Balance, ClientAccount, ReasonCode},
+    core::{AccountMismatch, Engine, Rebuild},
+    events::{Event, TimedEvent},
+    handle::EngineHandle,
+    outcomes::{Applied, Balances, Receipt},
+    sharded::{Finished, ShardReport, ShardedEngine},
+};
 
+pub mod clock;
 pub mod errors;
+pub mod fees;
+pub mod policy;
+pub mod reconciliation;
+pub mod risk;
 pub mod types;
 
 mod accounts;
 mod core;
 mod events;
+mod handle;
+mod outcomes;
+mod sharded;
@@ -1,21 +1,220 @@
-use crate::engine::types::{Amount, ClientId};
+use std::collections::BTreeMap;
 
-#[derive(Debug)]
+use rust_decimal::Decimal;
+
+use crate::engine::types::{Amount, ClientId, Currency, Money, SignedAmount, Timestamp};
+use crate::ledger::journal::{JournalAccount, JournalLine, Side};
+
+/// A client and its balances, one per currency it holds.
+///
+/// The lock applies to the client as a whole: a chargeback in one currency locks all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientAccount {
     pub client_id: ClientId,
-    pub available: Amount,
-    pub total: Amount,
-    pub is_locked: bool,
+    pub balances: BTreeMap<Currency, Balance>,
+    pub status: AccountStatus,
+    /// Reason of the last status change (chargeback, freeze, unlock or close), `None` if it never changed.
+    pub reason: Option<ReasonCode>,
+    /// Timestamp of the latest event of the client that had one, see [crate::policy::TimestampPolicy].
+    pub last_timestamp: Option<Timestamp>,
+}
+
+/// Whether an account accepts activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountStatus {
+    #[default]
+    Open,
+    /// Locked by a chargeback or frozen by an admin, until an admin unlocks it.
+    Locked,
+    /// Closed by an admin, for good.
+    Closed,
+}
+
+/// Why the status of an account changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReasonCode {
+    /// Set by the engine on the chargeback of a deposit.
+    Chargeback,
+    Compliance,
+    Fraud,
+    ClientRequest,
+    /// The cause of a lock was investigated and cleared, eg. to unlock the account.
+    Cleared,
+    Other,
+}
+
+impl ReasonCode {
+    pub const ALL: [ReasonCode; 6] = [
+        ReasonCode::Chargeback,
+        ReasonCode::Compliance,
+        ReasonCode::Fraud,
+        ReasonCode::ClientRequest,
+        ReasonCode::Cleared,
+        ReasonCode::Other,
+    ];
+
+    /// Parse a reason code, eg. `compliance`.
+    pub fn new(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|reason| reason.as_str() == code)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonCode::Chargeback => "chargeback",
+            ReasonCode::Compliance => "compliance",
+            ReasonCode::Fraud => "fraud",
+            ReasonCode::ClientRequest => "client_request",
+            ReasonCode::Cleared => "cleared",
+            ReasonCode::Other => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Balance in one currency.
+///
+/// `available` (and `total`) only go below zero if the [crate::policy::NegativeBalancePolicy] allows it,
+/// see [Balance::deficit].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Balance {
+    pub available: SignedAmount,
+    pub total: SignedAmount,
 }
 
 impl ClientAccount {
-    pub fn held(&self) -> Amount {
-        // SAFETY: The application ensures available amount is always <= total amount
-        // So this should never go negative.
-        let mut total = self.total;
-        total
-            .try_subtract(self.available)
-            .expect("Held amount calculation below zero");
-        total
+    /// A new, empty and open account.
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            balances: BTreeMap::new(),
+            status: AccountStatus::Open,
+            reason: None,
+            last_timestamp: None,
+        }
+    }
+
+    /// Whether the account refuses activity, locked or closed.
+    pub fn is_locked(&self) -> bool {
+        self.status != AccountStatus::Open
+    }
+
+    /// Lock the account for the given reason.
+    pub fn lock(&mut self, reason: ReasonCode) {
+        self.status = AccountStatus::Locked;
+        self.reason = Some(reason);
+    }
+
+    /// The first balance that isn't zero, an account can only be closed without any.
+    /// Its total, or its available funds if the total is zero (funds held against a deficit).
+    pub fn non_zero_balance(&self) -> Option<Money> {
+        self.balances.iter().find_map(|(currency, balance)| {
+            [balance.total, balance.available]
+                .into_iter()
+                .find(|amount| !amount.as_decimal().is_zero())
+                .map(|amount| Money {
+                    amount: amount.as_decimal().into(),
+                    currency: *currency,
+                })
+        })
+    }
+
+    /// Balance in the currency, zero if the client never held it.
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    /// Balance in the currency to update, created empty if the client never held it.
+    pub fn balance_mut(&mut self, currency: Currency) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+
+    /// Apply journal lines to the balances, lines of other accounts are ignored.
+    ///
+    /// Credits add to the available or held funds of the client, debits take from them.
+    /// The lines are netted first: a move between the available and held funds leaves the total untouched.
+    pub fn post(&mut self, lines: &[JournalLine]) {
+        let mut deltas = BTreeMap::<Currency, (Decimal, Decimal)>::new();
+        for line in lines {
+            let held = match line.account {
+                JournalAccount::Available(client_id) if client_id == self.client_id => false,
+                JournalAccount::Held(client_id) if client_id == self.client_id => true,
+                _ => continue,
+            };
+            let amount = match line.side {
+                Side::Credit => line.amount.amount.as_decimal(),
+                Side::Debit => -line.amount.amount.as_decimal(),
+            };
+            let (available, total) = deltas.entry(line.amount.currency).or_default();
+            if !held {
+                *available += amount;
+            }
+            *total += amount;
+        }
+
+        for (currency, (available, total)) in deltas {
+            let balance = self.balance_mut(currency);
+            if !available.is_zero() {
+                balance.available = (balance.available.as_decimal() + available).into();
+            }
+            if !total.is_zero() {
+                balance.total = (balance.total.as_decimal() + total).into();
+            }
+        }
+    }
+
+    /// Available funds in the currency, as money (negative if the balance has a deficit).
+    pub fn available(&self, currency: Currency) -> Money {
+        Money {
+            amount: self.balance(currency).available.as_decimal().into(),
+            currency,
+        }
+    }
+}
+
+impl Balance {
+    /// The disputed funds: the total minus the available funds.
+    ///
+    /// The engine keeps the available funds at or below the total, so this is never negative for the
+    /// balances it writes. A balance that drifted (eg. a corrupted ledger) gives a negative amount
+    /// instead of failing, see [crate::Engine::reconcile].
+    pub fn held(&self) -> SignedAmount {
+        SignedAmount::from(self.total.as_decimal() - self.available.as_decimal())
+    }
+
+    /// What the client owes: how far the available funds are below zero.
+    pub fn deficit(&self) -> Amount {
+        self.available.deficit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn non_zero_balance_reports_the_non_zero_part() {
+        let mut account = ClientAccount::new(ClientId::from(1));
+        assert_eq!(account.non_zero_balance(), None);
+
+        // 5 held against a deficit of 5: nothing in total, but the available funds aren't settled
+        let balance = account.balance_mut(Currency::NONE);
+        balance.available = Decimal::from(-5).into();
+        assert_eq!(
+            account.non_zero_balance(),
+            Some(Money::from(Amount::from(Decimal::from(-5))))
+        );
+
+        let balance = account.balance_mut(Currency::NONE);
+        balance.total = Decimal::from(2).into();
+        balance.available = Decimal::from(2).into();
+        assert_eq!(
+            account.non_zero_balance(),
+            Some(Money::from(Amount::from(Decimal::from(2))))
+        );
     }
 }
@@ -0,0 +1,56 @@
+//! Source of the current time of the engine.
+//!
+//! Events may carry the time they happened (see [crate::TimedEvent]), the engine falls back to its
+//! clock for the others. Tests inject a [ManualClock] to control time-based rules.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::engine::types::Timestamp;
+
+pub trait Clock: Debug + Send + Sync {
+    /// The current time, in seconds since the Unix epoch.
+    fn now(&self) -> Timestamp;
+}
+
+/// The system time, the default clock of an engine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Timestamp::from(secs)
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test keeps a clone to move the clock of an engine.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self(Arc::new(AtomicU64::new(now.as_inner())))
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.0.store(now.as_inner(), Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `secs` seconds.
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from(self.0.load(Ordering::SeqCst))
+    }
+}
@@ -1,21 +1,253 @@
-use crate::engine::types::{Amount, ClientId, TransactionId};
-use crate::errors::EngineError;
-use crate::ledger::transactions::{Direction, Transaction, TransactionStatus};
-use crate::{ClientAccount, Event, ledger::Ledger};
-use std::collections::HashMap;
+use crate::engine::clock::{Clock, SystemClock};
+use crate::engine::fees::FeeEvent;
+use crate::engine::policy::{
+    ExpiryAction, NegativeBalancePolicy, Policies, Policy, TimestampPolicy,
+};
+use crate::engine::reconciliation::{Expected, Reconciliation};
+use crate::engine::risk::RiskCheck;
+use crate::engine::types::{
+    Amount, ClientId, Currency, Money, SignedAmount, Timestamp, TransactionId,
+};
+use crate::errors::{EngineError, SystemFault};
+use crate::event_store::{EventOrigin, EventStore, RecordedEvent, in_memory::InMemoryEventStore};
+use crate::ledger::journal::{EntryKind, JournalAccount, JournalEntry, TrialBalance};
+use crate::ledger::transactions::{
+    Compensation, CompensationKind, Direction, FeePosting, Transaction, TransactionStatus,
+};
+use crate::{
+    AccountStatus, Applied, Balances, ClientAccount, Event, ReasonCode, Receipt, TimedEvent,
+    ledger::{Ledger, LedgerError},
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 
 #[derive(Debug)]
-pub struct Engine<L> {
+pub struct Engine<L, E = InMemoryEventStore> {
     accounts: HashMap<ClientId, ClientAccount>,
     ledger: L,
+    events: E,
+    policies: Policies,
+    clock: Arc<dyn Clock>,
+    /// Start of the held disputes of each client, see [Engine::expire_disputes].
+    open_disputes: HashMap<ClientId, BTreeMap<TransactionId, Timestamp>>,
+    /// Balances of the house account, see [Engine::revenue].
+    revenue: BTreeMap<Currency, Amount>,
+    /// Times of the recent withdrawals of each client, as far back as its risk rules look.
+    withdrawals: HashMap<ClientId, Vec<Timestamp>>,
+    /// Transactions approved after a review, see [Event::Approve].
+    reviewed: HashSet<(ClientId, TransactionId)>,
+    /// Latest timestamp given with an event, see [Engine::latest_timestamp].
+    latest_timestamp: Option<Timestamp>,
+    /// The event being applied, recorded right before it writes the ledger, see `apply_recorded`.
+    pending: Option<(TimedEvent, EventOrigin)>,
+    /// The last event of the event store on startup if it was accepted, see [Engine::recover].
+    unconfirmed: Option<RecordedEvent>,
+    /// Set once the event store and the ledger may disagree, the engine refuses further events.
+    stopped: Option<SystemFault>,
+}
+
+/// Time of an event.
+#[derive(Debug, Clone, Copy)]
+struct EventTime {
+    /// Timestamp given with the event, checked against the latest one of the client.
+    /// Only given timestamps are stored with transactions and count for the dispute deadlines.
+    given: Option<Timestamp>,
+    /// The time the event is booked at (journal entries, fees, risk rules): the given timestamp, or the engine clock.
+    now: Timestamp,
+}
+
+/// Result of [Engine::rebuild_from].
+#[derive(Debug)]
+pub struct Rebuild<L, E = InMemoryEventStore> {
+    /// Engine holding the state rebuilt from the events.
+    pub engine: Engine<L, E>,
+    /// Accounts that differ between the current and the rebuilt state, ordered by client ID.
+    pub mismatches: Vec<AccountMismatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountMismatch {
+    pub client_id: ClientId,
+    pub current: Option<ClientAccount>,
+    pub rebuilt: Option<ClientAccount>,
 }
 
 impl<L: Ledger> Engine<L> {
-    pub fn new(ledger: L) -> Self {
-        Engine {
+    /// Create an engine on top of the given ledger, loading the client accounts stored in it.
+    /// Events are recorded in memory.
+    pub async fn new(ledger: L) -> Result<Self, EngineError> {
+        Self::with_event_store(ledger, InMemoryEventStore::new()).await
+    }
+}
+
+impl<L: Ledger, E: EventStore> Engine<L, E> {
+    /// Create an engine on top of the given ledger, loading the client accounts stored in it.
+    /// Every applied event is recorded in the given event store.
+    pub async fn with_event_store(ledger: L, events: E) -> Result<Self, EngineError> {
+        let accounts: HashMap<_, _> = ledger
+            .accounts()
+            .await?
+            .into_iter()
+            .map(|account| (account.client_id, account))
+            .collect();
+        let mut open_disputes = HashMap::<_, BTreeMap<_, _>>::new();
+        for transaction in ledger.disputed().await? {
+            let info = transaction.info();
+            if let Some(disputed_at) = info.disputed_at {
+                open_disputes
+                    .entry(info.client_id)
+                    .or_default()
+                    .insert(info.id, disputed_at);
+            }
+        }
+
+        let mut revenue = BTreeMap::new();
+        for fee in ledger.fees().await? {
+            credit(&mut revenue, fee.amount);
+        }
+        // The withdrawal entries are booked at the time the risk rules saw them, `with_policies` drops
+        // the ones its rules don't look back at.
+        let mut withdrawals = HashMap::<_, Vec<_>>::new();
+        for entry in ledger.journal().await? {
+            if entry.kind == EntryKind::Withdrawal {
+                withdrawals
+                    .entry(entry.client_id)
+                    .or_default()
+                    .push(entry.timestamp);
+            }
+        }
+        let recorded = events.events().await?;
+        let reviewed = recorded
+            .iter()
+            .filter(|recorded| recorded.is_accepted())
+            .filter_map(|recorded| match recorded.event {
+                Event::Approve {
+                    client_id,
+                    transaction_id,
+                } => Some((client_id, transaction_id)),
+                _ => None,
+            })
+            .collect();
+        let unconfirmed = recorded
+            .into_iter()
+            .last()
+            .filter(RecordedEvent::is_accepted);
+        let latest_timestamp = accounts
+            .values()
+            .filter_map(|account| account.last_timestamp)
+            .max();
+
+        Ok(Engine {
             ledger,
-            accounts: <_>::default(),
+            accounts,
+            events,
+            policies: Policies::default(),
+            clock: Arc::new(SystemClock),
+            open_disputes,
+            revenue,
+            withdrawals,
+            reviewed,
+            latest_timestamp,
+            pending: None,
+            unconfirmed,
+            stopped: None,
+        })
+    }
+
+    /// Apply the given policies instead of the built-in rules, see [Policies].
+    pub fn with_policies(mut self, policies: Policies) -> Self {
+        self.policies = policies;
+        let policies = &self.policies;
+        self.withdrawals.retain(|client_id, withdrawals| {
+            let lookback = policies.of(*client_id).risk.lookback();
+            let latest = withdrawals.iter().max().copied();
+            withdrawals.retain(|withdrawn_at| Some(withdrawn_at.after(lookback)) > latest);
+            lookback > 0 && !withdrawals.is_empty()
+        });
+        self
+    }
+
+    /// Take the time of events without timestamp from `clock` instead of the system time.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The policies applied by this engine.
+    pub fn policies(&self) -> &Policies {
+        &self.policies
+    }
+
+    /// The store of all events applied to this engine.
+    pub fn event_store(&self) -> &E {
+        &self.events
+    }
+
+    /// Rebuild the accounts and the ledger from scratch by replaying the accepted events on an empty `ledger`,
+    /// recording them again in the empty `event_store`.
+    ///
+    /// The rebuilt accounts are checked against the current ones, the differences are returned as mismatches.
+    /// Fails if an accepted event is rejected during the replay, which means the events don't describe
+    /// a valid history.
+    pub async fn rebuild_from<L2: Ledger, E2: EventStore>(
+        &self,
+        events: impl IntoIterator<Item = RecordedEvent>,
+        ledger: L2,
+        event_store: E2,
+    ) -> Result<Rebuild<L2, E2>, EngineError> {
+        let mut engine = Engine::with_event_store(ledger, event_store)
+            .await?
+            .with_policies(self.policies.clone());
+        engine.clock = self.clock.clone();
+        for recorded in events.into_iter().filter(RecordedEvent::is_accepted) {
+            // only the recorded events are applied: the disputes that expired are among them
+            match recorded.origin {
+                EventOrigin::Partner => {
+                    let time = engine.event_time(recorded.timed());
+                    engine.apply_partner(recorded.timed(), time).await?
+                }
+                EventOrigin::System => {
+                    let at = recorded.timestamp.unwrap_or_else(|| engine.clock.now());
+                    engine.apply_system(recorded.event, at).await?
+                }
+            };
         }
+
+        let client_ids = self
+            .accounts
+            .keys()
+            .chain(engine.accounts.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+        let mismatches = client_ids
+            .into_iter()
+            .filter_map(|client_id| {
+                let current = self.accounts.get(&client_id);
+                let rebuilt = engine.accounts.get(&client_id);
+                (current != rebuilt).then(|| AccountMismatch {
+                    client_id,
+                    current: current.cloned(),
+                    rebuilt: rebuilt.cloned(),
+                })
+            })
+            .collect();
+
+        Ok(Rebuild { engine, mismatches })
+    }
+
+    /// The latest timestamp given with an event of any client (see [TimedEvent]), `None` if no event had one.
+    ///
+    /// Disputes of clients without later events only expire when asked, eg. at the end of a batch:
+    /// `engine.expire_disputes(latest)`, see [Self::expire_disputes].
+    pub fn latest_timestamp(&self) -> Option<Timestamp> {
+        self.latest_timestamp
+    }
+
+    /// The fault that stopped the engine, `None` while it accepts events.
+    /// A stopped engine refuses every further event until it is opened again.
+    pub fn fault(&self) -> Option<SystemFault> {
+        self.stopped
     }
 
     /// Returns a vector of client accounts sorted by client ID.
@@ -25,6 +257,51 @@ impl<L: Ledger> Engine<L> {
         accounts
     }
 
+    /// Returns the account of a client, `None` if the client has no activity yet.
+    pub fn account(&self, client_id: ClientId) -> Option<&ClientAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    /// Returns a transaction of a client from the ledger.
+    pub async fn transaction(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Option<Transaction>, EngineError> {
+        Ok(self.ledger.find(client_id, transaction_id).await?)
+    }
+
+    /// The compensating entries appended to a transaction by its disputes, resolves and chargebacks,
+    /// in order. Its status ([Self::transaction]) is derived from them.
+    pub async fn compensations(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Vec<Compensation>, EngineError> {
+        Ok(self.ledger.compensations(client_id, transaction_id).await?)
+    }
+
+    /// Balances of the house account per currency: the fees charged to the clients, see [crate::fees::FeeSchedule].
+    pub fn revenue(&self) -> &BTreeMap<Currency, Amount> {
+        &self.revenue
+    }
+
+    /// Totals of the journal accounts from the entries stored in the ledger, see [crate::ledger::journal].
+    ///
+    /// The books are balanced ([TrialBalance::is_balanced]) and the balances of each client can be derived
+    /// from it ([TrialBalance::balance]).
+    pub async fn trial_balance(&self) -> Result<TrialBalance, EngineError> {
+        Ok(TrialBalance::of(&self.ledger.journal().await?))
+    }
+
+    /// Recompute the balances of every client from the transactions and fees stored in the ledger,
+    /// and report those that differ from the accounts, see [crate::reconciliation].
+    pub async fn reconcile(&self) -> Result<Reconciliation, EngineError> {
+        let transactions = self.ledger.transactions().await?;
+        let fees = self.ledger.fees().await?;
+        Ok(Expected::of(&transactions, &fees).reconcile(self.accounts.values()))
+    }
+
     /// Returns an iterator over client accounts.
     /// Order is not guaranteed.
     pub fn accounts(&self) -> std::collections::hash_map::Values<'_, ClientId, ClientAccount> {
@@ -33,250 +310,2754 @@ impl<L: Ledger> Engine<L> {
 
     /// Apply an event to the engine and update the associated client account.
     ///
-    pub async fn apply(&mut self, event: Event) -> Result<(), EngineError> {
-        event.validate()?;
+    /// The event is recorded in the event store, together with the reason if it was rejected.
+    ///
+    /// Returns a receipt of what changed.
+    /// An exact replay of an already applied event succeeds with [Applied::AlreadyApplied]
+    /// and changes nothing.
+    ///
+    /// An [Event] has no timestamp: it is booked at the time of the engine clock, but its transaction and
+    /// dispute store none and aren't subject to the dispute deadlines ([crate::policy::DisputeDeadlines]).
+    /// An event with a timestamp older than the latest event of the client is rejected or flagged,
+    /// see [TimestampPolicy].
+    ///
+    /// Disputes of the client that expired by the timestamp of the event are settled first,
+    /// see [Self::expire_disputes].
+    ///
+    /// An event that writes the ledger is recorded right before the write, other events once applied.
+    /// An event that can't be recorded fails with a system error, as does one recorded whose ledger write
+    /// fails: the engine and its event store may disagree and it refuses any further event.
+    /// Once opened again, [Self::recover] applies the last recorded event again.
+    pub async fn apply(&mut self, event: impl Into<TimedEvent>) -> Result<Receipt, EngineError> {
+        let event = event.into();
+        self.recover().await?;
+        let time = self.event_time(event);
+        self.latest_timestamp = self.latest_timestamp.max(time.given);
+        if let Some(now) = time.given {
+            self.expire_client_disputes(event.event.client_id(), now)
+                .await?;
+        }
 
-        // Idempotency and atomicity considerations:
+        self.apply_partner(event, time).await
+    }
+
+    fn event_time(&self, event: TimedEvent) -> EventTime {
+        EventTime {
+            given: event.timestamp,
+            now: event.timestamp.unwrap_or_else(|| self.clock.now()),
+        }
+    }
+
+    /// Apply and record an event of the partner, without settling expired disputes first.
+    async fn apply_partner(
+        &mut self,
+        event: TimedEvent,
+        time: EventTime,
+    ) -> Result<Receipt, EngineError> {
+        self.apply_recorded(event, EventOrigin::Partner, time).await
+    }
+
+    /// Apply the last event recorded before the engine stopped again, in case it was recorded
+    /// but the ledger write that followed didn't happen (see [SystemFault::Unapplied]).
+    /// If the ledger has it already, it is an already applied replay.
+    ///
+    /// [Engine::apply] and [Engine::expire_disputes] recover first. Call it once the engine is configured
+    /// (eg. [Engine::with_policies]) to have the accounts up to date before any event.
+    pub async fn recover(&mut self) -> Result<Option<Receipt>, EngineError> {
+        let Some(recorded) = self.unconfirmed.take() else {
+            return Ok(None);
+        };
+        let time = match recorded.origin {
+            EventOrigin::Partner => self.event_time(recorded.timed()),
+            EventOrigin::System => EventTime {
+                given: None,
+                now: recorded.timestamp.unwrap_or_else(|| self.clock.now()),
+            },
+        };
+        self.latest_timestamp = self.latest_timestamp.max(time.given);
+        match self.apply_event(recorded.event, time).await {
+            Ok(receipt) => Ok(Some(receipt)),
+            Err(err) => {
+                // the ledger and the event store disagree, every attempt fails until that is fixed
+                self.unconfirmed = Some(recorded);
+                Err(err)
+            }
+        }
+    }
+
+    /// Settle the disputes held for longer than [crate::policy::DisputeDeadlines::max_disputed] at `now`.
+    ///
+    /// The engine emits a resolve or a chargeback (see [ExpiryAction]) of each of them, recorded in the
+    /// event store as a system event at the time the dispute expired. [Self::apply] does it for the client
+    /// of each event, this settles the disputes of all clients (eg. periodically or at the end of a batch).
+    ///
+    /// Returns the receipts of the settled disputes. A settlement rejected by the engine (eg. on a locked account)
+    /// is recorded with its rejection and not retried, the dispute stays held until the partner settles it.
+    pub async fn expire_disputes(&mut self, now: Timestamp) -> Result<Vec<Receipt>, EngineError> {
+        self.recover().await?;
+        let mut client_ids = self.open_disputes.keys().copied().collect::<Vec<_>>();
+        client_ids.sort();
+
+        let mut receipts = vec![];
+        for client_id in client_ids {
+            receipts.extend(self.expire_client_disputes(client_id, now).await?);
+        }
+
+        Ok(receipts)
+    }
+
+    async fn expire_client_disputes(
+        &mut self,
+        client_id: ClientId,
+        now: Timestamp,
+    ) -> Result<Vec<Receipt>, EngineError> {
+        let deadlines = self.policies.of(client_id).deadlines;
+        let Some(max_disputed) = deadlines.max_disputed else {
+            return Ok(vec![]);
+        };
+        let mut expired = self
+            .open_disputes
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .map(|(transaction_id, disputed_at)| (disputed_at.after(max_disputed), *transaction_id))
+            .filter(|(expires_at, _)| *expires_at <= now)
+            .collect::<Vec<_>>();
+        expired.sort();
+
+        let mut receipts = vec![];
+        for (expires_at, transaction_id) in expired {
+            let event = match deadlines.on_expiry {
+                ExpiryAction::Resolve => Event::Resolve {
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                },
+                ExpiryAction::Chargeback => Event::Chargeback {
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                },
+            };
+            match self.apply_system(event, expires_at).await {
+                Ok(receipt) => receipts.push(receipt),
+                Err(err) if err.is_partner_error() => {
+                    self.untrack_dispute(client_id, transaction_id)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(receipts)
+    }
+
+    /// Apply and record an event emitted by the engine at `at`.
+    async fn apply_system(&mut self, event: Event, at: Timestamp) -> Result<Receipt, EngineError> {
+        let time = EventTime {
+            given: None,
+            now: at,
+        };
+        self.apply_recorded(event.at(at), EventOrigin::System, time)
+            .await
+    }
+
+    /// Apply an event and record it with its outcome.
+    ///
+    /// An event that writes the ledger is recorded as accepted right before the write (see `record_pending`),
+    /// so the ledger never holds a write the event store misses. If the write fails, the engine stops
+    /// and [Engine::recover] applies the event again once the engine is opened again.
+    /// Other events (rejected, replays, approvals) are recorded once applied.
+    async fn apply_recorded(
+        &mut self,
+        event: TimedEvent,
+        origin: EventOrigin,
+        time: EventTime,
+    ) -> Result<Receipt, EngineError> {
+        self.ensure_running(&event.event)?;
+        self.pending = Some((event, origin));
+        let result = self.apply_event(event.event, time).await;
+        match self.pending.take() {
+            Some((event, origin)) => self.record(event, origin, &result).await?,
+            None if result.is_err() => {
+                self.stopped.get_or_insert(SystemFault::Unapplied);
+            }
+            None => {}
+        }
+
+        result
+    }
+
+    /// Record an event with its outcome, the engine stops if that fails.
+    async fn record(
+        &mut self,
+        event: TimedEvent,
+        origin: EventOrigin,
+        result: &Result<Receipt, EngineError>,
+    ) -> Result<(), EngineError> {
+        let rejection = result.as_ref().err().map(ToString::to_string);
+        if let Err(err) = self.events.append(event, origin, rejection).await {
+            self.stopped = Some(SystemFault::Unrecorded);
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    /// Record the event being applied as accepted, right before its ledger write. Called by every `apply_*`
+    /// that writes the ledger, once nothing but the write can fail.
+    async fn record_pending(&mut self) -> Result<(), EngineError> {
+        if let Some((event, origin)) = self.pending.take()
+            && let Err(err) = self.events.append(event, origin, None).await
+        {
+            self.stopped = Some(SystemFault::Unrecorded);
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    fn ensure_running(&self, event: &Event) -> Result<(), EngineError> {
+        match self.stopped {
+            Some(fault) => Err(EngineError::SystemError {
+                fault,
+                client_id: Some(event.client_id()),
+                transaction_id: Some(event.transaction_id()),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    async fn apply_event(&mut self, event: Event, time: EventTime) -> Result<Receipt, EngineError> {
+        TimedEvent {
+            event,
+            timestamp: time.given,
+        }
+        .validate()?;
+
+        let client_id = event.client_id();
+        let before = self.accounts.get(&client_id).cloned();
+        let was_locked = self.is_locked(client_id);
+
+        // Atomicity:
         //
         // Each of the `apply_*` methods does two things (roughly speaking)
         // 1. Add or update the transaction in the ledger.
         // 2. Update the associated client account.
         //
-        // In a real system,
-        // For strongly consistent systems: both operation should be done in a single transaction to ensure consistency.
-        // for eventually consistent systems: we should have a mechanism to recover from partial failures.
-        // It should also make sure the side effects are idempotent.
-        // Although this implementation rejects same transaction being applied twice (see the state machine in `transition_inbound`)
-        // There are still some edge cases: eg. withdraw failed due to insufficient funds will leave the transaction in the ledger.
+        // All validation (account lock, funds, state transition) is done on a staged copy of the account first.
+        // The funds moved are posted as balanced lines of a journal entry, which are applied to the staged account
+        // (see `transfer`): the balances are the sum of the journal lines.
+        // The single ledger write is the commit point: if it fails, the staged account is dropped and nothing changed.
+        // Once it succeeds, the staged account is stored, which can't fail.
+        // This holds for any `Ledger` implementation, as long as a failed `add`/`update` leaves the ledger untouched.
+        //
+        // Idempotency:
+        // Replays are detected from the ledger state, before anything else is checked:
+        // - Deposit/Withdraw: the transaction exists with the same details (whatever its current status).
+        // - Dispute/Resolve/Chargeback: the transaction already reached the target status (see `has_reached`)
+        //   and nothing is left to act on. Partial ones (with an amount) are replays if the chain of the
+        //   transaction has their entry (same kind, amount and timestamp, see `is_partial_replay`),
+        //   they are rejected without timestamp.
+        // Events that conflict with the original are still rejected (by the ledger or the state machine).
+        // The timestamp is checked once the event is known not to be a replay, see `stage_account_ensure_unlocked`.
         //
         // Ledger behaviour:
-        // In a real system, the ledger should be immutable and append-only.
-        // Therefore, we'd create compensating transactions to reverse deposit when its charged back.
+        // The ledger is append-only. Deposits and withdrawals add a transaction, which is never changed:
+        // disputes, resolves and chargebacks append a compensating entry to it (hold, release, reversal),
+        // the status of the transaction is derived from its chain of entries (see `Transaction::compensate`).
         //
-        // We'd also have an Event store to keep track of all the events to rebuild the client accounts later.
+        // Every event is recorded in the event store by `apply`, see `rebuild_from`: right before the ledger
+        // write if there is one (see `apply_recorded`), so a crash in between is recovered on startup.
 
-        match event {
+        let transacted = |(applied, transaction)| (applied, Some(transaction), None);
+        let charged = |(applied, transaction, fee)| (applied, Some(transaction), fee);
+        let account_event = |(applied, transaction)| (applied, transaction, None);
+        let (applied, transaction, fee) = match event {
             Event::Deposit {
                 client_id,
                 transaction_id,
                 amount,
-            } => {
-                self.apply_deposit(client_id, transaction_id, amount)
-                    .await?;
-            }
+            } => self
+                .apply_deposit(client_id, transaction_id, amount, time)
+                .await
+                .map(charged),
             Event::Withdraw {
                 client_id,
                 transaction_id,
                 amount,
-            } => {
-                self.apply_withdraw(client_id, transaction_id, amount)
-                    .await?;
-            }
+            } => self
+                .apply_withdraw(client_id, transaction_id, amount, time)
+                .await
+                .map(charged),
             Event::Dispute {
                 client_id,
                 transaction_id,
-            } => {
-                self.apply_dispute(client_id, transaction_id).await?;
-            }
+                amount,
+            } => self
+                .apply_dispute(client_id, transaction_id, amount, time)
+                .await
+                .map(transacted),
             Event::Resolve {
                 client_id,
                 transaction_id,
-            } => {
-                self.apply_dispute_resolve(client_id, transaction_id)
-                    .await?;
-            }
+                amount,
+            } => self
+                .apply_dispute_resolve(client_id, transaction_id, amount, time)
+                .await
+                .map(transacted),
             Event::Chargeback {
                 client_id,
                 transaction_id,
-            } => {
-                self.apply_dispute_chargeback(client_id, transaction_id)
-                    .await?;
+                amount,
+            } => self
+                .apply_dispute_chargeback(client_id, transaction_id, amount, time)
+                .await
+                .map(charged),
+            Event::Unlock {
+                client_id, reason, ..
+            } => self
+                .apply_account_status(client_id, AccountStatus::Open, reason, time)
+                .await
+                .map(account_event),
+            Event::Freeze {
+                client_id, reason, ..
+            } => self
+                .apply_account_status(client_id, AccountStatus::Locked, reason, time)
+                .await
+                .map(account_event),
+            Event::Close {
+                client_id, reason, ..
+            } => self
+                .apply_account_status(client_id, AccountStatus::Closed, reason, time)
+                .await
+                .map(account_event),
+            Event::Approve {
+                client_id,
+                transaction_id,
+            } => Ok(account_event(
+                self.apply_approval(client_id, transaction_id),
+            )),
+        }?;
+        if let Some(transaction) = &transaction {
+            self.track_dispute(transaction);
+        }
+
+        // Disputes act on the currency of the original transaction.
+        // Account events have no transaction, they report the balance without currency.
+        let currency = transaction.map_or(Currency::NONE, |transaction| {
+            transaction.info().amount.currency
+        });
+        let balances = |account: Option<&ClientAccount>| {
+            account
+                .map(|account| Balances::from(account.balance(currency)))
+                .unwrap_or_default()
+        };
+
+        Ok(Receipt {
+            client_id,
+            transaction_id: event.transaction_id(),
+            applied,
+            currency,
+            before: balances(before.as_ref()),
+            after: balances(self.accounts.get(&client_id)),
+            status: transaction.map(|transaction| transaction.status()),
+            locked: !was_locked && self.is_locked(client_id),
+            fee,
+            out_of_order: applied == Applied::New
+                && is_out_of_order(
+                    time.given,
+                    before.as_ref().and_then(|account| account.last_timestamp),
+                ),
+        })
+    }
+
+    /// Keep track of the start of the held disputes of the transaction, see [Self::expire_disputes].
+    fn track_dispute(&mut self, transaction: &Transaction) {
+        let info = transaction.info();
+        match info.disputed_at {
+            Some(disputed_at) => {
+                self.open_disputes
+                    .entry(info.client_id)
+                    .or_default()
+                    .insert(info.id, disputed_at);
             }
+            None => self.untrack_dispute(info.client_id, info.id),
         }
+    }
 
-        Ok(())
+    fn untrack_dispute(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        if let Some(disputes) = self.open_disputes.get_mut(&client_id) {
+            disputes.remove(&transaction_id);
+            if disputes.is_empty() {
+                self.open_disputes.remove(&client_id);
+            }
+        }
+    }
+
+    fn is_locked(&self, client_id: ClientId) -> bool {
+        self.accounts
+            .get(&client_id)
+            .is_some_and(|account| account.is_locked())
     }
 
-    /// Get the client account (creates a new one if it doesn't exist).
+    /// Get a copy of the client account to stage changes on (creates a new one if it doesn't exist).
     ///
-    /// If the account is locked, returns an error.
+    /// If the account is locked or closed, returns an error with the reason.
     /// This ensure that no further activity is allowed on a locked accounts.
-    fn get_account_mut_ensure_unlocked(
-        &mut self,
+    /// The timestamp of the event is checked against the latest one of the client, see [Self::track_timestamp].
+    ///
+    /// The returned account is not stored until it is passed to [Self::commit_account],
+    /// which should only happen after the ledger write succeeded.
+    fn stage_account_ensure_unlocked(
+        &self,
         client_id: ClientId,
-    ) -> Result<&mut ClientAccount, EngineError> {
-        let account = self
+        timestamp: Option<Timestamp>,
+    ) -> Result<ClientAccount, EngineError> {
+        let mut account = self
             .accounts
-            .entry(client_id)
-            .or_insert_with(|| ClientAccount {
-                client_id,
-                available: Amount::default(),
-                total: Amount::default(),
-                is_locked: false,
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_else(|| ClientAccount::new(client_id));
+
+        ensure_open(&account)?;
+        self.track_timestamp(&mut account, timestamp)?;
+
+        Ok(account)
+    }
+
+    /// Record the timestamp of an event on the (staged) account.
+    ///
+    /// An event older than the latest event of the client fails under [TimestampPolicy::Reject],
+    /// the latest timestamp never goes backwards.
+    fn track_timestamp(
+        &self,
+        account: &mut ClientAccount,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), EngineError> {
+        let Some(timestamp) = timestamp else {
+            return Ok(());
+        };
+
+        if let Some(latest) = account.last_timestamp
+            && timestamp < latest
+            && self.policies.of(account.client_id).timestamps == TimestampPolicy::Reject
+        {
+            return Err(EngineError::TimestampOutOfOrder {
+                client_id: account.client_id,
+                timestamp,
+                latest,
             });
+        }
+        account.last_timestamp = account.last_timestamp.max(Some(timestamp));
+
+        Ok(())
+    }
 
-        if account.is_locked {
-            return Err(EngineError::AccountLocked(client_id));
+    /// Store the staged account in memory, the ledger already persisted it along with the transaction.
+    /// This step is infallible, so it is always the last step of an `apply_*`.
+    fn commit_account(&mut self, account: ClientAccount) {
+        self.accounts.insert(account.client_id, account);
+    }
+
+    /// Run the risk rules of the client on a new deposit or withdrawal, before anything is changed.
+    fn check_risk(
+        &self,
+        account: &ClientAccount,
+        transaction: &Transaction,
+        time: EventTime,
+    ) -> Result<(), EngineError> {
+        let rules = &self.policies.of(account.client_id).risk;
+        if rules.is_empty() {
+            return Ok(());
         }
 
-        Ok(account)
+        let info = transaction.info();
+        let check = RiskCheck {
+            client_id: info.client_id,
+            transaction_id: info.id,
+            direction: transaction.direction(),
+            amount: info.amount,
+            account,
+            at: time.now,
+            recent_withdrawals: self
+                .withdrawals
+                .get(&info.client_id)
+                .map_or(&[], Vec::as_slice),
+            reviewed: self.reviewed.contains(&(info.client_id, info.id)),
+        };
+        rules
+            .check(&check)
+            .map_err(|(rule, reason)| EngineError::RiskRejected {
+                client_id: info.client_id,
+                transaction_id: info.id,
+                rule: rule.name().to_string(),
+                reason,
+            })
+    }
+
+    /// Keep the time of a withdrawal as long as the risk rules of the client look back, see [RiskCheck::recent_withdrawals].
+    fn track_withdrawal(&mut self, client_id: ClientId, at: Timestamp) {
+        let lookback = self.policies.of(client_id).risk.lookback();
+        if lookback == 0 {
+            return;
+        }
+        let withdrawals = self.withdrawals.entry(client_id).or_default();
+        withdrawals.retain(|withdrawn_at| withdrawn_at.after(lookback) > at);
+        withdrawals.push(at);
+    }
+
+    /// Credit a fee stored in the ledger to the house account.
+    fn commit_fee(&mut self, fee: Option<FeePosting>) {
+        if let Some(fee) = fee {
+            credit(&mut self.revenue, fee.amount);
+        }
+    }
+
+    /// Find the transaction in the ledger if it was already added with the same details.
+    /// Its current status may differ (eg. a deposit disputed since).
+    ///
+    /// Fails if its id is taken by another transaction (of the client with other details, or of another client):
+    /// the ledger would reject its `add`, which comes after the event is recorded (see `record_pending`).
+    async fn find_replayed(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Option<Transaction>, EngineError> {
+        let info = transaction.info();
+        let (client_id, transaction_id) = (info.client_id, info.id);
+        match self.ledger.find(client_id, transaction_id).await? {
+            Some(existing) if existing.same_details(transaction) => return Ok(Some(existing)),
+            Some(_) => {
+                return Err(LedgerError::DifferentDetails {
+                    client_id,
+                    transaction_id,
+                }
+                .into());
+            }
+            None => {}
+        }
+        match self.ledger.client_of(transaction_id).await? {
+            Some(other) if other != client_id => Err(LedgerError::DifferentClient {
+                client_id,
+                transaction_id,
+            }
+            .into()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether a partial dispute/resolve/chargeback already appended its entry to the chain of the transaction.
+    ///
+    /// A partial event is identified by its kind, amount (rounded, see [partial_amount]) and timestamp:
+    /// a further partial event of the same amount comes at another time. Partial events without timestamp
+    /// are rejected, see [TimedEvent::validate].
+    async fn is_partial_replay(
+        &self,
+        transaction: &Transaction,
+        kind: CompensationKind,
+        amount: Option<Amount>,
+        time: EventTime,
+    ) -> Result<bool, EngineError> {
+        let (Some(amount), Some(timestamp)) = (amount, time.given) else {
+            return Ok(false);
+        };
+        let info = transaction.info();
+        let chain = self.ledger.compensations(info.client_id, info.id).await?;
+
+        Ok(chain.iter().any(|compensation| {
+            compensation.kind == kind
+                && compensation.amount.amount == amount
+                && compensation.timestamp == Some(timestamp)
+        }))
+    }
+
+    /// Find the transaction a dispute/resolve/chargeback refers to, a deposit or a withdrawal.
+    async fn find_disputable(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Transaction, EngineError> {
+        self.ledger
+            .find(client_id, transaction_id)
+            .await?
+            .ok_or(EngineError::TransactionNotFound {
+                client_id,
+                transaction_id,
+            })
     }
 
     async fn apply_withdraw(
         &mut self,
         client_id: ClientId,
         transaction_id: TransactionId,
-        amount: Amount,
-    ) -> Result<(), EngineError> {
-        let transaction = Transaction::new_settled_outbound(transaction_id, client_id, amount);
+        amount: Money,
+        time: EventTime,
+    ) -> Result<(Applied, Transaction, Option<Money>), EngineError> {
+        let transaction =
+            Transaction::new_settled_outbound(transaction_id, client_id, amount, time.given);
+        if let Some(existing) = self.find_replayed(&transaction).await? {
+            return Ok((Applied::AlreadyApplied, existing, None));
+        }
 
-        // Limitation: Ledger entry and account update is not an atomic operation.
-        // For failures due to insufficient funds, we will leave the transaction in the ledger.
-        //
-        // In a real app, the withdraw transaction could move to a `Failed` state.
-        self.ledger.add(client_id, transaction).await?;
-
-        let account = self.get_account_mut_ensure_unlocked(client_id)?;
-        account
-            .available
-            .try_subtract(amount)
-            .ok_or(EngineError::InsufficientFunds)?;
-        account
-            .total
-            .try_subtract(amount)
-            .ok_or(EngineError::InsufficientFunds)?; // unlikely to happen because we checked available above (which could be < total).
+        let mut account = self.stage_account_ensure_unlocked(client_id, time.given)?;
+        self.check_risk(&account, &transaction, time)?;
+        // The fee is withdrawn along with the amount.
+        let fee = self
+            .policies
+            .of(client_id)
+            .fees
+            .fee(FeeEvent::Withdrawal, client_id, amount);
+        let mut requested = amount;
+        if let Some(fee) = fee {
+            requested.amount += fee.amount;
+        }
+        // Withdrawals need the available funds whatever the negative balance policy.
+        if account.balance(amount.currency).available < SignedAmount::from(requested.amount) {
+            return Err(EngineError::InsufficientFunds {
+                client_id,
+                transaction_id,
+                requested,
+                available: account.available(amount.currency),
+            });
+        }
+        let mut entry = JournalEntry::new(EntryKind::Withdrawal, &transaction, time.now);
+        let available = JournalAccount::Available(client_id);
+        transfer(
+            &mut account,
+            &mut entry,
+            available,
+            JournalAccount::Settlement,
+            amount,
+        );
+        if let Some(fee) = fee {
+            transfer(
+                &mut account,
+                &mut entry,
+                available,
+                JournalAccount::House,
+                fee,
+            );
+        }
 
-        Ok(())
+        // Funds are checked before the ledger write, so a failed withdrawal leaves no trace in the ledger.
+        let posting = fee.map(|fee| fee_posting(&transaction, FeeEvent::Withdrawal, fee, time.now));
+        self.record_pending().await?;
+        self.ledger
+            .add(&account, transaction, posting, entry)
+            .await?;
+        self.commit_account(account);
+        self.commit_fee(posting);
+        self.track_withdrawal(client_id, time.now);
+
+        Ok((Applied::New, transaction, fee))
     }
 
     async fn apply_deposit(
         &mut self,
         client_id: ClientId,
         transaction_id: TransactionId,
-        amount: Amount,
-    ) -> Result<(), EngineError> {
-        let transaction = Transaction::new_settled_inbound(transaction_id, client_id, amount);
+        amount: Money,
+        time: EventTime,
+    ) -> Result<(Applied, Transaction, Option<Money>), EngineError> {
+        let transaction =
+            Transaction::new_settled_inbound(transaction_id, client_id, amount, time.given);
+        if let Some(existing) = self.find_replayed(&transaction).await? {
+            return Ok((Applied::AlreadyApplied, existing, None));
+        }
 
-        // Limitation: lack of idempotency check.
-        //
-        // Workaround:
-        // When the same event is replayed, This will
-        // Fail with `AlreadyExists` error and prevent double counting the same transaction.
-        self.ledger.add(client_id, transaction).await?;
+        let mut account = self.stage_account_ensure_unlocked(client_id, time.given)?;
+        self.check_risk(&account, &transaction, time)?;
+        let mut entry = JournalEntry::new(EntryKind::Deposit, &transaction, time.now);
+        transfer(
+            &mut account,
+            &mut entry,
+            JournalAccount::Settlement,
+            JournalAccount::Available(client_id),
+            amount,
+        );
+        let fee = self.charge_fee(&mut account, &mut entry, FeeEvent::Deposit, amount);
 
-        let account = self.get_account_mut_ensure_unlocked(client_id)?;
-        account.total += amount;
-        account.available += amount;
+        // A transaction with the same id but different details fails with a conflict.
+        let posting = fee.map(|fee| fee_posting(&transaction, FeeEvent::Deposit, fee, time.now));
+        self.record_pending().await?;
+        self.ledger
+            .add(&account, transaction, posting, entry)
+            .await?;
+        self.commit_account(account);
+        self.commit_fee(posting);
 
-        Ok(())
+        Ok((Applied::New, transaction, fee))
     }
 
     async fn apply_dispute(
         &mut self,
         client_id: ClientId,
         transaction_id: TransactionId,
-    ) -> Result<(), EngineError> {
+        amount: Option<Amount>,
+        time: EventTime,
+    ) -> Result<(Applied, Transaction), EngineError> {
+        let Policy {
+            transitions: policy,
+            negative_balance,
+            deadlines,
+            ..
+        } = self.policies.of(client_id);
+
         //
         // Update transaction
         //
-        let mut transaction = self
-            .ledger
-            .find(client_id, transaction_id)
+        let mut transaction = self.find_disputable(client_id, transaction_id).await?;
+        let amount = partial_amount(&transaction, amount)?;
+        if amount.is_none()
+            && transaction.has_reached(TransactionStatus::Disputed)
+            && transaction.disputable(policy).amount == Amount::default()
+        {
+            return Ok((Applied::AlreadyApplied, transaction));
+        }
+        if self
+            .is_partial_replay(&transaction, CompensationKind::Hold, amount, time)
             .await?
-            .ok_or(EngineError::InvalidEvent("transaction not found"))?;
-
-        if transaction.direction() != Direction::Inbound {
-            return Err(EngineError::InvalidAssociatedTransaction(
-                "Dispute must be on a deposit",
-            ));
+        {
+            return Ok((Applied::AlreadyApplied, transaction));
         }
-        // this will fail if the transaction already in `Disputed` state or in any other wrong state.
-        transaction.transition_inbound(TransactionStatus::Disputed)?;
+
+        // this will fail if the policy doesn't allow the dispute, or if the amount exceeds the disputable part.
+        let amount = transaction.dispute(policy, amount, time.given)?;
 
         //
         // Update Account
         //
-        let account = self.get_account_mut_ensure_unlocked(client_id)?;
+        let mut account = self.stage_account_ensure_unlocked(client_id, time.given)?;
+        // the window only applies to the times given by the partner
+        if let (Some(window), Some(settled_at), Some(disputed_at)) = (
+            deadlines.dispute_window,
+            transaction.info().timestamp,
+            time.given,
+        ) {
+            let closed_at = settled_at.after(window);
+            if disputed_at > closed_at {
+                return Err(EngineError::DisputeWindowClosed {
+                    client_id,
+                    transaction_id,
+                    closed_at,
+                });
+            }
+        }
+        let mut entry = JournalEntry::new(EntryKind::Dispute, &transaction, time.now);
+        hold(
+            &mut account,
+            &mut entry,
+            &transaction,
+            amount,
+            *negative_balance,
+        )?;
 
-        account
-            .available
-            .try_subtract(transaction.info().amount)
-            .ok_or(EngineError::InsufficientFunds)?; // for this example, we don't allow negative balance.
+        let compensation = transaction.compensation(CompensationKind::Hold, amount, time.given);
+        self.record_pending().await?;
+        self.ledger
+            .compensate(&account, compensation, None, entry)
+            .await?;
+        self.commit_account(account);
 
-        self.ledger.update(client_id, transaction).await?; // update ledger when account update is successful.
-        Ok(())
+        Ok((Applied::New, transaction))
     }
 
     async fn apply_dispute_resolve(
         &mut self,
         client_id: ClientId,
         transaction_id: TransactionId,
-    ) -> Result<(), EngineError> {
+        amount: Option<Amount>,
+        time: EventTime,
+    ) -> Result<(Applied, Transaction), EngineError> {
+        let Policy {
+            transitions: policy,
+            negative_balance,
+            ..
+        } = self.policies.of(client_id);
+
         //
         // Update Transaction
         //
-        let mut transaction = self
-            .ledger
-            .find(client_id, transaction_id)
-            .await?
-            .ok_or(EngineError::InvalidEvent("transaction not found"))?;
+        let mut transaction = self.find_disputable(client_id, transaction_id).await?;
+        let amount = partial_amount(&transaction, amount)?;
 
-        if transaction.direction() != Direction::Inbound {
-            return Err(EngineError::InvalidAssociatedTransaction(
-                "Dispute resolution must be on a deposit",
-            ));
+        if amount.is_none() && transaction.has_reached(TransactionStatus::Resolved) {
+            return Ok((Applied::AlreadyApplied, transaction));
+        }
+        if self
+            .is_partial_replay(&transaction, CompensationKind::Release, amount, time)
+            .await?
+        {
+            return Ok((Applied::AlreadyApplied, transaction));
         }
 
-        // this will bail if the transaction is not alredy in `Disputed` state.
-        transaction.transition_inbound(TransactionStatus::Resolved)?;
+        // this will bail if the policy doesn't allow the resolve (by default: not in `Disputed` state),
+        // or if the amount exceeds the held part.
+        let settlement = transaction.settle_dispute(policy, TransactionStatus::Resolved, amount)?;
 
         //
         // Update Account
         //
-        let account = self.get_account_mut_ensure_unlocked(client_id)?;
+        let mut account = self.stage_account_ensure_unlocked(client_id, time.given)?;
+        let mut entry = JournalEntry::new(EntryKind::Resolve, &transaction, time.now);
+        if !settlement.was_held {
+            hold(
+                &mut account,
+                &mut entry,
+                &transaction,
+                settlement.amount,
+                *negative_balance,
+            )?;
+        }
 
-        // release the held amount (= increase the available amount)
-        account.available += transaction.info().amount;
+        let amount = settlement.amount;
+        match transaction.direction() {
+            // release the held amount (= increase the available amount)
+            Direction::Inbound => transfer(
+                &mut account,
+                &mut entry,
+                JournalAccount::Held(client_id),
+                JournalAccount::Available(client_id),
+                amount,
+            ),
+            // the withdrawal stands, drop the held claim
+            Direction::Outbound => {
+                release_total(&mut account, &mut entry, amount, *negative_balance)?
+            }
+        }
 
-        self.ledger.update(client_id, transaction).await?; //update ledger when account update is successful.
+        let compensation = transaction.compensation(CompensationKind::Release, amount, time.given);
+        self.record_pending().await?;
+        self.ledger
+            .compensate(&account, compensation, None, entry)
+            .await?;
+        self.commit_account(account);
 
-        Ok(())
+        Ok((Applied::New, transaction))
     }
 
+    /// Admin events: unlock (`Open`), freeze (`Locked`) or close the account, recording the reason.
+    ///
+    /// Any lock can be lifted, whatever its reason, a closed account stays closed.
+    /// An event that doesn't change the status or the reason is an already applied replay.
+    /// A freeze of a client without account opens it locked (eg. a compliance hold before the first
+    /// deposit), an unlock or close of one fails as there is nothing to act on.
+    async fn apply_account_status(
+        &mut self,
+        client_id: ClientId,
+        status: AccountStatus,
+        reason: ReasonCode,
+        time: EventTime,
+    ) -> Result<(Applied, Option<Transaction>), EngineError> {
+        let mut account = match self.accounts.get(&client_id) {
+            Some(account) => account.clone(),
+            None if status == AccountStatus::Locked => ClientAccount::new(client_id),
+            None => return Err(EngineError::AccountNotFound { client_id }),
+        };
+
+        let unchanged = match status {
+            AccountStatus::Open => account.status == AccountStatus::Open,
+            AccountStatus::Locked | AccountStatus::Closed => {
+                account.status == status && account.reason == Some(reason)
+            }
+        };
+        if unchanged {
+            return Ok((Applied::AlreadyApplied, None));
+        }
+        if account.status == AccountStatus::Closed {
+            ensure_open(&account)?;
+        }
+        if status == AccountStatus::Closed
+            && let Some(balance) = account.non_zero_balance()
+        {
+            return Err(EngineError::NonZeroBalance { client_id, balance });
+        }
+        self.track_timestamp(&mut account, time.given)?;
+
+        account.status = status;
+        account.reason = Some(reason);
+
+        self.record_pending().await?;
+        self.ledger.update_account(&account).await?;
+        self.commit_account(account);
+
+        Ok((Applied::New, None))
+    }
+
+    /// Admin event: approve a transaction held for review, see [crate::risk::ReviewThreshold].
+    ///
+    /// An approval doesn't touch the account or the ledger, a second one is an already applied replay.
+    fn apply_approval(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> (Applied, Option<Transaction>) {
+        let applied = match self.reviewed.insert((client_id, transaction_id)) {
+            true => Applied::New,
+            false => Applied::AlreadyApplied,
+        };
+        (applied, None)
+    }
+
+    /// A chargeback of a deposit locks the account, a chargeback of a withdrawal reverses it.
     async fn apply_dispute_chargeback(
         &mut self,
         client_id: ClientId,
         transaction_id: TransactionId,
-    ) -> Result<(), EngineError> {
-        let mut transaction = self
-            .ledger
-            .find(client_id, transaction_id)
+        amount: Option<Amount>,
+        time: EventTime,
+    ) -> Result<(Applied, Transaction, Option<Money>), EngineError> {
+        let Policy {
+            transitions: policy,
+            negative_balance,
+            ..
+        } = self.policies.of(client_id);
+
+        let mut transaction = self.find_disputable(client_id, transaction_id).await?;
+        let amount = partial_amount(&transaction, amount)?;
+        let status = match transaction.direction() {
+            Direction::Inbound => TransactionStatus::ChargedBack,
+            Direction::Outbound => TransactionStatus::Reversed,
+        };
+
+        if amount.is_none() && transaction.has_reached(status) {
+            return Ok((Applied::AlreadyApplied, transaction, None));
+        }
+        if self
+            .is_partial_replay(&transaction, CompensationKind::Reversal, amount, time)
             .await?
-            .ok_or(EngineError::InvalidEvent("transaction not found"))?;
+        {
+            return Ok((Applied::AlreadyApplied, transaction, None));
+        }
 
-        if transaction.direction() != Direction::Inbound {
-            return Err(EngineError::InvalidAssociatedTransaction(
-                "Chargeback must be on a deposit",
-            ));
+        // this will bail if the policy doesn't allow the chargeback (by default: not in `Disputed` state),
+        // or if the amount exceeds the held part.
+        let settlement = transaction.settle_dispute(policy, status, amount)?;
+
+        let mut account = self.stage_account_ensure_unlocked(client_id, time.given)?;
+        let mut entry = JournalEntry::new(EntryKind::Chargeback, &transaction, time.now);
+        if !settlement.was_held {
+            // charged back straight away: the funds are held first, as if disputed
+            hold(
+                &mut account,
+                &mut entry,
+                &transaction,
+                settlement.amount,
+                *negative_balance,
+            )?;
         }
 
-        // this will bail if the transaction is not in `Disputed` state.
-        transaction.transition_inbound(TransactionStatus::ChargedBack)?;
+        let amount = settlement.amount;
+        match transaction.direction() {
+            // Available balance was already decreased when the transaction was disputed.
+            // Now update the total amount.
+            Direction::Inbound => {
+                release_total(&mut account, &mut entry, amount, *negative_balance)?;
+                account.lock(ReasonCode::Chargeback);
+            }
+            // The held claim is credited back.
+            Direction::Outbound => transfer(
+                &mut account,
+                &mut entry,
+                JournalAccount::Held(client_id),
+                JournalAccount::Available(client_id),
+                amount,
+            ),
+        }
+        let fee = self.charge_fee(&mut account, &mut entry, FeeEvent::Chargeback, amount);
 
-        let account = self.get_account_mut_ensure_unlocked(client_id)?;
+        let posting = fee.map(|fee| fee_posting(&transaction, FeeEvent::Chargeback, fee, time.now));
+        let compensation = transaction.compensation(CompensationKind::Reversal, amount, time.given);
+        self.record_pending().await?;
+        self.ledger
+            .compensate(&account, compensation, posting, entry)
+            .await?;
+        self.commit_account(account);
+        self.commit_fee(posting);
 
-        // Available balance was already decreased when the transaction was disputed.
-        // Now update the total amount.
-        account
-            .total
-            .try_subtract(transaction.info().amount)
-            .ok_or(EngineError::SystemError(
-                "Bug: total amount should never be negative.",
-            ))?;
-        account.is_locked = true;
+        Ok((Applied::New, transaction, fee))
+    }
 
-        self.ledger.update(client_id, transaction).await?; // update ledger when account update is successful.
+    /// Take the fee of `event` on `amount` from the available funds of the (staged) account, see [crate::fees::FeeSchedule].
+    ///
+    /// Unless the policy allows negative balances, the fee is capped to the available funds:
+    /// a deposit or chargeback is never rejected because of its fee. Returns the charged fee.
+    fn charge_fee(
+        &self,
+        account: &mut ClientAccount,
+        entry: &mut JournalEntry,
+        event: FeeEvent,
+        amount: Money,
+    ) -> Option<Money> {
+        let client_id = account.client_id;
+        let policy = self.policies.of(client_id);
+        let mut fee = policy.fees.fee(event, client_id, amount)?;
 
-        Ok(())
+        if policy.negative_balance == NegativeBalancePolicy::Reject {
+            let available = account
+                .balance(amount.currency)
+                .available
+                .as_decimal()
+                .max(Decimal::ZERO);
+            fee.amount = Amount::from(fee.amount.as_decimal().min(available));
+        }
+        if fee.amount == Amount::default() {
+            return None;
+        }
+        transfer(
+            account,
+            entry,
+            JournalAccount::Available(client_id),
+            JournalAccount::House,
+            fee,
+        );
+
+        Some(fee)
+    }
+}
+
+/// Round the amount of a partial dispute/resolve/chargeback to the minor units of the currency of its transaction.
+///
+/// Fails if nothing is left, eg. 0.4 of a JPY deposit.
+fn partial_amount(
+    transaction: &Transaction,
+    amount: Option<Amount>,
+) -> Result<Option<Amount>, EngineError> {
+    let Some(amount) = amount else {
+        return Ok(None);
+    };
+    let rounded = transaction.in_currency(amount);
+    if rounded.amount == Amount::default() {
+        let info = transaction.info();
+        return Err(EngineError::NonPositiveAmount {
+            client_id: info.client_id,
+            transaction_id: info.id,
+            amount: rounded,
+        });
+    }
+
+    Ok(Some(rounded.amount))
+}
+
+/// Whether an event at `timestamp` is older than the latest event of its client.
+fn is_out_of_order(timestamp: Option<Timestamp>, latest: Option<Timestamp>) -> bool {
+    matches!((timestamp, latest), (Some(timestamp), Some(latest)) if timestamp < latest)
+}
+
+/// Fails if the account doesn't accept activity: [EngineError::AccountLocked] (by a chargeback or a freeze,
+/// see the reason) or [EngineError::AccountClosed].
+fn ensure_open(account: &ClientAccount) -> Result<(), EngineError> {
+    let client_id = account.client_id;
+    // the engine records a reason on every status change
+    let reason = account.reason.unwrap_or(ReasonCode::Other);
+    match account.status {
+        AccountStatus::Open => Ok(()),
+        AccountStatus::Locked => Err(EngineError::AccountLocked { client_id, reason }),
+        AccountStatus::Closed => Err(EngineError::AccountClosed { client_id, reason }),
+    }
+}
+
+/// The ledger entry of a fee charged for `transaction`.
+fn fee_posting(
+    transaction: &Transaction,
+    event: FeeEvent,
+    amount: Money,
+    timestamp: Timestamp,
+) -> FeePosting {
+    let info = transaction.info();
+    FeePosting {
+        client_id: info.client_id,
+        transaction_id: info.id,
+        event,
+        amount,
+        timestamp,
+    }
+}
+
+fn credit(revenue: &mut BTreeMap<Currency, Amount>, fee: Money) {
+    *revenue.entry(fee.currency).or_default() += fee.amount;
+}
+
+/// Move `amount` between two journal accounts: post the lines to the journal entry and to the (staged) account.
+fn transfer(
+    account: &mut ClientAccount,
+    entry: &mut JournalEntry,
+    debit: JournalAccount,
+    credit: JournalAccount,
+    amount: Money,
+) {
+    account.post(&entry.transfer(debit, credit, amount));
+}
+
+/// Hold the disputed amount of the transaction on the (staged) account.
+///
+/// Holding a deposit the client already spent drives the available funds negative,
+/// only if the policy allows it.
+fn hold(
+    account: &mut ClientAccount,
+    entry: &mut JournalEntry,
+    transaction: &Transaction,
+    amount: Money,
+    negative_balance: NegativeBalancePolicy,
+) -> Result<(), EngineError> {
+    let client_id = account.client_id;
+    match transaction.direction() {
+        Direction::Inbound => {
+            if negative_balance == NegativeBalancePolicy::Reject
+                && account.balance(amount.currency).available < SignedAmount::from(amount.amount)
+            {
+                return Err(EngineError::InsufficientFunds {
+                    client_id,
+                    transaction_id: transaction.info().id,
+                    requested: amount,
+                    available: account.available(amount.currency),
+                });
+            }
+            transfer(
+                account,
+                entry,
+                JournalAccount::Available(client_id),
+                JournalAccount::Held(client_id),
+                amount,
+            );
+        }
+        // The withdrawn funds are claimed back: they are held until the dispute is settled.
+        Direction::Outbound => transfer(
+            account,
+            entry,
+            JournalAccount::Settlement,
+            JournalAccount::Held(client_id),
+            amount,
+        ),
+    }
+
+    Ok(())
+}
+
+/// Take a settled dispute amount out of the total: the held funds go to the settlement account.
+///
+/// The total only goes negative if the policy let a dispute drive the available funds negative.
+fn release_total(
+    account: &mut ClientAccount,
+    entry: &mut JournalEntry,
+    amount: Money,
+    negative_balance: NegativeBalancePolicy,
+) -> Result<(), EngineError> {
+    if negative_balance == NegativeBalancePolicy::Reject
+        && account.balance(amount.currency).total < SignedAmount::from(amount.amount)
+    {
+        return Err(EngineError::SystemError {
+            fault: SystemFault::NegativeTotal,
+            client_id: Some(entry.client_id),
+            transaction_id: Some(entry.transaction_id),
+        });
+    }
+    let client_id = account.client_id;
+    transfer(
+        account,
+        entry,
+        JournalAccount::Held(client_id),
+        JournalAccount::Settlement,
+        amount,
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Balance;
+    use crate::engine::clock::ManualClock;
+    use crate::engine::fees::{Fee, FeeRule, FeeSchedule};
+    use crate::engine::reconciliation::{Discrepancy, Funds};
+    use crate::event_store::EventStoreError;
+    use crate::ledger::LedgerError;
+    use crate::ledger::in_memory::InMemoryLedger;
+
+    mod deadlines;
+    mod fees;
+    mod risk;
+
+    /// Wraps the in-memory ledger and fails every write while `fail_writes` is set.
+    /// Used to inject a failure between the account staging and the ledger write.
+    #[derive(Debug, Default)]
+    struct FailingLedger {
+        inner: InMemoryLedger,
+        fail_writes: bool,
+    }
+
+    impl Ledger for FailingLedger {
+        async fn add(
+            &mut self,
+            account: &ClientAccount,
+            transaction: Transaction,
+            fee: Option<FeePosting>,
+            entry: JournalEntry,
+        ) -> Result<(), LedgerError> {
+            if self.fail_writes {
+                return Err(LedgerError::Storage("injected failure".to_string()));
+            }
+            self.inner.add(account, transaction, fee, entry).await
+        }
+
+        async fn compensate(
+            &mut self,
+            account: &ClientAccount,
+            compensation: Compensation,
+            fee: Option<FeePosting>,
+            entry: JournalEntry,
+        ) -> Result<(), LedgerError> {
+            if self.fail_writes {
+                return Err(LedgerError::Storage("injected failure".to_string()));
+            }
+            self.inner
+                .compensate(account, compensation, fee, entry)
+                .await
+        }
+
+        async fn update_account(&mut self, account: &ClientAccount) -> Result<(), LedgerError> {
+            if self.fail_writes {
+                return Err(LedgerError::Storage("injected failure".to_string()));
+            }
+            self.inner.update_account(account).await
+        }
+
+        async fn find(
+            &self,
+            client_id: ClientId,
+            transaction_id: TransactionId,
+        ) -> Result<Option<Transaction>, LedgerError> {
+            self.inner.find(client_id, transaction_id).await
+        }
+
+        async fn client_of(
+            &self,
+            transaction_id: TransactionId,
+        ) -> Result<Option<ClientId>, LedgerError> {
+            self.inner.client_of(transaction_id).await
+        }
+
+        async fn compensations(
+            &self,
+            client_id: ClientId,
+            transaction_id: TransactionId,
+        ) -> Result<Vec<Compensation>, LedgerError> {
+            self.inner.compensations(client_id, transaction_id).await
+        }
+
+        async fn accounts(&self) -> Result<Vec<ClientAccount>, LedgerError> {
+            self.inner.accounts().await
+        }
+
+        async fn transactions(&self) -> Result<Vec<Transaction>, LedgerError> {
+            self.inner.transactions().await
+        }
+
+        async fn disputed(&self) -> Result<Vec<Transaction>, LedgerError> {
+            self.inner.disputed().await
+        }
+
+        async fn fees(&self) -> Result<Vec<FeePosting>, LedgerError> {
+            self.inner.fees().await
+        }
+
+        async fn journal(&self) -> Result<Vec<JournalEntry>, LedgerError> {
+            self.inner.journal().await
+        }
+    }
+
+    /// Wraps the in-memory event store and fails every append while `fail_appends` is set.
+    #[derive(Debug, Default)]
+    struct FailingEventStore {
+        inner: InMemoryEventStore,
+        fail_appends: bool,
+    }
+
+    impl EventStore for FailingEventStore {
+        async fn append(
+            &mut self,
+            event: TimedEvent,
+            origin: EventOrigin,
+            rejection: Option<String>,
+        ) -> Result<u64, EventStoreError> {
+            if self.fail_appends {
+                return Err(EventStoreError::Storage("injected failure".to_string()));
+            }
+            self.inner.append(event, origin, rejection).await
+        }
+
+        async fn events(&self) -> Result<Vec<RecordedEvent>, EventStoreError> {
+            self.inner.events().await
+        }
+    }
+
+    fn client() -> ClientId {
+        ClientId::from(1)
+    }
+
+    fn deposit(tx: u32, minor: u32) -> Event {
+        Event::Deposit {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: Amount::from_minor(minor).into(),
+        }
+    }
+
+    fn withdraw(tx: u32, minor: u32) -> Event {
+        Event::Withdraw {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: Amount::from_minor(minor).into(),
+        }
+    }
+
+    /// The default policy with `change` applied, for all clients.
+    fn policies(change: impl FnOnce(&mut Policy)) -> Policies {
+        let mut policy = Policy::default();
+        change(&mut policy);
+        Policies::new(policy)
+    }
+
+    /// (available, total, is_locked) of the test client, if the account exists.
+    fn snapshot(engine: &Engine<FailingLedger>) -> Option<(Amount, Amount, bool)> {
+        engine.accounts.get(&client()).map(|a| {
+            let balance = a.balance(Currency::NONE);
+            let amount = |balance: SignedAmount| Amount::from(balance.as_decimal());
+            (
+                amount(balance.available),
+                amount(balance.total),
+                a.is_locked(),
+            )
+        })
+    }
+
+    async fn status(engine: &Engine<FailingLedger>, tx: u32) -> Option<TransactionStatus> {
+        engine
+            .ledger
+            .find(client(), TransactionId::from(tx))
+            .await
+            .unwrap()
+            .map(|t| t.status())
+    }
+
+    /// A withdrawal rejected for insufficient funds must not leave a transaction in the ledger.
+    #[tokio::test]
+    async fn insufficient_funds_withdraw_leaves_no_transaction() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 100)).await.unwrap();
+
+        let err = engine.apply(withdraw(2, 500)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::InsufficientFunds {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                requested: Amount::from_minor(500).into(),
+                available: Amount::from_minor(100).into(),
+            }
+        );
+        assert_eq!(status(&engine, 2).await, None);
+
+        // the transaction id is not blocked by the failed attempt
+        engine.apply(withdraw(2, 50)).await.unwrap();
+        assert_eq!(
+            snapshot(&engine),
+            Some((Amount::from_minor(50), Amount::from_minor(50), false))
+        );
+    }
+
+    /// Open the engine again on its ledger and event store, applying the last recorded event again.
+    async fn reopen(engine: Engine<FailingLedger>) -> (Engine<FailingLedger>, Option<Receipt>) {
+        let mut engine = Engine::with_event_store(engine.ledger, engine.events)
+            .await
+            .unwrap();
+        let recovered = engine.recover().await.unwrap();
+        (engine, recovered)
+    }
+
+    fn unapplied(tx: u32) -> EngineError {
+        EngineError::SystemError {
+            fault: SystemFault::Unapplied,
+            client_id: Some(client()),
+            transaction_id: Some(TransactionId::from(tx)),
+        }
+    }
+
+    /// A failed ledger write leaves the account untouched. The event was recorded right before it,
+    /// so the engine stops until it is opened again, which applies the event.
+    #[tokio::test]
+    async fn failed_ledger_add_leaves_account_untouched() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+
+        engine.ledger.fail_writes = true;
+        assert!(engine.apply(deposit(1, 100)).await.is_err());
+        assert_eq!(snapshot(&engine), None, "no account is created");
+        assert_eq!(engine.apply(deposit(2, 100)).await, Err(unapplied(2)));
+
+        engine.ledger.fail_writes = false;
+        let (engine, recovered) = reopen(engine).await;
+        assert_eq!(recovered.map(|receipt| receipt.applied), Some(Applied::New));
+        let before = snapshot(&engine);
+        assert_eq!(
+            before,
+            Some((Amount::from_minor(100), Amount::from_minor(100), false))
+        );
+        // the last event is already in the ledger
+        let (mut engine, recovered) = reopen(engine).await;
+        assert_eq!(
+            recovered.map(|receipt| receipt.applied),
+            Some(Applied::AlreadyApplied)
+        );
+
+        engine.ledger.fail_writes = true;
+        assert!(engine.apply(withdraw(2, 50)).await.is_err());
+        assert_eq!(snapshot(&engine), before);
+    }
+
+    #[tokio::test]
+    async fn failed_ledger_update_leaves_account_untouched() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 100)).await.unwrap();
+        engine.apply(deposit(2, 100)).await.unwrap();
+
+        let dispute = |tx: u32| Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        };
+        let resolve = |tx: u32| Event::Resolve {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        };
+        let chargeback = |tx: u32| Event::Chargeback {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        };
+
+        // dispute
+        let before = snapshot(&engine);
+        engine.ledger.fail_writes = true;
+        assert!(engine.apply(dispute(1)).await.is_err());
+        assert_eq!(snapshot(&engine), before);
+        assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Settled));
+        assert_eq!(engine.apply(dispute(2)).await, Err(unapplied(2)));
+
+        engine.ledger.fail_writes = false;
+        let (mut engine, _) = reopen(engine).await;
+        assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Disputed));
+        engine.apply(dispute(2)).await.unwrap();
+
+        // resolve
+        let before = snapshot(&engine);
+        engine.ledger.fail_writes = true;
+        assert!(engine.apply(resolve(1)).await.is_err());
+        assert_eq!(snapshot(&engine), before);
+        assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Disputed));
+
+        engine.ledger.fail_writes = false;
+        let (mut engine, _) = reopen(engine).await;
+        assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Resolved));
+
+        // chargeback
+        let before = snapshot(&engine);
+        engine.ledger.fail_writes = true;
+        assert!(engine.apply(chargeback(2)).await.is_err());
+        assert_eq!(snapshot(&engine), before, "account is not locked");
+        assert_eq!(status(&engine, 2).await, Some(TransactionStatus::Disputed));
+
+        engine.ledger.fail_writes = false;
+        let (engine, _) = reopen(engine).await;
+        assert_eq!(
+            snapshot(&engine),
+            Some((Amount::from_minor(100), Amount::from_minor(100), true))
+        );
+    }
+
+    /// Every event is recorded with a sequence number, rejected ones with the reason.
+    #[tokio::test]
+    async fn events_are_recorded() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 100)).await.unwrap();
+        engine.apply(withdraw(2, 500)).await.unwrap_err();
+
+        let events = engine.event_store().events().await.unwrap();
+        assert_eq!(
+            events,
+            vec![
+                RecordedEvent {
+                    sequence: 1,
+                    event: deposit(1, 100),
+                    timestamp: None,
+                    origin: EventOrigin::Partner,
+                    rejection: None,
+                },
+                RecordedEvent {
+                    sequence: 2,
+                    event: withdraw(2, 500),
+                    timestamp: None,
+                    origin: EventOrigin::Partner,
+                    rejection: Some(
+                        "Insufficient funds: requested 5.0000, available 1.0000".to_string()
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rebuild_from_events() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 100)).await.unwrap();
+        engine.apply(deposit(2, 300)).await.unwrap();
+        engine.apply(withdraw(3, 500)).await.unwrap_err();
+        engine.apply(withdraw(4, 50)).await.unwrap();
+        engine
+            .apply(Event::Dispute {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: None,
+            })
+            .await
+            .unwrap();
+
+        let events = engine.event_store().events().await.unwrap();
+        let rebuild = engine
+            .rebuild_from(
+                events.clone(),
+                InMemoryLedger::new(),
+                InMemoryEventStore::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rebuild.mismatches, vec![]);
+        assert_eq!(
+            rebuild
+                .engine
+                .ledger
+                .find(client(), TransactionId::from(2))
+                .await
+                .unwrap()
+                .map(|t| t.status()),
+            Some(TransactionStatus::Disputed)
+        );
+
+        // drift in the current state is reported
+        engine
+            .accounts
+            .get_mut(&client())
+            .unwrap()
+            .balance_mut(Currency::NONE)
+            .available = Amount::from_minor(1).into();
+        let rebuild = engine
+            .rebuild_from(events, InMemoryLedger::new(), InMemoryEventStore::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            rebuild.mismatches,
+            vec![AccountMismatch {
+                client_id: client(),
+                current: engine.accounts.get(&client()).cloned(),
+                rebuilt: rebuild.engine.accounts.get(&client()).cloned(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn receipts() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        let balances = |available, held, total| Balances {
+            available: Amount::from_minor(available).into(),
+            held: Amount::from_minor(held).into(),
+            total: Amount::from_minor(total).into(),
+            deficit: Amount::default(),
+        };
+        let dispute = Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            amount: None,
+        };
+
+        let receipt = engine.apply(deposit(1, 300)).await.unwrap();
+        assert_eq!(
+            receipt,
+            Receipt {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                applied: Applied::New,
+                currency: Currency::NONE,
+                before: Balances::default(),
+                after: balances(300, 0, 300),
+                status: Some(TransactionStatus::Settled),
+                locked: false,
+                out_of_order: false,
+                fee: None,
+            }
+        );
+
+        let receipt = engine.apply(dispute).await.unwrap();
+        assert_eq!(receipt.before, balances(300, 0, 300));
+        assert_eq!(receipt.after, balances(0, 300, 300));
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+
+        let receipt = engine.apply(dispute).await.unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+        assert_eq!(receipt.before, receipt.after);
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+
+        let receipt = engine
+            .apply(Event::Chargeback {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(receipt.after, balances(0, 0, 0));
+        assert_eq!(receipt.status, Some(TransactionStatus::ChargedBack));
+        assert!(receipt.locked);
+    }
+
+    /// Each currency has its own balance, disputes act on the currency of the disputed deposit.
+    #[tokio::test]
+    async fn balances_per_currency() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        let eur = Currency::new("EUR").unwrap();
+        let jpy = Currency::new("JPY").unwrap();
+        let deposit = |tx: u32, value: i64, currency| Event::Deposit {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: Money::new(rust_decimal::Decimal::new(value, 2), currency),
+        };
+
+        engine.apply(deposit(1, 1050, eur)).await.unwrap();
+        engine.apply(deposit(2, 50050, jpy)).await.unwrap(); // 500.50 JPY is rounded to 500
+        let receipt = engine
+            .apply(Event::Dispute {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(receipt.currency, jpy);
+
+        let account = engine.account(client()).unwrap();
+        let balance = |available: i64, total: i64, scale| Balance {
+            available: rust_decimal::Decimal::new(available, scale).into(),
+            total: rust_decimal::Decimal::new(total, scale).into(),
+        };
+        assert_eq!(account.balance(eur), balance(1050, 1050, 2));
+        assert_eq!(account.balance(jpy), balance(0, 500, 0));
+
+        // the EUR balance is not enough for a JPY withdrawal
+        let err = engine
+            .apply(Event::Withdraw {
+                client_id: client(),
+                transaction_id: TransactionId::from(3),
+                amount: Money::new(rust_decimal::Decimal::from(1), jpy),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "insufficient_funds");
+
+        // a chargeback locks the client in all currencies
+        engine
+            .apply(Event::Chargeback {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: None,
+            })
+            .await
+            .unwrap();
+        let err = engine.apply(deposit(4, 100, eur)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::AccountLocked {
+                client_id: client(),
+                reason: ReasonCode::Chargeback,
+            }
+        );
+    }
+
+    /// Withdrawals can be disputed: the withdrawn amount is held, then either dropped (resolved)
+    /// or credited back (reversed).
+    #[tokio::test]
+    async fn outbound_disputes() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        let event = |ty: fn(ClientId, TransactionId) -> Event, tx: u32| {
+            ty(client(), TransactionId::from(tx))
+        };
+        let dispute = |client_id, transaction_id| Event::Dispute {
+            client_id,
+            transaction_id,
+            amount: None,
+        };
+        let resolve = |client_id, transaction_id| Event::Resolve {
+            client_id,
+            transaction_id,
+            amount: None,
+        };
+        let chargeback = |client_id, transaction_id| Event::Chargeback {
+            client_id,
+            transaction_id,
+            amount: None,
+        };
+        let amounts = |available, total| {
+            Some((
+                Amount::from_minor(available),
+                Amount::from_minor(total),
+                false,
+            ))
+        };
+
+        engine.apply(deposit(1, 500)).await.unwrap();
+        engine.apply(withdraw(2, 200)).await.unwrap();
+        engine.apply(withdraw(3, 100)).await.unwrap();
+        assert_eq!(snapshot(&engine), amounts(200, 200));
+
+        // a withdrawal must be disputed before it is resolved or reversed
+        let err = engine.apply(event(chargeback, 2)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::InvalidTransactionStatus {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                current: TransactionStatus::Settled,
+                desired: TransactionStatus::Reversed,
+            }
+        );
+
+        // Settled -> Disputed: the claimed amount is held
+        let receipt = engine.apply(event(dispute, 2)).await.unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+        assert_eq!(snapshot(&engine), amounts(200, 400));
+
+        // Disputed -> Reversed: the withdrawal is credited back, the account stays unlocked
+        let receipt = engine.apply(event(chargeback, 2)).await.unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Reversed));
+        assert!(!receipt.locked);
+        assert_eq!(snapshot(&engine), amounts(400, 400));
+        let receipt = engine.apply(event(chargeback, 2)).await.unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        // Settled -> Disputed -> Resolved: the withdrawal stands
+        engine.apply(event(dispute, 3)).await.unwrap();
+        assert_eq!(snapshot(&engine), amounts(400, 500));
+        let receipt = engine.apply(event(resolve, 3)).await.unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Resolved));
+        assert_eq!(snapshot(&engine), amounts(400, 400));
+
+        // a resolved withdrawal can't be reversed
+        let err = engine.apply(event(chargeback, 3)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::InvalidTransactionStatus {
+                client_id: client(),
+                transaction_id: TransactionId::from(3),
+                current: TransactionStatus::Resolved,
+                desired: TransactionStatus::Reversed,
+            }
+        );
+    }
+
+    /// Partial disputes hold, release or charge back only their amount,
+    /// the undisputed rest of a deposit can't be over-disputed.
+    #[tokio::test]
+    async fn partial_disputes() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        let transaction_id = TransactionId::from(1);
+        let amount = |minor| Some(Amount::from_minor(minor));
+        let dispute = |amount| Event::Dispute {
+            client_id: client(),
+            transaction_id,
+            amount,
+        };
+        let resolve = |amount| Event::Resolve {
+            client_id: client(),
+            transaction_id,
+            amount,
+        };
+        let chargeback = |amount| Event::Chargeback {
+            client_id: client(),
+            transaction_id,
+            amount,
+        };
+        let amounts = |available, total, locked| {
+            Some((
+                Amount::from_minor(available),
+                Amount::from_minor(total),
+                locked,
+            ))
+        };
+        let money = |minor| Money::from(Amount::from_minor(minor));
+
+        engine.apply(deposit(1, 1000)).await.unwrap();
+
+        // two partial disputes are open at the same time
+        engine
+            .apply(dispute(amount(300)).at(Timestamp::from(10)))
+            .await
+            .unwrap();
+        let receipt = engine
+            .apply(dispute(amount(200)).at(Timestamp::from(20)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+        assert_eq!(snapshot(&engine), amounts(500, 1000, false));
+
+        let err = engine
+            .apply(dispute(amount(600)).at(Timestamp::from(30)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::OverDispute {
+                client_id: client(),
+                transaction_id,
+                requested: money(600),
+                undisputed: money(500),
+            }
+        );
+
+        // the transaction stays disputed while a part is held
+        let receipt = engine
+            .apply(resolve(amount(300)).at(Timestamp::from(40)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+        assert_eq!(snapshot(&engine), amounts(800, 1000, false));
+
+        let err = engine
+            .apply(chargeback(amount(300)).at(Timestamp::from(50)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::ExceedsHeldAmount {
+                client_id: client(),
+                transaction_id,
+                requested: money(300),
+                held: money(200),
+            }
+        );
+
+        let receipt = engine
+            .apply(resolve(None).at(Timestamp::from(60)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Resolved));
+        assert_eq!(snapshot(&engine), amounts(1000, 1000, false));
+
+        // a dispute without amount disputes the undisputed rest of a resolved deposit
+        engine
+            .apply(dispute(None).at(Timestamp::from(70)))
+            .await
+            .unwrap();
+        assert_eq!(snapshot(&engine), amounts(500, 1000, false));
+        let receipt = engine
+            .apply(dispute(None).at(Timestamp::from(80)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        let receipt = engine
+            .apply(chargeback(amount(100)).at(Timestamp::from(90)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::Disputed));
+        assert_eq!(snapshot(&engine), amounts(500, 900, true));
+
+        let transaction = engine.transaction(client(), transaction_id).await.unwrap();
+        assert_eq!(
+            transaction.unwrap().info().disputes,
+            crate::ledger::transactions::Disputes {
+                held: Amount::from_minor(400),
+                resolved: Amount::from_minor(500),
+                charged_back: Amount::from_minor(100),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_amounts_must_be_positive() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 1000)).await.unwrap();
+
+        let err = engine
+            .apply(Event::Dispute {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: Some(Amount::default()),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "non_positive_amount");
+
+        // rounded to the minor units of the deposit first: nothing is left of 0.4 JPY
+        let jpy = Currency::new("JPY").unwrap();
+        engine
+            .apply(Event::Deposit {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: Money::new(Decimal::from(1_000), jpy),
+            })
+            .await
+            .unwrap();
+        let err = engine
+            .apply(
+                Event::Dispute {
+                    client_id: client(),
+                    transaction_id: TransactionId::from(2),
+                    amount: Some(Amount::from(Decimal::new(4, 1))),
+                }
+                .at(Timestamp::from(10)),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::NonPositiveAmount {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: Money::new(Decimal::ZERO, jpy),
+            }
+        );
+        assert_eq!(
+            engine.accounts[&client()].balance(jpy).held(),
+            SignedAmount::default()
+        );
+    }
+
+    /// A partial event is a replay if its entry (same amount once rounded, same timestamp) is on the chain,
+    /// it is rejected without timestamp.
+    #[tokio::test]
+    async fn partial_replays() {
+        let usd = Currency::new("USD").unwrap();
+        let mut engine = Engine::new(InMemoryLedger::new()).await.unwrap();
+        engine
+            .apply(Event::Deposit {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: Money::new(Decimal::new(1_000, 2), usd),
+            })
+            .await
+            .unwrap();
+        let dispute = |amount| Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            amount: Some(Amount::from(amount)),
+        };
+
+        let receipt = engine
+            .apply(dispute(Decimal::new(300, 2)).at(Timestamp::from(10)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+        let receipt = engine
+            .apply(dispute(Decimal::new(300, 2)).at(Timestamp::from(10)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+        let receipt = engine
+            .apply(dispute(Decimal::new(300, 2)).at(Timestamp::from(20)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+
+        // stored as 3.00 USD, a resend is a replay
+        let receipt = engine
+            .apply(dispute(Decimal::new(3_001, 3)).at(Timestamp::from(30)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+        let receipt = engine
+            .apply(dispute(Decimal::new(3_001, 3)).at(Timestamp::from(30)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        let err = engine
+            .apply(dispute(Decimal::new(100, 2)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::PartialWithoutTimestamp {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+            }
+        );
+        assert_eq!(err.code(), "partial_without_timestamp");
+
+        let balance = engine.accounts[&client()].balance(usd);
+        assert_eq!(balance.available, SignedAmount::from(Decimal::new(100, 2)));
+    }
+
+    /// Partners can have their own transitions, other clients keep the built-in rules.
+    #[tokio::test]
+    async fn policies_per_partner() {
+        use crate::engine::policy::TransitionPolicy;
+
+        let partner = ClientId::from(2);
+        let lenient = TransitionPolicy::default()
+            .allow(
+                Direction::Inbound,
+                TransactionStatus::Settled,
+                TransactionStatus::ChargedBack,
+            )
+            .with_redispute_resolved(true);
+        let policies = Policies::default().with_partner(
+            [partner],
+            Policy {
+                transitions: lenient,
+                ..Policy::default()
+            },
+        );
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies);
+
+        let event = |client_id, tx: u32, ty: &str| {
+            let transaction_id = TransactionId::from(tx);
+            match ty {
+                "deposit" => Event::Deposit {
+                    client_id,
+                    transaction_id,
+                    amount: Amount::from_minor(500).into(),
+                },
+                "dispute" => Event::Dispute {
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                },
+                "resolve" => Event::Resolve {
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                },
+                _ => Event::Chargeback {
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                },
+            }
+        };
+
+        // built-in rules: a chargeback needs a dispute, a resolved deposit can't be disputed again
+        engine.apply(event(client(), 1, "deposit")).await.unwrap();
+        let err = engine
+            .apply(event(client(), 1, "chargeback"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "invalid_transaction_status");
+        engine.apply(event(client(), 1, "dispute")).await.unwrap();
+        engine.apply(event(client(), 1, "resolve")).await.unwrap();
+        let receipt = engine.apply(event(client(), 1, "dispute")).await.unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        // the partner re-disputes a resolved deposit
+        engine.apply(event(partner, 2, "deposit")).await.unwrap();
+        engine.apply(event(partner, 2, "dispute")).await.unwrap();
+        engine.apply(event(partner, 2, "resolve")).await.unwrap();
+        let receipt = engine.apply(event(partner, 2, "dispute")).await.unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+        assert_eq!(receipt.after.held, Amount::from_minor(500).into());
+
+        // and charges back straight from settled
+        engine.apply(event(partner, 3, "deposit")).await.unwrap();
+        let receipt = engine.apply(event(partner, 3, "chargeback")).await.unwrap();
+        assert_eq!(receipt.status, Some(TransactionStatus::ChargedBack));
+        assert_eq!(receipt.before.total, Amount::from_minor(1000).into());
+        assert_eq!(receipt.after.available, Amount::default().into());
+        assert_eq!(receipt.after.total, Amount::from_minor(500).into());
+        assert!(receipt.locked);
+    }
+
+    /// A policy without outbound transitions doesn't allow disputes of withdrawals.
+    #[tokio::test]
+    async fn policy_without_outbound_disputes() {
+        use crate::engine::policy::TransitionPolicy;
+        use crate::ledger::transactions::Direction;
+
+        let mut transitions = TransitionPolicy::empty();
+        for transition in TransitionPolicy::default().transitions() {
+            if transition.direction == Direction::Inbound {
+                transitions =
+                    transitions.allow(transition.direction, transition.from, transition.to);
+            }
+        }
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies(|policy| policy.transitions = transitions));
+
+        engine.apply(deposit(1, 500)).await.unwrap();
+        engine.apply(withdraw(2, 200)).await.unwrap();
+        let err = engine
+            .apply(Event::Dispute {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                amount: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::InvalidAssociatedTransaction {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                direction: Direction::Outbound,
+            }
+        );
+    }
+
+    /// Disputes may drive the balance negative if the policy allows it, the deficit is reported.
+    #[tokio::test]
+    async fn negative_balances() {
+        let dispute = Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            amount: None,
+        };
+        let chargeback = Event::Chargeback {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            amount: None,
+        };
+
+        // strict by default
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 1000)).await.unwrap();
+        engine.apply(withdraw(2, 800)).await.unwrap();
+        let err = engine.apply(dispute).await.unwrap_err();
+        assert_eq!(err.code(), "insufficient_funds");
+
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies(|policy| {
+                policy.negative_balance = NegativeBalancePolicy::Allow
+            }));
+        engine.apply(deposit(1, 1000)).await.unwrap();
+        engine.apply(withdraw(2, 800)).await.unwrap();
+
+        let receipt = engine.apply(dispute).await.unwrap();
+        let signed = |minor: i64| SignedAmount::from(rust_decimal::Decimal::new(minor, 2));
+        assert_eq!(
+            receipt.after,
+            Balances {
+                available: signed(-800),
+                held: signed(1000),
+                total: signed(200),
+                deficit: Amount::from_minor(800),
+            }
+        );
+
+        // deposits pay the deficit off, withdrawals still need available funds
+        engine.apply(deposit(3, 300)).await.unwrap();
+        let err = engine.apply(withdraw(4, 100)).await.unwrap_err();
+        assert_eq!(err.code(), "insufficient_funds");
+
+        let receipt = engine.apply(chargeback).await.unwrap();
+        assert_eq!(
+            receipt.after,
+            Balances {
+                available: signed(-500),
+                held: SignedAmount::default(),
+                total: signed(-500),
+                deficit: Amount::from_minor(500),
+            }
+        );
+        assert!(receipt.locked);
+    }
+
+    /// Admin events freeze, unlock and close accounts, the reason is recorded on the account.
+    #[tokio::test]
+    async fn account_events() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        let admin = |tx: u32| (client(), TransactionId::from(tx));
+        let freeze = |(client_id, transaction_id), reason| Event::Freeze {
+            client_id,
+            transaction_id,
+            reason,
+        };
+        let unlock = |(client_id, transaction_id), reason| Event::Unlock {
+            client_id,
+            transaction_id,
+            reason,
+        };
+        let close = |(client_id, transaction_id), reason| Event::Close {
+            client_id,
+            transaction_id,
+            reason,
+        };
+
+        let err = engine
+            .apply(unlock(admin(10), ReasonCode::Cleared))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::AccountNotFound {
+                client_id: client()
+            }
+        );
+
+        // a freeze blocks any activity, with its reason
+        engine.apply(deposit(1, 500)).await.unwrap();
+        let receipt = engine
+            .apply(freeze(admin(11), ReasonCode::Compliance))
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, None);
+        assert!(receipt.locked);
+        let err = engine.apply(withdraw(2, 100)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::AccountLocked {
+                client_id: client(),
+                reason: ReasonCode::Compliance,
+            }
+        );
+        let receipt = engine
+            .apply(freeze(admin(11), ReasonCode::Compliance))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        // unlocked, the account accepts activity again
+        engine
+            .apply(unlock(admin(12), ReasonCode::Cleared))
+            .await
+            .unwrap();
+        let account = engine.account(client()).unwrap();
+        assert_eq!(
+            (account.status, account.reason),
+            (AccountStatus::Open, Some(ReasonCode::Cleared))
+        );
+        engine.apply(withdraw(2, 100)).await.unwrap();
+
+        // closed only without balance, for good
+        let err = engine
+            .apply(close(admin(13), ReasonCode::ClientRequest))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::NonZeroBalance {
+                client_id: client(),
+                balance: Amount::from_minor(400).into(),
+            }
+        );
+        engine.apply(withdraw(3, 400)).await.unwrap();
+        engine
+            .apply(close(admin(13), ReasonCode::ClientRequest))
+            .await
+            .unwrap();
+        let err = engine
+            .apply(unlock(admin(14), ReasonCode::Cleared))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::AccountClosed {
+                client_id: client(),
+                reason: ReasonCode::ClientRequest,
+            }
+        );
+        assert_eq!(err.code(), "account_closed");
+    }
+
+    /// A freeze before the first transaction of the client opens its account locked.
+    #[tokio::test]
+    async fn freeze_without_account() {
+        let mut engine = Engine::new(InMemoryLedger::new()).await.unwrap();
+        let freeze = Event::Freeze {
+            client_id: client(),
+            transaction_id: TransactionId::from(10),
+            reason: ReasonCode::Compliance,
+        };
+
+        let receipt = engine.apply(freeze).await.unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+        assert!(receipt.locked);
+        let err = engine.apply(deposit(1, 500)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::AccountLocked {
+                client_id: client(),
+                reason: ReasonCode::Compliance,
+            }
+        );
+        assert_eq!(
+            engine.ledger.accounts().await.unwrap(),
+            vec![engine.account(client()).unwrap().clone()]
+        );
+    }
+
+    /// An admin can lift the lock of a chargeback.
+    #[tokio::test]
+    async fn unlock_after_chargeback() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine.apply(deposit(1, 500)).await.unwrap();
+        engine.apply(deposit(2, 200)).await.unwrap();
+        for event in [
+            Event::Dispute {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            },
+            Event::Chargeback {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            },
+        ] {
+            engine.apply(event).await.unwrap();
+        }
+        let account = engine.account(client()).unwrap();
+        assert_eq!(
+            (account.status, account.reason),
+            (AccountStatus::Locked, Some(ReasonCode::Chargeback))
+        );
+
+        engine
+            .apply(Event::Unlock {
+                client_id: client(),
+                transaction_id: TransactionId::from(3),
+                reason: ReasonCode::Cleared,
+            })
+            .await
+            .unwrap();
+        engine.apply(withdraw(4, 200)).await.unwrap();
+        assert_eq!(
+            snapshot(&engine),
+            Some((Amount::default(), Amount::default(), false))
+        );
+    }
+
+    async fn timestamp(engine: &Engine<FailingLedger>, tx: u32) -> Option<Timestamp> {
+        let transaction = engine.transaction(client(), TransactionId::from(tx)).await;
+        transaction
+            .unwrap()
+            .and_then(|transaction| transaction.info().timestamp)
+    }
+
+    /// Transactions take the timestamp of their event, the time of the engine clock isn't stored with them.
+    #[tokio::test]
+    async fn timestamps() {
+        let clock = ManualClock::new(Timestamp::from(1_000));
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_clock(clock.clone());
+
+        engine.apply(deposit(1, 100)).await.unwrap();
+        assert_eq!(timestamp(&engine, 1).await, None);
+        assert_eq!(engine.account(client()).unwrap().last_timestamp, None);
+
+        clock.advance(500);
+        engine.apply(dispute(1)).await.unwrap();
+        let disputed = engine.transaction(client(), TransactionId::from(1)).await;
+        assert_eq!(disputed.unwrap().unwrap().info().disputed_at, None);
+        assert_eq!(
+            engine
+                .compensations(client(), TransactionId::from(1))
+                .await
+                .unwrap()[0]
+                .timestamp,
+            None
+        );
+
+        engine
+            .apply(deposit(3, 100).at(Timestamp::from(200)))
+            .await
+            .unwrap();
+        assert_eq!(timestamp(&engine, 3).await, Some(Timestamp::from(200)));
+        assert_eq!(
+            engine.account(client()).unwrap().last_timestamp,
+            Some(Timestamp::from(200))
+        );
+    }
+
+    /// An event older than the latest event of its client is rejected, or flagged if the policy says so.
+    #[tokio::test]
+    async fn timestamps_out_of_order() {
+        let mut engine = Engine::new(FailingLedger::default()).await.unwrap();
+        engine
+            .apply(deposit(1, 100).at(Timestamp::from(2_000)))
+            .await
+            .unwrap();
+        engine
+            .apply(deposit(2, 100).at(Timestamp::from(3_000)))
+            .await
+            .unwrap();
+
+        let err = engine
+            .apply(withdraw(3, 100).at(Timestamp::from(2_500)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::TimestampOutOfOrder {
+                client_id: client(),
+                timestamp: Timestamp::from(2_500),
+                latest: Timestamp::from(3_000),
+            }
+        );
+        assert_eq!(err.code(), "timestamp_out_of_order");
+        // a replay is still recognized as such, whatever its timestamp
+        let receipt = engine
+            .apply(deposit(1, 100).at(Timestamp::from(2_000)))
+            .await
+            .unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+        assert!(!receipt.out_of_order);
+        // events without timestamp aren't checked
+        engine.apply(withdraw(4, 50)).await.unwrap();
+
+        let mut engine =
+            engine.with_policies(policies(|policy| policy.timestamps = TimestampPolicy::Flag));
+        let receipt = engine
+            .apply(withdraw(3, 100).at(Timestamp::from(2_500)))
+            .await
+            .unwrap();
+        assert!(receipt.out_of_order);
+        assert_eq!(
+            engine.account(client()).unwrap().last_timestamp,
+            Some(Timestamp::from(3_000))
+        );
+        assert_eq!(
+            snapshot(&engine),
+            Some((Amount::from_minor(50), Amount::from_minor(50), false))
+        );
+    }
+
+    fn dispute(tx: u32) -> Event {
+        Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        }
+    }
+
+    /// An event that can't be recorded is a system error and isn't applied, the engine refuses anything further.
+    #[tokio::test]
+    async fn unrecorded_event_stops_engine() {
+        let mut engine =
+            Engine::with_event_store(FailingLedger::default(), FailingEventStore::default())
+                .await
+                .unwrap();
+        engine.apply(deposit(1, 100)).await.unwrap();
+
+        engine.events.fail_appends = true;
+        let err = engine.apply(deposit(2, 100)).await.unwrap_err();
+        assert_eq!(
+            err,
+            EngineError::StorageError("injected failure".to_string())
+        );
+        assert!(!err.is_partner_error());
+
+        engine.events.fail_appends = false;
+        let err = engine.apply(deposit(3, 100)).await.unwrap_err();
+        assert!(!err.is_partner_error());
+        assert_eq!(engine.events.events().await.unwrap().len(), 1);
+        let deposit = engine
+            .ledger
+            .find(client(), TransactionId::from(2))
+            .await
+            .unwrap();
+        assert_eq!(deposit, None);
+    }
+
+    /// Every write posts a balanced journal entry, the balances of the clients and the house account
+    /// can be derived from the journal.
+    #[tokio::test]
+    async fn journal_balances() {
+        let schedule = FeeSchedule::new().with_rule(FeeRule {
+            event: FeeEvent::Withdrawal,
+            tier: None,
+            currency: None,
+            fee: Fee {
+                fixed: Amount::default(),
+                percent: Decimal::ONE,
+            },
+        });
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies(|policy| policy.fees = schedule));
+        let resolve = |tx, amount| Event::Resolve {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount,
+        };
+        let chargeback = |tx| Event::Chargeback {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        };
+
+        engine.apply(deposit(1, 1_000)).await.unwrap();
+        engine.apply(deposit(2, 500)).await.unwrap();
+        engine.apply(withdraw(3, 200)).await.unwrap();
+        engine.apply(dispute(3)).await.unwrap();
+        engine.apply(chargeback(3)).await.unwrap();
+        engine.apply(dispute(2)).await.unwrap();
+        engine
+            .apply(resolve(2, Some(Amount::from_minor(100))).at(Timestamp::from(1_000)))
+            .await
+            .unwrap();
+        engine.apply(dispute(1)).await.unwrap();
+        engine.apply(chargeback(1)).await.unwrap();
+        // a replay posts nothing
+        engine.apply(deposit(1, 1_000)).await.unwrap();
+
+        let journal = engine.ledger.journal().await.unwrap();
+        assert_eq!(
+            journal.iter().map(|entry| entry.kind).collect::<Vec<_>>(),
+            [
+                EntryKind::Deposit,
+                EntryKind::Deposit,
+                EntryKind::Withdrawal,
+                EntryKind::Dispute,
+                EntryKind::Chargeback,
+                EntryKind::Dispute,
+                EntryKind::Resolve,
+                EntryKind::Dispute,
+                EntryKind::Chargeback,
+            ]
+        );
+        assert!(journal.iter().all(JournalEntry::is_balanced));
+
+        let trial = engine.trial_balance().await.unwrap();
+        assert!(trial.is_balanced());
+        let account = engine.account(client()).unwrap();
+        assert_eq!(
+            trial.balance(client(), Currency::NONE),
+            account.balance(Currency::NONE)
+        );
+        assert_eq!(
+            account.balance(Currency::NONE),
+            Balance {
+                available: Amount::from_minor(98).into(),
+                total: Amount::from_minor(498).into(),
+            }
+        );
+        assert_eq!(
+            trial
+                .totals(JournalAccount::House, Currency::NONE)
+                .balance(),
+            engine.revenue()[&Currency::NONE].into()
+        );
+        // the funds that came in: 15.00 deposited, 10.00 charged back, 2.00 withdrawn and reversed
+        assert_eq!(
+            trial
+                .totals(JournalAccount::Settlement, Currency::NONE)
+                .balance(),
+            Decimal::new(-500, 2).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_balances() {
+        let schedule = FeeSchedule::new().with_rule(FeeRule {
+            event: FeeEvent::Withdrawal,
+            tier: None,
+            currency: None,
+            fee: Fee {
+                fixed: Amount::from_minor(10),
+                percent: Decimal::ZERO,
+            },
+        });
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies(|policy| policy.fees = schedule));
+        let partial = |tx, minor| Event::Dispute {
+            client_id: client(),
+            transaction_id: TransactionId::from(tx),
+            amount: Some(Amount::from_minor(minor)),
+        };
+
+        engine.apply(deposit(1, 1_000)).await.unwrap();
+        engine.apply(withdraw(2, 200)).await.unwrap();
+        engine.apply(dispute(2)).await.unwrap();
+        engine
+            .apply(partial(1, 300).at(Timestamp::from(1_000)))
+            .await
+            .unwrap();
+        engine
+            .apply(Event::Deposit {
+                client_id: ClientId::from(2),
+                transaction_id: TransactionId::from(3),
+                amount: Money::new(Decimal::new(500, 2), Currency::new("EUR").unwrap()),
+            })
+            .await
+            .unwrap();
+
+        let reconciliation = engine.reconcile().await.unwrap();
+        assert!(reconciliation.is_clean());
+        assert_eq!(reconciliation.balances, 2);
+
+        // a balance drifting from the ledger, held above the total
+        let mut account = engine.account(client()).unwrap().clone();
+        let expected = Funds::of(&account.balance(Currency::NONE));
+        account.balance_mut(Currency::NONE).available = Amount::from_minor(1_000).into();
+        let mut ledger = engine.ledger;
+        ledger.inner.put_account(account);
+        let engine = Engine::new(ledger).await.unwrap();
+
+        assert_eq!(
+            engine.reconcile().await.unwrap().discrepancies,
+            vec![Discrepancy {
+                client_id: client(),
+                currency: Currency::NONE,
+                expected,
+                actual: Funds {
+                    available: Amount::from_minor(1_000).into(),
+                    held: Decimal::new(-10, 2).into(),
+                    total: expected.total,
+                },
+            }]
+        );
+        assert_eq!(
+            expected,
+            Funds {
+                available: Amount::from_minor(490).into(),
+                held: Amount::from_minor(500).into(),
+                total: Amount::from_minor(990).into(),
+            }
+        );
+    }
+
+    /// Disputes, resolves and chargebacks append compensating entries, the status is derived from the chain.
+    #[tokio::test]
+    async fn compensating_entries() {
+        use crate::engine::policy::TransitionPolicy;
+
+        let transitions = TransitionPolicy::default().allow(
+            Direction::Inbound,
+            TransactionStatus::Settled,
+            TransactionStatus::ChargedBack,
+        );
+        let mut engine = Engine::new(FailingLedger::default())
+            .await
+            .unwrap()
+            .with_policies(policies(|policy| policy.transitions = transitions))
+            .with_clock(ManualClock::new(Timestamp::from(1_000)));
+        let partial = |event: fn(ClientId, TransactionId, Option<Amount>) -> Event, minor| {
+            event(
+                client(),
+                TransactionId::from(1),
+                Some(Amount::from_minor(minor)),
+            )
+            .at(Timestamp::from(2_000))
+        };
+        let dispute = |client_id, transaction_id, amount| Event::Dispute {
+            client_id,
+            transaction_id,
+            amount,
+        };
+        let resolve = |client_id, transaction_id, amount| Event::Resolve {
+            client_id,
+            transaction_id,
+            amount,
+        };
+        let chargeback = |client_id, transaction_id, amount| Event::Chargeback {
+            client_id,
+            transaction_id,
+            amount,
+        };
+
+        engine.apply(deposit(1, 1_000)).await.unwrap();
+        let other = ClientId::from(2);
+        engine
+            .apply(Event::Deposit {
+                client_id: other,
+                transaction_id: TransactionId::from(2),
+                amount: Amount::from_minor(500).into(),
+            })
+            .await
+            .unwrap();
+        engine.apply(partial(dispute, 400)).await.unwrap();
+        engine.apply(partial(resolve, 400)).await.unwrap();
+        engine.apply(partial(dispute, 600)).await.unwrap();
+        engine.apply(partial(chargeback, 600)).await.unwrap();
+        // charged back straight from settled, without a dispute, on another client as a chargeback locks
+        engine
+            .apply(chargeback(other, TransactionId::from(2), None))
+            .await
+            .unwrap();
+
+        let entry = |kind, minor| Compensation {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            kind,
+            amount: Amount::from_minor(minor).into(),
+            timestamp: Some(Timestamp::from(2_000)),
+        };
+        assert_eq!(
+            engine
+                .compensations(client(), TransactionId::from(1))
+                .await
+                .unwrap(),
+            vec![
+                entry(CompensationKind::Hold, 400),
+                entry(CompensationKind::Release, 400),
+                entry(CompensationKind::Hold, 600),
+                entry(CompensationKind::Reversal, 600),
+            ]
+        );
+        assert_eq!(
+            engine
+                .compensations(other, TransactionId::from(2))
+                .await
+                .unwrap(),
+            vec![Compensation {
+                client_id: other,
+                transaction_id: TransactionId::from(2),
+                timestamp: None,
+                ..entry(CompensationKind::Reversal, 500)
+            }]
+        );
+
+        // the original transactions with their chains lead to the stored status
+        for (client_id, tx, minor) in [(client(), 1, 1_000), (other, 2, 500)] {
+            let transaction_id = TransactionId::from(tx);
+            let mut derived = Transaction::new_settled_inbound(
+                transaction_id,
+                client_id,
+                Amount::from_minor(minor).into(),
+                None,
+            );
+            for compensation in engine
+                .compensations(client_id, transaction_id)
+                .await
+                .unwrap()
+            {
+                derived.compensate(&compensation).unwrap();
+            }
+            let stored = engine.transaction(client_id, transaction_id).await.unwrap();
+            assert_eq!(stored, Some(derived));
+            assert_eq!(derived.status(), TransactionStatus::ChargedBack);
+        }
+        assert_eq!(
+            engine
+                .transaction(client(), TransactionId::from(1))
+                .await
+                .unwrap()
+                .unwrap()
+                .info()
+                .disputes,
+            crate::ledger::transactions::Disputes {
+                held: Amount::default(),
+                resolved: Amount::from_minor(400),
+                charged_back: Amount::from_minor(600),
+            }
+        );
     }
 }
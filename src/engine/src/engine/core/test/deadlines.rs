@@ -0,0 +1,246 @@
+//! Dispute deadlines: the dispute window and the settlement of expired disputes.
+
+use super::*;
+use crate::engine::policy::DisputeDeadlines;
+
+/// A transaction can only be disputed within the window after it happened.
+#[tokio::test]
+async fn dispute_window() {
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| {
+            policy.deadlines = DisputeDeadlines {
+                dispute_window: Some(100),
+                ..DisputeDeadlines::default()
+            }
+        }));
+    engine
+        .apply(deposit(1, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+    engine
+        .apply(deposit(2, 100).at(Timestamp::from(1_050)))
+        .await
+        .unwrap();
+
+    let err = engine
+        .apply(dispute(1).at(Timestamp::from(1_101)))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        EngineError::DisputeWindowClosed {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            closed_at: Timestamp::from(1_100),
+        }
+    );
+    assert_eq!(err.code(), "dispute_window_closed");
+    assert!(err.is_partner_error());
+
+    // the window ends with its last second
+    engine
+        .apply(dispute(2).at(Timestamp::from(1_150)))
+        .await
+        .unwrap();
+    assert_eq!(status(&engine, 2).await, Some(TransactionStatus::Disputed));
+}
+
+/// Only the times given by the partner count for the deadlines, not the engine clock.
+#[tokio::test]
+async fn deadlines_without_timestamps() {
+    let clock = ManualClock::new(Timestamp::from(1_000));
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_clock(clock.clone())
+        .with_policies(policies(|policy| {
+            policy.deadlines = DisputeDeadlines {
+                dispute_window: Some(100),
+                max_disputed: Some(50),
+                ..DisputeDeadlines::default()
+            }
+        }));
+    engine.apply(deposit(1, 100)).await.unwrap();
+    engine
+        .apply(deposit(2, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+
+    clock.set(Timestamp::from(5_000));
+    engine.apply(dispute(1)).await.unwrap();
+    engine.apply(dispute(2)).await.unwrap();
+    let receipts = engine
+        .expire_disputes(Timestamp::from(10_000))
+        .await
+        .unwrap();
+    assert_eq!(receipts, vec![]);
+    assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Disputed));
+    assert_eq!(status(&engine, 2).await, Some(TransactionStatus::Disputed));
+}
+
+/// A dispute on a locked account reports the lock, even after the window.
+#[tokio::test]
+async fn dispute_window_of_locked_account() {
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| {
+            policy.deadlines = DisputeDeadlines {
+                dispute_window: Some(100),
+                ..DisputeDeadlines::default()
+            }
+        }));
+    engine
+        .apply(deposit(1, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+    engine
+        .apply(
+            Event::Freeze {
+                client_id: client(),
+                transaction_id: TransactionId::from(2),
+                reason: ReasonCode::Compliance,
+            }
+            .at(Timestamp::from(1_010)),
+        )
+        .await
+        .unwrap();
+
+    let err = engine
+        .apply(dispute(1).at(Timestamp::from(2_000)))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        EngineError::AccountLocked {
+            client_id: client(),
+            reason: ReasonCode::Compliance,
+        }
+    );
+}
+
+/// A dispute held for too long is resolved by the engine, recorded as a system event
+/// at the time it expired.
+#[tokio::test]
+async fn expired_dispute_is_resolved() {
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| {
+            policy.deadlines = DisputeDeadlines {
+                max_disputed: Some(50),
+                ..DisputeDeadlines::default()
+            }
+        }));
+    engine
+        .apply(deposit(1, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+    engine
+        .apply(dispute(1).at(Timestamp::from(1_010)))
+        .await
+        .unwrap();
+    engine
+        .apply(deposit(2, 100).at(Timestamp::from(1_059)))
+        .await
+        .unwrap();
+    assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Disputed));
+
+    // the next event of the client settles it first
+    engine
+        .apply(deposit(3, 100).at(Timestamp::from(1_060)))
+        .await
+        .unwrap();
+    assert_eq!(status(&engine, 1).await, Some(TransactionStatus::Resolved));
+    assert_eq!(
+        snapshot(&engine),
+        Some((Amount::from_minor(300), Amount::from_minor(300), false))
+    );
+
+    let events = engine.event_store().events().await.unwrap();
+    let system = events
+        .iter()
+        .filter(|recorded| recorded.origin == EventOrigin::System)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        system,
+        vec![&RecordedEvent {
+            sequence: 4,
+            event: Event::Resolve {
+                client_id: client(),
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            },
+            timestamp: Some(Timestamp::from(1_060)),
+            origin: EventOrigin::System,
+            rejection: None,
+        }]
+    );
+
+    // the system events are replayed as such, the replay doesn't expire the dispute again
+    let rebuild = engine
+        .rebuild_from(
+            events.clone(),
+            InMemoryLedger::new(),
+            InMemoryEventStore::new(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(rebuild.mismatches, vec![]);
+    assert_eq!(rebuild.engine.event_store().events().await.unwrap(), events);
+}
+
+/// [Engine::expire_disputes] settles the expired disputes of all clients, with a chargeback if the policy says so.
+#[tokio::test]
+async fn expired_dispute_is_charged_back() {
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| {
+            policy.deadlines = DisputeDeadlines {
+                max_disputed: Some(50),
+                on_expiry: ExpiryAction::Chargeback,
+                ..DisputeDeadlines::default()
+            }
+        }));
+    engine
+        .apply(deposit(1, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+    engine
+        .apply(deposit(2, 100).at(Timestamp::from(1_000)))
+        .await
+        .unwrap();
+    engine
+        .apply(dispute(1).at(Timestamp::from(1_010)))
+        .await
+        .unwrap();
+
+    let receipts = engine
+        .expire_disputes(Timestamp::from(1_059))
+        .await
+        .unwrap();
+    assert_eq!(receipts, vec![]);
+
+    let receipts = engine
+        .expire_disputes(Timestamp::from(2_000))
+        .await
+        .unwrap();
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(receipts[0].transaction_id, TransactionId::from(1));
+    assert_eq!(receipts[0].status, Some(TransactionStatus::ChargedBack));
+    assert!(receipts[0].locked);
+    assert_eq!(
+        snapshot(&engine),
+        Some((Amount::from_minor(100), Amount::from_minor(100), true))
+    );
+
+    // nothing is left to expire
+    let receipts = engine
+        .expire_disputes(Timestamp::from(3_000))
+        .await
+        .unwrap();
+    assert_eq!(receipts, vec![]);
+}
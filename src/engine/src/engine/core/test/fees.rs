@@ -0,0 +1,95 @@
+//! Fees: charged on the events of the schedule and booked to the house account.
+
+use super::*;
+
+/// Fees are taken from the client, credited to the house account and stored as ledger entries.
+#[tokio::test]
+async fn fees_are_booked() {
+    let rule = |event, fixed, percent| FeeRule {
+        event,
+        tier: None,
+        currency: None,
+        fee: Fee {
+            fixed: Amount::from_minor(fixed),
+            percent: Decimal::from(percent),
+        },
+    };
+    let schedule = FeeSchedule::new()
+        .with_rule(rule(FeeEvent::Withdrawal, 0, 10))
+        .with_rule(rule(FeeEvent::Chargeback, 100, 0));
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| policy.fees = schedule));
+    engine.apply(deposit(1, 300)).await.unwrap();
+    engine.apply(deposit(2, 100)).await.unwrap();
+
+    // the fee must be available along with the amount
+    let err = engine.apply(withdraw(3, 380)).await.unwrap_err();
+    assert_eq!(
+        err,
+        EngineError::InsufficientFunds {
+            client_id: client(),
+            transaction_id: TransactionId::from(3),
+            requested: Amount::from_minor(418).into(),
+            available: Amount::from_minor(400).into(),
+        }
+    );
+    let receipt = engine.apply(withdraw(3, 200)).await.unwrap();
+    assert_eq!(receipt.fee, Some(Amount::from_minor(20).into()));
+    assert_eq!(
+        snapshot(&engine),
+        Some((Amount::from_minor(180), Amount::from_minor(180), false))
+    );
+    // a replay isn't charged again
+    let receipt = engine.apply(withdraw(3, 200)).await.unwrap();
+    assert_eq!(receipt.fee, None);
+
+    // the chargeback fee is capped to the available funds
+    engine.apply(dispute(1)).await.unwrap_err();
+    engine.apply(dispute(2)).await.unwrap();
+    let receipt = engine
+        .apply(Event::Chargeback {
+            client_id: client(),
+            transaction_id: TransactionId::from(2),
+            amount: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(receipt.fee, Some(Amount::from_minor(80).into()));
+    assert_eq!(
+        snapshot(&engine),
+        Some((Amount::default(), Amount::default(), true))
+    );
+
+    assert_eq!(
+        engine.revenue(),
+        &BTreeMap::from([(Currency::NONE, Amount::from_minor(100))])
+    );
+    let postings = engine.ledger.fees().await.unwrap();
+    assert_eq!(
+        postings
+            .iter()
+            .map(|fee| (fee.transaction_id, fee.event, fee.amount))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                TransactionId::from(3),
+                FeeEvent::Withdrawal,
+                Amount::from_minor(20).into()
+            ),
+            (
+                TransactionId::from(2),
+                FeeEvent::Chargeback,
+                Amount::from_minor(80).into()
+            ),
+        ]
+    );
+
+    // the house account is restored from the ledger
+    let engine = Engine::new(engine.ledger).await.unwrap();
+    assert_eq!(
+        engine.revenue(),
+        &BTreeMap::from([(Currency::NONE, Amount::from_minor(100))])
+    );
+}
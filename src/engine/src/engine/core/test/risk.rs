@@ -0,0 +1,123 @@
+//! Risk rules: checked before deposits and withdrawals are applied.
+
+use super::*;
+use crate::engine::risk::{
+    MaxAmount, MaxWithdrawalPercent, ReviewThreshold, RiskRules, WithdrawalVelocity,
+};
+
+fn risk_rule(err: EngineError) -> String {
+    match err {
+        EngineError::RiskRejected { rule, .. } => rule,
+        err => panic!("not a risk rejection: {err:?}"),
+    }
+}
+
+fn approve(tx: u32) -> Event {
+    Event::Approve {
+        client_id: client(),
+        transaction_id: TransactionId::from(tx),
+    }
+}
+
+/// Risk rules reject transactions before anything is changed, naming the rule that fired.
+#[tokio::test]
+async fn risk_rules() {
+    let rules = RiskRules::new()
+        .with_rule(MaxAmount {
+            max: Amount::from_minor(1_000),
+        })
+        .with_rule(ReviewThreshold {
+            threshold: Amount::from_minor(500),
+        })
+        .with_rule(WithdrawalVelocity {
+            max_count: 2,
+            window: 60,
+        })
+        .with_rule(MaxWithdrawalPercent {
+            percent: Decimal::from(50),
+        });
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(policies(|policy| policy.risk = rules));
+
+    let err = engine.apply(deposit(1, 1_001)).await.unwrap_err();
+    assert_eq!(err.code(), "risk_rejected");
+    assert!(err.is_partner_error());
+    assert_eq!(risk_rule(err), "max_amount");
+
+    // held for review until approved
+    let err = engine.apply(deposit(1, 800)).await.unwrap_err();
+    assert_eq!(
+        err,
+        EngineError::RiskRejected {
+            client_id: client(),
+            transaction_id: TransactionId::from(1),
+            rule: "review_threshold".to_string(),
+            reason: "8.0000 exceeds 5.0000, held for review".to_string(),
+        }
+    );
+    assert_eq!(engine.account(client()), None);
+    engine.apply(approve(1)).await.unwrap();
+    engine.apply(deposit(1, 800)).await.unwrap();
+
+    let err = engine.apply(withdraw(2, 401)).await.unwrap_err();
+    assert_eq!(risk_rule(err), "max_withdrawal_percent");
+
+    let at = |secs: u64| Timestamp::from(1_000 + secs);
+    engine.apply(withdraw(2, 100).at(at(0))).await.unwrap();
+    engine.apply(withdraw(3, 100).at(at(30))).await.unwrap();
+    let err = engine.apply(withdraw(4, 100).at(at(59))).await.unwrap_err();
+    assert_eq!(risk_rule(err), "withdrawal_velocity");
+    // a replay is still recognized as such
+    let receipt = engine.apply(withdraw(3, 100).at(at(30))).await.unwrap();
+    assert_eq!(receipt.applied, Applied::AlreadyApplied);
+    // the first withdrawal left the window
+    engine.apply(withdraw(4, 100).at(at(60))).await.unwrap();
+
+    assert_eq!(
+        snapshot(&engine),
+        Some((Amount::from_minor(500), Amount::from_minor(500), false))
+    );
+}
+
+/// The recent withdrawals are restored from the ledger on startup, the approvals from the event store.
+#[tokio::test]
+async fn risk_state_survives_restart() {
+    let rules = || {
+        policies(|policy| {
+            policy.risk = RiskRules::new()
+                .with_rule(ReviewThreshold {
+                    threshold: Amount::from_minor(500),
+                })
+                .with_rule(WithdrawalVelocity {
+                    max_count: 2,
+                    window: 60,
+                })
+        })
+    };
+    let mut engine = Engine::new(FailingLedger::default())
+        .await
+        .unwrap()
+        .with_policies(rules());
+
+    engine.apply(approve(1)).await.unwrap();
+    let receipt = engine.apply(approve(1)).await.unwrap();
+    assert_eq!(receipt.applied, Applied::AlreadyApplied);
+    engine.apply(deposit(1, 800)).await.unwrap();
+    engine.apply(approve(5)).await.unwrap();
+    let at = |secs: u64| Timestamp::from(1_000 + secs);
+    engine.apply(withdraw(2, 100).at(at(0))).await.unwrap();
+    engine.apply(withdraw(3, 100).at(at(30))).await.unwrap();
+
+    let mut engine = Engine::with_event_store(engine.ledger, engine.events)
+        .await
+        .unwrap()
+        .with_policies(rules());
+    let err = engine.apply(withdraw(4, 100).at(at(59))).await.unwrap_err();
+    assert_eq!(risk_rule(err), "withdrawal_velocity");
+    engine.apply(withdraw(4, 100).at(at(60))).await.unwrap();
+    engine.apply(deposit(5, 600).at(at(61))).await.unwrap();
+    let err = engine.apply(deposit(6, 600).at(at(62))).await.unwrap_err();
+    assert_eq!(risk_rule(err), "review_threshold");
+}
@@ -1,30 +1,331 @@
-use crate::{engine::types::ClientId, ledger::LedgerError, ledger::transactions::TransitionError};
+use crate::{
+    engine::accounts::ReasonCode,
+    engine::types::{ClientId, Money, Timestamp, TransactionId},
+    event_store::EventStoreError,
+    ledger::LedgerError,
+    ledger::transactions::{Direction, TransactionStatus, TransitionError},
+};
 
-#[derive(thiserror::Error, Debug)]
+/// Errors of the engine.
+///
+/// Each variant carries the structured context of the failure, so callers can act on it
+/// without parsing the message. See [EngineError::code] for a stable identifier of each variant.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
 pub enum EngineError {
-    #[error("Invalid associated transaction: {0}")]
-    InvalidAssociatedTransaction(&'static str),
-    #[error("Insufficient funds")]
-    InsufficientFunds,
-    #[error("Transaction is in invalid status: {0}")]
-    InvalidTransactionStatus(String),
-    #[error("Duplicate event")]
-    DuplicateEvent,
-    #[error("Client {0} account is locked, no further activity is allowed")]
-    AccountLocked(ClientId),
-    /// Error in events coming from the partner.
-    /// Eg. invalid reference to a transaction (invalid direction, not found etc.)
-    #[error("Invalid event: {0}")]
-    InvalidEvent(&'static str),
-    #[error("System error: {0}")]
-    SystemError(&'static str),
+    /// A dispute/resolve/chargeback refers to a transaction that doesn't support the operation.
+    #[error(
+        "Invalid associated transaction: transaction {transaction_id} is {direction:?}, the operation doesn't apply to it"
+    )]
+    InvalidAssociatedTransaction {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        direction: Direction,
+    },
+    #[error("Insufficient funds: requested {requested}, available {available}")]
+    InsufficientFunds {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        requested: Money,
+        available: Money,
+    },
+    #[error(
+        "Transaction is in invalid status: Operation doesn't apply to this transaction. Transition from {current} to {desired}"
+    )]
+    InvalidTransactionStatus {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        current: TransactionStatus,
+        desired: TransactionStatus,
+    },
+    #[error("Duplicate event for transaction {transaction_id}")]
+    DuplicateEvent {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    #[error("Client {client_id} account is locked ({reason}), no further activity is allowed")]
+    AccountLocked {
+        client_id: ClientId,
+        reason: ReasonCode,
+    },
+    /// The account was closed, it can't be unlocked either.
+    #[error("Client {client_id} account is closed ({reason}), no further activity is allowed")]
+    AccountClosed {
+        client_id: ClientId,
+        reason: ReasonCode,
+    },
+    /// An unlock or close refers to a client without account.
+    #[error("Client {client_id} has no account")]
+    AccountNotFound { client_id: ClientId },
+    /// An account with a balance can't be closed.
+    #[error("Client {client_id} account can't be closed with a balance of {balance}")]
+    NonZeroBalance { client_id: ClientId, balance: Money },
+    /// The event is older than the latest event of the client, see [crate::policy::TimestampPolicy].
+    #[error(
+        "Event at {timestamp} is older than the latest event of client {client_id} at {latest}"
+    )]
+    TimestampOutOfOrder {
+        client_id: ClientId,
+        timestamp: Timestamp,
+        latest: Timestamp,
+    },
+    /// The dispute comes after the dispute window of the transaction, see [crate::policy::DisputeDeadlines].
+    #[error("Dispute window of transaction {transaction_id} closed at {closed_at}")]
+    DisputeWindowClosed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        closed_at: Timestamp,
+    },
+    /// A risk rule fired before the transaction was applied, see [crate::risk::RiskRules].
+    #[error("Risk rule `{rule}` rejected transaction {transaction_id}: {reason}")]
+    RiskRejected {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        rule: String,
+        reason: String,
+    },
+    /// A deposit/withdrawal or a partial dispute/resolve/chargeback of zero or less,
+    /// once rounded to the minor units of its currency.
+    #[error("Invalid event: Amount must be positive, got {amount}")]
+    NonPositiveAmount {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Money,
+    },
+    /// A partial dispute/resolve/chargeback without timestamp, see [crate::TimedEvent::validate].
+    #[error("Invalid event: partial event of transaction {transaction_id} without timestamp")]
+    PartialWithoutTimestamp {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// The referenced transaction doesn't exist for this client.
+    /// A transaction of another client isn't distinguished, to not leak its existence.
+    #[error("Invalid event: transaction {transaction_id} not found")]
+    TransactionNotFound {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// The transaction id is already used by another client.
+    #[error("Invalid event: Transaction {transaction_id} belong to a different client")]
+    TransactionOfDifferentClient {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// The transaction id is already used by the client for a different transaction.
+    #[error("Invalid event: Transaction {transaction_id} already exist but with different details")]
+    ConflictingTransaction {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// A dispute asks for more than the undisputed part of the transaction.
+    #[error("Over-dispute: requested {requested}, undisputed {undisputed}")]
+    OverDispute {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        requested: Money,
+        undisputed: Money,
+    },
+    /// A resolve/chargeback asks for more than the disputed part of the transaction that is held.
+    #[error("Exceeds held amount: requested {requested}, held {held}")]
+    ExceedsHeldAmount {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        requested: Money,
+        held: Money,
+    },
+    /// A part of the engine stopped or an invariant was violated, processing must stop.
+    #[error("System error: {fault}")]
+    SystemError {
+        fault: SystemFault,
+        /// The client of the event that hit the fault, if any.
+        client_id: Option<ClientId>,
+        /// The transaction of the event that hit the fault, if any.
+        transaction_id: Option<TransactionId>,
+    },
+    #[error("Storage error: {0}")]
+    StorageError(String),
+}
+
+/// What went wrong in a [EngineError::SystemError].
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemFault {
+    /// An earlier event couldn't be recorded in the event store, the engine refuses further events.
+    #[error("an event couldn't be recorded in the event store, the engine stopped")]
+    Unrecorded,
+    /// An event was recorded in the event store but its ledger write failed, the engine refuses further events
+    /// until it is opened again (see [crate::Engine::recover]).
+    #[error("an event was recorded but couldn't be written to the ledger, the engine stopped")]
+    Unapplied,
+    /// Settling a dispute would drive the total negative although the policy rejects negative balances.
+    #[error("bug: total amount should never be negative")]
+    NegativeTotal,
+    /// The thread running the [crate::EngineHandle] actor is gone.
+    #[error("engine thread stopped")]
+    EngineStopped,
+    /// A shard worker of the [crate::ShardedEngine] panicked.
+    #[error("shard worker panicked")]
+    ShardPanicked,
+    /// A shard worker of the [crate::ShardedEngine] stopped before the end of the input.
+    #[error("shard worker stopped")]
+    ShardStopped,
+}
+
+impl EngineError {
+    /// A system error without event context.
+    pub fn system(fault: SystemFault) -> Self {
+        EngineError::SystemError {
+            fault,
+            client_id: None,
+            transaction_id: None,
+        }
+    }
+
+    /// Partner errors are caused by invalid data from the partner: the event is rejected
+    /// and processing can continue.
+    /// Any other error is a system error (invariant violation, storage failure) and processing should stop.
+    pub fn is_partner_error(&self) -> bool {
+        match self {
+            EngineError::InvalidAssociatedTransaction { .. }
+            | EngineError::InsufficientFunds { .. }
+            | EngineError::InvalidTransactionStatus { .. }
+            | EngineError::DuplicateEvent { .. }
+            | EngineError::AccountLocked { .. }
+            | EngineError::AccountClosed { .. }
+            | EngineError::AccountNotFound { .. }
+            | EngineError::NonZeroBalance { .. }
+            | EngineError::TimestampOutOfOrder { .. }
+            | EngineError::DisputeWindowClosed { .. }
+            | EngineError::RiskRejected { .. }
+            | EngineError::NonPositiveAmount { .. }
+            | EngineError::PartialWithoutTimestamp { .. }
+            | EngineError::TransactionNotFound { .. }
+            | EngineError::TransactionOfDifferentClient { .. }
+            | EngineError::ConflictingTransaction { .. }
+            | EngineError::OverDispute { .. }
+            | EngineError::ExceedsHeldAmount { .. } => true,
+            EngineError::SystemError { .. } | EngineError::StorageError(_) => false,
+        }
+    }
+
+    /// Stable identifier of the error kind, for machine-readable reports.
+    /// Unlike the message, it doesn't change between versions.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::InvalidAssociatedTransaction { .. } => "invalid_associated_transaction",
+            EngineError::InsufficientFunds { .. } => "insufficient_funds",
+            EngineError::InvalidTransactionStatus { .. } => "invalid_transaction_status",
+            EngineError::DuplicateEvent { .. } => "duplicate_event",
+            EngineError::AccountLocked { .. } => "account_locked",
+            EngineError::AccountClosed { .. } => "account_closed",
+            EngineError::AccountNotFound { .. } => "account_not_found",
+            EngineError::NonZeroBalance { .. } => "non_zero_balance",
+            EngineError::TimestampOutOfOrder { .. } => "timestamp_out_of_order",
+            EngineError::DisputeWindowClosed { .. } => "dispute_window_closed",
+            EngineError::RiskRejected { .. } => "risk_rejected",
+            EngineError::NonPositiveAmount { .. } => "non_positive_amount",
+            EngineError::PartialWithoutTimestamp { .. } => "partial_without_timestamp",
+            EngineError::TransactionNotFound { .. } => "transaction_not_found",
+            EngineError::TransactionOfDifferentClient { .. } => "transaction_of_different_client",
+            EngineError::ConflictingTransaction { .. } => "conflicting_transaction",
+            EngineError::OverDispute { .. } => "over_dispute",
+            EngineError::ExceedsHeldAmount { .. } => "exceeds_held_amount",
+            EngineError::SystemError { .. } => "system_error",
+            EngineError::StorageError(_) => "storage_error",
+        }
+    }
+
+    /// The client the rejected event belongs to, if the error is about a specific event.
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self {
+            EngineError::InvalidAssociatedTransaction { client_id, .. }
+            | EngineError::InsufficientFunds { client_id, .. }
+            | EngineError::InvalidTransactionStatus { client_id, .. }
+            | EngineError::DuplicateEvent { client_id, .. }
+            | EngineError::AccountLocked { client_id, .. }
+            | EngineError::AccountClosed { client_id, .. }
+            | EngineError::AccountNotFound { client_id }
+            | EngineError::NonZeroBalance { client_id, .. }
+            | EngineError::TimestampOutOfOrder { client_id, .. }
+            | EngineError::DisputeWindowClosed { client_id, .. }
+            | EngineError::RiskRejected { client_id, .. }
+            | EngineError::NonPositiveAmount { client_id, .. }
+            | EngineError::PartialWithoutTimestamp { client_id, .. }
+            | EngineError::TransactionNotFound { client_id, .. }
+            | EngineError::TransactionOfDifferentClient { client_id, .. }
+            | EngineError::ConflictingTransaction { client_id, .. }
+            | EngineError::OverDispute { client_id, .. }
+            | EngineError::ExceedsHeldAmount { client_id, .. } => Some(*client_id),
+            EngineError::SystemError { client_id, .. } => *client_id,
+            EngineError::StorageError(_) => None,
+        }
+    }
+
+    /// The transaction the rejected event refers to, if the error is about a specific transaction.
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match self {
+            EngineError::InvalidAssociatedTransaction { transaction_id, .. }
+            | EngineError::InsufficientFunds { transaction_id, .. }
+            | EngineError::InvalidTransactionStatus { transaction_id, .. }
+            | EngineError::DuplicateEvent { transaction_id, .. }
+            | EngineError::DisputeWindowClosed { transaction_id, .. }
+            | EngineError::RiskRejected { transaction_id, .. }
+            | EngineError::NonPositiveAmount { transaction_id, .. }
+            | EngineError::PartialWithoutTimestamp { transaction_id, .. }
+            | EngineError::TransactionNotFound { transaction_id, .. }
+            | EngineError::TransactionOfDifferentClient { transaction_id, .. }
+            | EngineError::ConflictingTransaction { transaction_id, .. }
+            | EngineError::OverDispute { transaction_id, .. }
+            | EngineError::ExceedsHeldAmount { transaction_id, .. } => Some(*transaction_id),
+            EngineError::SystemError { transaction_id, .. } => *transaction_id,
+            EngineError::AccountLocked { .. }
+            | EngineError::AccountClosed { .. }
+            | EngineError::AccountNotFound { .. }
+            | EngineError::NonZeroBalance { .. }
+            | EngineError::TimestampOutOfOrder { .. }
+            | EngineError::StorageError(_) => None,
+        }
+    }
 }
 
 impl From<LedgerError> for EngineError {
     fn from(err: LedgerError) -> Self {
         match err {
-            LedgerError::AlreadyExists => EngineError::DuplicateEvent,
-            LedgerError::Conflict(message) => EngineError::InvalidEvent(message),
+            LedgerError::AlreadyExists {
+                client_id,
+                transaction_id,
+            } => EngineError::DuplicateEvent {
+                client_id,
+                transaction_id,
+            },
+            LedgerError::DifferentClient {
+                client_id,
+                transaction_id,
+            } => EngineError::TransactionOfDifferentClient {
+                client_id,
+                transaction_id,
+            },
+            LedgerError::NotFound {
+                client_id,
+                transaction_id,
+            } => EngineError::TransactionNotFound {
+                client_id,
+                transaction_id,
+            },
+            LedgerError::DifferentDetails {
+                client_id,
+                transaction_id,
+            } => EngineError::ConflictingTransaction {
+                client_id,
+                transaction_id,
+            },
+            LedgerError::Storage(message) => EngineError::StorageError(message),
+        }
+    }
+}
+
+impl From<EventStoreError> for EngineError {
+    fn from(err: EventStoreError) -> Self {
+        match err {
+            EventStoreError::Storage(message) => EngineError::StorageError(message),
         }
     }
 }
@@ -32,16 +333,48 @@ impl From<LedgerError> for EngineError {
 impl From<TransitionError> for EngineError {
     fn from(err: TransitionError) -> Self {
         match err {
-            TransitionError::InvalidTransition(current, desired) => {
-                EngineError::InvalidTransactionStatus(format!(
-                    "Operation doesn't apply to this transaction. Transition from {} to {}",
-                    current, desired
-                ))
-            }
-            TransitionError::InvalidDirection => {
-                // not used, there are better error messages
-                EngineError::InvalidEvent("Invalid transaction")
-            }
+            TransitionError::InvalidTransition {
+                client_id,
+                transaction_id,
+                current,
+                desired,
+            } => EngineError::InvalidTransactionStatus {
+                client_id,
+                transaction_id,
+                current,
+                desired,
+            },
+            TransitionError::InvalidDirection {
+                client_id,
+                transaction_id,
+                direction,
+            } => EngineError::InvalidAssociatedTransaction {
+                client_id,
+                transaction_id,
+                direction,
+            },
+            TransitionError::ExceedsUndisputed {
+                client_id,
+                transaction_id,
+                requested,
+                undisputed,
+            } => EngineError::OverDispute {
+                client_id,
+                transaction_id,
+                requested,
+                undisputed,
+            },
+            TransitionError::ExceedsHeld {
+                client_id,
+                transaction_id,
+                requested,
+                held,
+            } => EngineError::ExceedsHeldAmount {
+                client_id,
+                transaction_id,
+                requested,
+                held,
+            },
         }
     }
 }
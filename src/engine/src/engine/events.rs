@@ -1,41 +1,209 @@
-use crate::engine::types::{Amount, ClientId, TransactionId};
+use crate::engine::accounts::ReasonCode;
+use crate::engine::types::{Amount, ClientId, Money, Timestamp, TransactionId};
 use crate::errors::EngineError;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Event {
     Deposit {
         client_id: ClientId,
         transaction_id: TransactionId,
-        amount: Amount,
+        amount: Money,
     },
     Withdraw {
         client_id: ClientId,
         transaction_id: TransactionId,
-        amount: Amount,
+        amount: Money,
     },
+    /// Dispute `amount` of the transaction, the whole undisputed amount if `None`.
     Dispute {
         client_id: ClientId,
         transaction_id: TransactionId,
+        amount: Option<Amount>,
     },
+    /// Resolve `amount` of the held disputes, all of them if `None`.
     Resolve {
         client_id: ClientId,
         transaction_id: TransactionId,
+        amount: Option<Amount>,
     },
+    /// Charge back `amount` of the held disputes, all of them if `None`.
     Chargeback {
         client_id: ClientId,
         transaction_id: TransactionId,
+        amount: Option<Amount>,
     },
+    /// Admin: lift the lock of a chargeback or a freeze.
+    ///
+    /// Account events don't refer to a transaction, `transaction_id` identifies the admin action
+    /// (eg. the `tx` column of the input). It isn't stored in the ledger.
+    Unlock {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        reason: ReasonCode,
+    },
+    /// Admin: lock the account proactively, eg. for a compliance review.
+    ///
+    /// A client without account gets a locked one.
+    Freeze {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        reason: ReasonCode,
+    },
+    /// Admin: close the account for good, only without balance.
+    Close {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        reason: ReasonCode,
+    },
+    /// Admin: approve a deposit or withdrawal held for review (see [crate::risk::ReviewThreshold]),
+    /// it is applied once the partner sends it again.
+    ///
+    /// `transaction_id` is the held transaction. Nothing is stored in the ledger, the approval is
+    /// recorded in the event store only.
+    Approve {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+}
+
+/// An event along with the time it happened, as told by its source (eg. the `timestamp` column of the input).
+///
+/// [crate::Engine::apply] takes any [Event] as an event without timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedEvent {
+    pub event: Event,
+    /// `None` if the source doesn't tell, the engine then uses its clock (see [crate::clock::Clock]).
+    pub timestamp: Option<Timestamp>,
+}
+
+impl From<Event> for TimedEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            event,
+            timestamp: None,
+        }
+    }
+}
+
+impl TimedEvent {
+    /// Validate the event, see [Event::validate].
+    ///
+    /// A partial dispute, resolve or chargeback must have a timestamp: it is identified by its amount
+    /// and timestamp, a resend without one couldn't be told apart from a further partial event.
+    pub fn validate(&self) -> Result<(), EngineError> {
+        self.event.validate()?;
+        match self.event {
+            Event::Dispute {
+                client_id,
+                transaction_id,
+                amount: Some(_),
+            }
+            | Event::Resolve {
+                client_id,
+                transaction_id,
+                amount: Some(_),
+            }
+            | Event::Chargeback {
+                client_id,
+                transaction_id,
+                amount: Some(_),
+            } if self.timestamp.is_none() => Err(EngineError::PartialWithoutTimestamp {
+                client_id,
+                transaction_id,
+            }),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Event {
+    /// The event, happened at `timestamp`.
+    pub fn at(self, timestamp: Timestamp) -> TimedEvent {
+        TimedEvent {
+            event: self,
+            timestamp: Some(timestamp),
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Event::Deposit { client_id, .. }
+            | Event::Withdraw { client_id, .. }
+            | Event::Dispute { client_id, .. }
+            | Event::Resolve { client_id, .. }
+            | Event::Chargeback { client_id, .. }
+            | Event::Unlock { client_id, .. }
+            | Event::Freeze { client_id, .. }
+            | Event::Close { client_id, .. }
+            | Event::Approve { client_id, .. } => *client_id,
+        }
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        match self {
+            Event::Deposit { transaction_id, .. }
+            | Event::Withdraw { transaction_id, .. }
+            | Event::Dispute { transaction_id, .. }
+            | Event::Resolve { transaction_id, .. }
+            | Event::Chargeback { transaction_id, .. }
+            | Event::Unlock { transaction_id, .. }
+            | Event::Freeze { transaction_id, .. }
+            | Event::Close { transaction_id, .. }
+            | Event::Approve { transaction_id, .. } => *transaction_id,
+        }
+    }
+
+    /// Whether the event acts on the account rather than on a transaction (unlock, freeze, close).
+    pub fn is_account_event(&self) -> bool {
+        matches!(
+            self,
+            Event::Unlock { .. } | Event::Freeze { .. } | Event::Close { .. }
+        )
+    }
+
     /// Validate the event.
     ///
-    /// Currently the only validation is to check that the amount is positive for Deposit and Withdraw events.
+    /// Currently the only validation is to check that the amount is strictly positive for Deposit and
+    /// Withdraw events, and for a partial Dispute, Resolve or Chargeback. The engine checks a partial
+    /// amount again once rounded to the currency of its transaction, which isn't known here.
     pub fn validate(&self) -> Result<(), EngineError> {
         match self {
-            Event::Deposit { amount, .. } | Event::Withdraw { amount, .. } => {
-                if amount.as_decimal().is_sign_negative() {
-                    return Err(EngineError::InvalidEvent("Amount must be positive"));
-                }
+            Event::Deposit {
+                client_id,
+                transaction_id,
+                amount,
+            }
+            | Event::Withdraw {
+                client_id,
+                transaction_id,
+                amount,
+            } if amount.amount <= Amount::default() => {
+                return Err(EngineError::NonPositiveAmount {
+                    client_id: *client_id,
+                    transaction_id: *transaction_id,
+                    amount: *amount,
+                });
+            }
+            Event::Dispute {
+                client_id,
+                transaction_id,
+                amount: Some(amount),
+            }
+            | Event::Resolve {
+                client_id,
+                transaction_id,
+                amount: Some(amount),
+            }
+            | Event::Chargeback {
+                client_id,
+                transaction_id,
+                amount: Some(amount),
+            } if *amount <= Amount::default() => {
+                return Err(EngineError::NonPositiveAmount {
+                    client_id: *client_id,
+                    transaction_id: *transaction_id,
+                    amount: (*amount).into(),
+                });
             }
             _ => {}
         }
@@ -0,0 +1,156 @@
+//! Fees charged by the engine on the events of a client.
+//!
+//! A [FeeSchedule] is part of the [crate::policy::Policy] of a client. Fees are booked against the client
+//! and credited to the house account (see [crate::Engine::revenue]), each one is stored as its own
+//! ledger entry ([crate::ledger::transactions::FeePosting]).
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::{Amount, ClientId, Currency, Money};
+
+/// Events that can be charged a fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeeEvent {
+    Deposit,
+    Withdrawal,
+    /// Chargeback of a deposit, or reversal of a withdrawal.
+    Chargeback,
+}
+
+/// A fixed amount plus a percentage of the amount of the event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fee {
+    /// In the currency of the event.
+    pub fixed: Amount,
+    /// Eg. `1.5` for 1.5%.
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// The fee on `amount`, rounded to the minor unit of its currency.
+    pub fn of(&self, amount: Money) -> Money {
+        let variable = amount.amount.as_decimal() * self.percent / Decimal::ONE_HUNDRED;
+        Money::new(self.fixed.as_decimal() + variable, amount.currency)
+    }
+}
+
+/// A fee of the schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeRule {
+    pub event: FeeEvent,
+    /// Only charged to the clients of this tier, see [FeeSchedule::with_tier]. All clients if `None`.
+    pub tier: Option<String>,
+    /// Only charged on amounts in this currency. All currencies if `None`.
+    pub currency: Option<Currency>,
+    pub fee: Fee,
+}
+
+/// Fees per type of event, optionally per client tier. Nothing is charged by default.
+///
+/// The most specific rule applies: a rule of the tier of the client over a rule for all clients,
+/// then a rule of the currency over a rule for all currencies. The last one wins between rules
+/// as specific as each other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    rules: Vec<FeeRule>,
+    tiers: HashMap<ClientId, String>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: FeeRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Put the given clients in `tier`, a client is in one tier at most.
+    pub fn with_tier(
+        mut self,
+        tier: impl Into<String>,
+        clients: impl IntoIterator<Item = ClientId>,
+    ) -> Self {
+        let tier = tier.into();
+        for client_id in clients {
+            self.tiers.insert(client_id, tier.clone());
+        }
+        self
+    }
+
+    /// The tier of a client, `None` if it isn't in any.
+    pub fn tier(&self, client_id: ClientId) -> Option<&str> {
+        self.tiers.get(&client_id).map(String::as_str)
+    }
+
+    /// The fee charged to `client_id` for `event` on `amount`, `None` if there is nothing to charge.
+    pub fn fee(&self, event: FeeEvent, client_id: ClientId, amount: Money) -> Option<Money> {
+        let tier = self.tier(client_id);
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| rule.event == event)
+            .filter(|rule| rule.tier.is_none() || rule.tier.as_deref() == tier)
+            .filter(|rule| {
+                rule.currency
+                    .is_none_or(|currency| currency == amount.currency)
+            })
+            .max_by_key(|rule| (rule.tier.is_some(), rule.currency.is_some()))?;
+
+        let fee = rule.fee.of(amount);
+        (fee.amount != Amount::default()).then_some(fee)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn usd(value: Decimal) -> Money {
+        Money::new(value, Currency::new("USD").unwrap())
+    }
+
+    #[test]
+    fn most_specific_rule_applies() {
+        let rule = |tier: Option<&str>, currency, fixed| FeeRule {
+            event: FeeEvent::Withdrawal,
+            tier: tier.map(str::to_string),
+            currency,
+            fee: Fee {
+                fixed: Amount::from(Decimal::from(fixed)),
+                percent: Decimal::ONE,
+            },
+        };
+        let schedule = FeeSchedule::new()
+            .with_rule(rule(None, None, 1))
+            .with_rule(rule(None, Currency::new("USD"), 2))
+            .with_rule(rule(Some("gold"), None, 0))
+            .with_tier("gold", [ClientId::from(2)]);
+
+        // 2 + 1% of 150, rounded to cents
+        let amount = usd(Decimal::new(150, 0));
+        assert_eq!(
+            schedule.fee(FeeEvent::Withdrawal, ClientId::from(1), amount),
+            Some(usd(Decimal::new(350, 2)))
+        );
+        assert_eq!(
+            schedule.fee(
+                FeeEvent::Withdrawal,
+                ClientId::from(1),
+                Amount::from_minor(100).into()
+            ),
+            Some(Money::new(Decimal::new(101, 2), Currency::NONE))
+        );
+        assert_eq!(
+            schedule.fee(FeeEvent::Withdrawal, ClientId::from(2), amount),
+            Some(usd(Decimal::new(150, 2)))
+        );
+        assert_eq!(
+            schedule.fee(FeeEvent::Chargeback, ClientId::from(1), amount),
+            None
+        );
+    }
+}
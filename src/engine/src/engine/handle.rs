@@ -0,0 +1,207 @@
+//! Thread-safe handle to an engine, to share it between tasks (eg. in a server).
+//!
+//! The engine is owned by a single actor, handles send it commands over a channel.
+//! Commands are processed one at a time, so concurrent events of the same client can't race.
+//!
+//! The actor runs on a thread of its own: ledgers and event stores may block on I/O (eg. the fsync
+//! of [crate::ledger::file::FileLedger]), which must not stall the workers of the runtime.
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::engine::types::{ClientId, Timestamp, TransactionId};
+use crate::errors::{EngineError, SystemFault};
+use crate::event_store::EventStore;
+use crate::ledger::Ledger;
+use crate::ledger::transactions::Transaction;
+use crate::{ClientAccount, Engine, Receipt, TimedEvent};
+
+/// Commands buffered before callers wait for the actor.
+const COMMAND_QUEUE_SIZE: usize = 1024;
+
+/// Cloneable handle to an engine running on its own thread.
+///
+/// The thread stops once all handles are dropped.
+#[derive(Debug, Clone)]
+pub struct EngineHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+enum Command {
+    Apply(TimedEvent, oneshot::Sender<Result<Receipt, EngineError>>),
+    Account(ClientId, oneshot::Sender<Option<ClientAccount>>),
+    Accounts(oneshot::Sender<Vec<ClientAccount>>),
+    Fault(oneshot::Sender<Option<SystemFault>>),
+    ExpireDisputes(
+        Timestamp,
+        oneshot::Sender<Result<Vec<Receipt>, EngineError>>,
+    ),
+    Transaction(
+        ClientId,
+        TransactionId,
+        oneshot::Sender<Result<Option<Transaction>, EngineError>>,
+    ),
+}
+
+impl EngineHandle {
+    /// Move the engine onto a dedicated thread, driven by the current tokio runtime.
+    ///
+    /// Panics if called outside of a tokio runtime or if the thread can't be spawned.
+    pub fn spawn<L, E>(engine: Engine<L, E>) -> Self
+    where
+        L: Ledger + Send + Sync + 'static,
+        E: EventStore + Send + Sync + 'static,
+    {
+        let (commands, receiver) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::Builder::new()
+            .name("payment-engine".to_string())
+            .spawn(move || runtime.block_on(run(engine, receiver)))
+            .expect("spawn the engine thread");
+
+        Self { commands }
+    }
+
+    /// See [Engine::apply].
+    pub async fn apply(&self, event: impl Into<TimedEvent>) -> Result<Receipt, EngineError> {
+        let event = event.into();
+        self.request(|reply| Command::Apply(event, reply)).await?
+    }
+
+    /// The account of the client, `None` if the client has no activity yet.
+    pub async fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, EngineError> {
+        self.request(|reply| Command::Account(client_id, reply))
+            .await
+    }
+
+    /// All client accounts sorted by client ID.
+    pub async fn accounts(&self) -> Result<Vec<ClientAccount>, EngineError> {
+        self.request(Command::Accounts).await
+    }
+
+    /// See [Engine::fault]. The engine thread being gone is reported as [SystemFault::EngineStopped].
+    pub async fn fault(&self) -> Option<SystemFault> {
+        match self.request(Command::Fault).await {
+            Ok(fault) => fault,
+            Err(_) => Some(SystemFault::EngineStopped),
+        }
+    }
+
+    /// See [Engine::expire_disputes].
+    pub async fn expire_disputes(&self, now: Timestamp) -> Result<Vec<Receipt>, EngineError> {
+        self.request(|reply| Command::ExpireDisputes(now, reply))
+            .await?
+    }
+
+    /// See [Engine::transaction].
+    pub async fn transaction(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Option<Transaction>, EngineError> {
+        self.request(|reply| Command::Transaction(client_id, transaction_id, reply))
+            .await?
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, EngineError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| EngineError::system(SystemFault::EngineStopped))?;
+
+        response
+            .await
+            .map_err(|_| EngineError::system(SystemFault::EngineStopped))
+    }
+}
+
+async fn run<L: Ledger, E: EventStore>(
+    mut engine: Engine<L, E>,
+    mut commands: mpsc::Receiver<Command>,
+) {
+    // A caller that gave up waiting dropped its receiver, the reply is discarded.
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Apply(event, reply) => {
+                let _ = reply.send(engine.apply(event).await);
+            }
+            Command::Account(client_id, reply) => {
+                let _ = reply.send(engine.account(client_id).cloned());
+            }
+            Command::Accounts(reply) => {
+                let accounts = engine.accounts_ordered().into_iter().cloned().collect();
+                let _ = reply.send(accounts);
+            }
+            Command::Fault(reply) => {
+                let _ = reply.send(engine.fault());
+            }
+            Command::ExpireDisputes(now, reply) => {
+                let _ = reply.send(engine.expire_disputes(now).await);
+            }
+            Command::Transaction(client_id, transaction_id, reply) => {
+                let _ = reply.send(engine.transaction(client_id, transaction_id).await);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Event;
+    use crate::engine::types::{Amount, Currency, TransactionId};
+    use crate::ledger::in_memory::InMemoryLedger;
+
+    #[test]
+    fn handle_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone + 'static>() {}
+        assert_send_sync::<EngineHandle>();
+    }
+
+    /// Deposits and withdrawals of the same client from many tasks never overdraw the account.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_events_of_a_client() {
+        let engine = Engine::new(InMemoryLedger::new()).await.unwrap();
+        let handle = EngineHandle::spawn(engine);
+        let client_id = ClientId::from(1);
+
+        let mut tasks = vec![];
+        for tx in 0..200u32 {
+            let handle = handle.clone();
+            tasks.push(tokio::spawn(async move {
+                let event = if tx % 2 == 0 {
+                    Event::Deposit {
+                        client_id,
+                        transaction_id: TransactionId::from(tx),
+                        amount: Amount::from_minor(100).into(),
+                    }
+                } else {
+                    Event::Withdraw {
+                        client_id,
+                        transaction_id: TransactionId::from(tx),
+                        amount: Amount::from_minor(150).into(),
+                    }
+                };
+                handle.apply(event).await.is_ok() as u32
+            }));
+        }
+
+        let mut withdrawals = 0;
+        for (tx, task) in tasks.into_iter().enumerate() {
+            let applied = task.await.unwrap();
+            if tx % 2 == 1 {
+                withdrawals += applied;
+            }
+        }
+
+        let account = handle.account(client_id).await.unwrap().unwrap();
+        let expected = Amount::from_minor(100 * 100 - 150 * withdrawals).into();
+        let balance = account.balance(Currency::NONE);
+        assert_eq!(balance.total, expected);
+        assert_eq!(balance.available, expected);
+        assert_eq!(handle.accounts().await.unwrap(), vec![account]);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::engine::accounts::Balance;
+use crate::engine::types::{Amount, ClientId, Currency, Money, SignedAmount, TransactionId};
+use crate::ledger::transactions::TransactionStatus;
+
+/// Successful outcome of [crate::Engine::apply].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applied {
+    /// The event was applied and changed the engine state.
+    New,
+    /// The event is an exact replay of an event that was already applied.
+    /// Nothing changed.
+    AlreadyApplied,
+}
+
+/// What an applied event changed, returned by [crate::Engine::apply].
+///
+/// Lets callers log, audit or stream balance changes without diffing account snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Receipt {
+    pub client_id: ClientId,
+    pub transaction_id: TransactionId,
+    pub applied: Applied,
+    /// Currency of the transaction, the balances are in this currency.
+    /// [Currency::NONE] for account events.
+    pub currency: Currency,
+    /// Balances of the client account before the event (zero for a new account).
+    pub before: Balances,
+    /// Balances of the client account after the event.
+    /// Same as `before` if the event was already applied.
+    pub after: Balances,
+    /// Status of the transaction the event created or acted on, `None` for account events (eg. a freeze).
+    pub status: Option<TransactionStatus>,
+    /// Whether this event locked the account.
+    pub locked: bool,
+    /// Whether the event is older than the latest event of the client,
+    /// only applied if the [crate::policy::TimestampPolicy] flags it.
+    pub out_of_order: bool,
+    /// Fee charged for the event, in the currency of the transaction, see [crate::fees::FeeSchedule].
+    pub fee: Option<Money>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Balances {
+    pub available: SignedAmount,
+    /// See [Balance::held].
+    pub held: SignedAmount,
+    pub total: SignedAmount,
+    /// See [Balance::deficit].
+    pub deficit: Amount,
+}
+
+impl From<Balance> for Balances {
+    fn from(balance: Balance) -> Self {
+        Self {
+            available: balance.available,
+            held: balance.held(),
+            total: balance.total,
+            deficit: balance.deficit(),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Rules of the engine that differ between partners.
+//!
+//! An engine applies a default [Policy], partners with their own rules get a policy for their clients,
+//! see [Policies].
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::engine::fees::FeeSchedule;
+use crate::engine::risk::RiskRules;
+use crate::engine::types::ClientId;
+use crate::ledger::transactions::{Direction, TransactionStatus};
+
+/// Rules applied to the events of a client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub transitions: TransitionPolicy,
+    pub negative_balance: NegativeBalancePolicy,
+    pub timestamps: TimestampPolicy,
+    pub deadlines: DisputeDeadlines,
+    pub fees: FeeSchedule,
+    pub risk: RiskRules,
+}
+
+/// A default policy plus the policies of partners, each applying to the clients of the partner.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policies {
+    default: Policy,
+    partners: Vec<Policy>,
+    clients: HashMap<ClientId, usize>,
+}
+
+impl Policies {
+    /// Apply `policy` to every client.
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            default: policy,
+            ..Self::default()
+        }
+    }
+
+    /// Apply `policy` to the clients of a partner instead of the default policy.
+    ///
+    /// A client belongs to a single partner, the last one given wins.
+    pub fn with_partner(
+        mut self,
+        clients: impl IntoIterator<Item = ClientId>,
+        policy: Policy,
+    ) -> Self {
+        let index = self.partners.len();
+        self.partners.push(policy);
+        self.clients
+            .extend(clients.into_iter().map(|client_id| (client_id, index)));
+        self
+    }
+
+    /// The policy of the client.
+    pub fn of(&self, client_id: ClientId) -> &Policy {
+        self.clients
+            .get(&client_id)
+            .map_or(&self.default, |index| &self.partners[*index])
+    }
+}
+
+/// A transition a transaction may take from one status to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Transition {
+    pub direction: Direction,
+    pub from: TransactionStatus,
+    pub to: TransactionStatus,
+}
+
+/// The transitions a transaction may take, a table per direction.
+///
+/// A direction without any transition can't be disputed at all.
+/// `ChargedBack` only applies to inbound transactions and `Reversed` to outbound ones,
+/// a chargeback of a withdrawal reverses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionPolicy {
+    transitions: BTreeSet<Transition>,
+    redispute_resolved: bool,
+}
+
+impl Default for TransitionPolicy {
+    /// The built-in rules.
+    ///
+    /// Inbound transactions (deposits):
+    /// ```text
+    /// ┌─────────┐
+    /// │ Settled │
+    /// └────┬────┘
+    ///      │ dispute
+    ///      ▼
+    /// ┌──────────┐
+    /// │ Disputed │
+    /// └────┬─────┘
+    ///      │
+    ///      ├─────── resolve ────────┐
+    ///      │                        ▼
+    ///      │                   ┌──────────┐
+    ///      │                   │ Resolved │
+    ///      │                   └──────────┘
+    ///      │
+    ///      └──── chargeback ───────┐
+    ///                              ▼
+    ///                         ┌────────────┐
+    ///                         │ ChargedBack│
+    ///                         └────────────┘
+    /// ```
+    ///
+    /// Outbound transactions (withdrawals) take the same path, a chargeback reverses them (`Reversed`).
+    /// Withdrawals used to be final: forbid `Settled → Disputed` for [Direction::Outbound] to keep
+    /// rejecting their disputes.
+    ///
+    /// Valid transitions:
+    /// - Settled → Disputed
+    /// - Disputed → Resolved
+    /// - Disputed → ChargedBack (inbound) or Reversed (outbound)
+    /// - Disputed/Resolved → Disputed: another partial dispute of the undisputed rest, see
+    ///   [crate::ledger::transactions::Transaction::dispute]
+    ///
+    /// A resolved dispute can't be charged back, and the resolved part can't be disputed again.
+    fn default() -> Self {
+        let mut policy = Self::empty();
+        for (direction, charged_back) in [
+            (Direction::Inbound, TransactionStatus::ChargedBack),
+            (Direction::Outbound, TransactionStatus::Reversed),
+        ] {
+            policy = policy
+                .allow(
+                    direction,
+                    TransactionStatus::Settled,
+                    TransactionStatus::Disputed,
+                )
+                .allow(
+                    direction,
+                    TransactionStatus::Disputed,
+                    TransactionStatus::Disputed,
+                )
+                .allow(
+                    direction,
+                    TransactionStatus::Resolved,
+                    TransactionStatus::Disputed,
+                )
+                .allow(
+                    direction,
+                    TransactionStatus::Disputed,
+                    TransactionStatus::Resolved,
+                )
+                .allow(direction, TransactionStatus::Disputed, charged_back);
+        }
+        policy
+    }
+}
+
+impl TransitionPolicy {
+    /// A policy without any transition: nothing can be disputed.
+    pub fn empty() -> Self {
+        Self {
+            transitions: BTreeSet::new(),
+            redispute_resolved: false,
+        }
+    }
+
+    /// Allow the transition, eg. a chargeback straight from `Settled`.
+    pub fn allow(
+        mut self,
+        direction: Direction,
+        from: TransactionStatus,
+        to: TransactionStatus,
+    ) -> Self {
+        self.transitions.insert(Transition {
+            direction,
+            from,
+            to,
+        });
+        self
+    }
+
+    /// Forbid the transition.
+    pub fn forbid(
+        mut self,
+        direction: Direction,
+        from: TransactionStatus,
+        to: TransactionStatus,
+    ) -> Self {
+        self.transitions.remove(&Transition {
+            direction,
+            from,
+            to,
+        });
+        self
+    }
+
+    /// Whether the resolved part of a transaction can be disputed again.
+    /// Only applies if `Resolved → Disputed` is allowed.
+    pub fn with_redispute_resolved(mut self, redispute_resolved: bool) -> Self {
+        self.redispute_resolved = redispute_resolved;
+        self
+    }
+
+    pub fn allows(
+        &self,
+        direction: Direction,
+        from: TransactionStatus,
+        to: TransactionStatus,
+    ) -> bool {
+        self.transitions.contains(&Transition {
+            direction,
+            from,
+            to,
+        })
+    }
+
+    /// Whether transactions of the direction can be disputed at all.
+    pub fn has_transitions(&self, direction: Direction) -> bool {
+        self.transitions
+            .iter()
+            .any(|transition| transition.direction == direction)
+    }
+
+    pub fn redispute_resolved(&self) -> bool {
+        self.redispute_resolved
+    }
+
+    /// The allowed transitions, ordered.
+    pub fn transitions(&self) -> impl Iterator<Item = Transition> + '_ {
+        self.transitions.iter().copied()
+    }
+}
+
+/// Whether disputes and chargebacks may drive the balance of a client below zero.
+///
+/// Withdrawals never do: they always need the available funds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NegativeBalancePolicy {
+    /// A dispute of funds the client no longer has fails with `InsufficientFunds`.
+    #[default]
+    Reject,
+    /// The dispute holds the funds anyway, `available` goes negative (and `total` once charged back).
+    /// The client owes the [crate::Balance::deficit], later deposits pay it off.
+    Allow,
+}
+
+/// What happens to an event whose timestamp is older than the latest event of its client.
+///
+/// Only events with a timestamp are checked, see [crate::TimedEvent].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampPolicy {
+    /// The event fails with `TimestampOutOfOrder`.
+    #[default]
+    Reject,
+    /// The event is applied, the receipt flags it as `out_of_order`.
+    Flag,
+}
+
+/// Time limits of disputes, in seconds. There is no limit by default.
+///
+/// The time of an event is its timestamp, or the engine clock for events without one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisputeDeadlines {
+    /// A transaction can only be disputed this long after it happened, later disputes fail with
+    /// `DisputeWindowClosed`. Transactions or disputes without timestamp aren't limited.
+    pub dispute_window: Option<u64>,
+    /// A transaction stays `Disputed` this long at most, counted from the first dispute that is still held.
+    /// The engine then settles all held disputes with [Self::on_expiry], see [crate::Engine::expire_disputes].
+    /// A dispute without timestamp doesn't expire.
+    pub max_disputed: Option<u64>,
+    pub on_expiry: ExpiryAction,
+}
+
+/// How the engine settles a dispute held for longer than [DisputeDeadlines::max_disputed].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExpiryAction {
+    /// The transaction stands, as if the partner resolved the dispute.
+    #[default]
+    Resolve,
+    /// The transaction is charged back (reversed for a withdrawal).
+    Chargeback,
+}
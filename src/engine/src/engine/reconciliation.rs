@@ -0,0 +1,247 @@
+//! Reconciliation of the client balances with the ledger.
+//!
+//! The balances of the accounts are updated along with every write, nothing else ties them to the
+//! transactions. [Expected] recomputes them from the transactions stored in the ledger, with the status
+//! derived from their compensating entries, and the fees charged. [crate::Engine::reconcile] reports
+//! every balance that differs as a [Discrepancy].
+//!
+//! Per client and currency:
+//! - `total` is the deposits, minus the withdrawals, minus what was charged back, minus the fees.
+//!   A withdrawal under dispute holds its amount again, a reversed one is credited back.
+//! - `held` is the disputed (`Disputed`) part of the transactions.
+//! - `available` is the total minus the held funds.
+
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::{ClientId, Currency, Money, SignedAmount};
+use crate::ledger::transactions::{Direction, FeePosting, Transaction};
+use crate::{Balance, ClientAccount};
+
+/// Available, held and total funds of a client in one currency.
+///
+/// `held` is negative if the total drifted below the available funds, see [Balance::held].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Funds {
+    pub available: SignedAmount,
+    pub held: SignedAmount,
+    pub total: SignedAmount,
+}
+
+impl Funds {
+    /// The funds of a stored balance.
+    pub fn of(balance: &Balance) -> Self {
+        Funds {
+            available: balance.available,
+            held: balance.held(),
+            total: balance.total,
+        }
+    }
+}
+
+/// A balance that doesn't match the ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// Recomputed from the ledger.
+    pub expected: Funds,
+    /// Stored on the account, zero if the client has no account.
+    pub actual: Funds,
+}
+
+/// Result of [crate::Engine::reconcile].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reconciliation {
+    /// Number of balances (client and currency) checked.
+    pub balances: usize,
+    /// Balances that don't match the ledger, ordered by client and currency.
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    /// Whether every balance matches the ledger.
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Balances recomputed from the transactions and fees of the ledger, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Expected {
+    /// (held, total) per client and currency.
+    funds: BTreeMap<(ClientId, Currency), (Decimal, Decimal)>,
+}
+
+impl Expected {
+    pub fn of<'a>(
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+        fees: impl IntoIterator<Item = &'a FeePosting>,
+    ) -> Self {
+        let mut expected = Expected::default();
+        for transaction in transactions {
+            let info = transaction.info();
+            let disputes = info.disputes;
+            let (held, total) = expected.entry(info.client_id, info.amount);
+            let amount = info.amount.amount.as_decimal();
+            *held += disputes.held.as_decimal();
+            *total += match transaction.direction() {
+                Direction::Inbound => amount - disputes.charged_back.as_decimal(),
+                Direction::Outbound => {
+                    disputes.held.as_decimal() + disputes.charged_back.as_decimal() - amount
+                }
+            };
+        }
+        for fee in fees {
+            let (_, total) = expected.entry(fee.client_id, fee.amount);
+            *total -= fee.amount.amount.as_decimal();
+        }
+        expected
+    }
+
+    fn entry(&mut self, client_id: ClientId, money: Money) -> &mut (Decimal, Decimal) {
+        self.funds.entry((client_id, money.currency)).or_default()
+    }
+
+    /// The expected funds of a client in the currency, zero if the ledger has nothing for it.
+    pub fn funds(&self, client_id: ClientId, currency: Currency) -> Funds {
+        let (held, total) = self
+            .funds
+            .get(&(client_id, currency))
+            .copied()
+            .unwrap_or_default();
+        Funds {
+            available: SignedAmount::from(total - held),
+            held: SignedAmount::from(held),
+            total: SignedAmount::from(total),
+        }
+    }
+
+    /// Check the balances of the accounts against the expected ones.
+    ///
+    /// Balances of clients or currencies the ledger has nothing for must be zero.
+    pub fn reconcile<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = &'a ClientAccount>,
+    ) -> Reconciliation {
+        let mut actual = BTreeMap::new();
+        for account in accounts {
+            for (currency, balance) in &account.balances {
+                actual.insert((account.client_id, *currency), Funds::of(balance));
+            }
+        }
+        let keys = self
+            .funds
+            .keys()
+            .chain(actual.keys())
+            .copied()
+            .collect::<std::collections::BTreeSet<_>>();
+
+        let discrepancies = keys
+            .iter()
+            .filter_map(|&(client_id, currency)| {
+                let expected = self.funds(client_id, currency);
+                let actual = actual
+                    .get(&(client_id, currency))
+                    .copied()
+                    .unwrap_or_default();
+                (expected != actual).then_some(Discrepancy {
+                    client_id,
+                    currency,
+                    expected,
+                    actual,
+                })
+            })
+            .collect();
+
+        Reconciliation {
+            balances: keys.len(),
+            discrepancies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::policy::TransitionPolicy;
+    use crate::engine::types::{Amount, Timestamp, TransactionId};
+    use crate::ledger::transactions::TransactionStatus;
+
+    #[test]
+    fn expected_funds() {
+        let client_id = ClientId::from(1);
+        let money = |minor| Money::from(Amount::from_minor(minor));
+        let mut deposit =
+            Transaction::new_settled_inbound(TransactionId::from(1), client_id, money(1_000), None);
+        deposit
+            .dispute(
+                &TransitionPolicy::default(),
+                Some(Amount::from_minor(300)),
+                None,
+            )
+            .unwrap();
+        let mut withdrawal =
+            Transaction::new_settled_outbound(TransactionId::from(2), client_id, money(200), None);
+        withdrawal
+            .dispute(&TransitionPolicy::default(), None, None)
+            .unwrap();
+        withdrawal
+            .settle_dispute(
+                &TransitionPolicy::default(),
+                TransactionStatus::Reversed,
+                None,
+            )
+            .unwrap();
+        let fee = FeePosting {
+            client_id,
+            transaction_id: TransactionId::from(1),
+            event: crate::fees::FeeEvent::Deposit,
+            amount: money(10),
+            timestamp: Timestamp::from(0),
+        };
+
+        let expected = Expected::of([&deposit, &withdrawal], [&fee]);
+        let funds = Funds {
+            available: Amount::from_minor(690).into(),
+            held: Amount::from_minor(300).into(),
+            total: Amount::from_minor(990).into(),
+        };
+        assert_eq!(expected.funds(client_id, Currency::NONE), funds);
+
+        let mut account = ClientAccount::new(client_id);
+        *account.balance_mut(Currency::NONE) = Balance {
+            available: funds.available,
+            total: funds.total,
+        };
+        assert_eq!(
+            expected.reconcile([&account]),
+            Reconciliation {
+                balances: 1,
+                discrepancies: vec![],
+            }
+        );
+
+        // held above the total: reported instead of a panic
+        account.balance_mut(Currency::NONE).available = Amount::from_minor(1_000).into();
+        assert_eq!(
+            crate::Balances::from(account.balance(Currency::NONE)).held,
+            SignedAmount::from(Decimal::new(-10, 2))
+        );
+        let reconciliation = expected.reconcile([&account]);
+        assert_eq!(
+            reconciliation.discrepancies,
+            vec![Discrepancy {
+                client_id,
+                currency: Currency::NONE,
+                expected: funds,
+                actual: Funds {
+                    available: Amount::from_minor(1_000).into(),
+                    held: SignedAmount::from(Decimal::new(-10, 2)),
+                    total: funds.total,
+                },
+            }]
+        );
+    }
+}
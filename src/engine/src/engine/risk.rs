@@ -0,0 +1,203 @@
+//! Risk rules checked before a deposit or withdrawal is applied.
+//!
+//! The [RiskRules] of a client are part of its [crate::policy::Policy]. Each rule sees the transaction
+//! and the state of the client (see [RiskCheck]), a rule that fires rejects the transaction with
+//! `EngineError::RiskRejected` naming it. Nothing is changed by a rejected transaction.
+//!
+//! Limits are in units of the currency of the transaction.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+
+use crate::ClientAccount;
+use crate::engine::types::{Amount, ClientId, Money, Timestamp, TransactionId};
+use crate::ledger::transactions::Direction;
+
+/// A deposit or withdrawal about to be applied, with what a rule may need to know about its client.
+#[derive(Debug, Clone, Copy)]
+pub struct RiskCheck<'a> {
+    pub client_id: ClientId,
+    pub transaction_id: TransactionId,
+    /// `Inbound` for a deposit, `Outbound` for a withdrawal.
+    pub direction: Direction,
+    pub amount: Money,
+    /// The account of the client before the transaction.
+    pub account: &'a ClientAccount,
+    /// Time of the transaction.
+    pub at: Timestamp,
+    /// Times of the previous withdrawals of the client, as far back as the rules look (see [RiskRule::lookback]).
+    pub recent_withdrawals: &'a [Timestamp],
+    /// Whether the transaction was approved after a review, see [crate::Event::Approve].
+    pub reviewed: bool,
+}
+
+pub trait RiskRule: Debug + Send + Sync {
+    /// Name of the rule, reported in `EngineError::RiskRejected`.
+    fn name(&self) -> &str;
+
+    /// Returns the reason to reject the transaction, if the rule fires.
+    fn check(&self, check: &RiskCheck) -> Result<(), String>;
+
+    /// How far back (in seconds) the rule looks at past withdrawals, see [RiskCheck::recent_withdrawals].
+    fn lookback(&self) -> u64 {
+        0
+    }
+}
+
+/// Rules checked in order, the first one that fires rejects the transaction. None by default.
+#[derive(Debug, Clone, Default)]
+pub struct RiskRules(Vec<Arc<dyn RiskRule>>);
+
+impl RiskRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: impl RiskRule + 'static) -> Self {
+        self.0.push(Arc::new(rule));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The first rule that fires with its reason.
+    pub fn check(&self, check: &RiskCheck) -> Result<(), (&dyn RiskRule, String)> {
+        for rule in &self.0 {
+            rule.check(check)
+                .map_err(|reason| (rule.as_ref(), reason))?;
+        }
+        Ok(())
+    }
+
+    /// How far back the rules look at past withdrawals.
+    pub fn lookback(&self) -> u64 {
+        self.0.iter().map(|rule| rule.lookback()).max().unwrap_or(0)
+    }
+}
+
+/// Rules are shared, not compared: two lists are equal if they hold the same rules.
+impl PartialEq for RiskRules {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|(rule, other)| Arc::ptr_eq(rule, other))
+    }
+}
+
+impl Eq for RiskRules {}
+
+/// Deposits and withdrawals over `max` are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxAmount {
+    pub max: Amount,
+}
+
+impl RiskRule for MaxAmount {
+    fn name(&self) -> &str {
+        "max_amount"
+    }
+
+    fn check(&self, check: &RiskCheck) -> Result<(), String> {
+        if check.amount.amount > self.max {
+            return Err(format!(
+                "{} exceeds the maximum of {}",
+                check.amount, self.max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A client makes at most `max_count` withdrawals within `window` seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawalVelocity {
+    pub max_count: usize,
+    pub window: u64,
+}
+
+impl RiskRule for WithdrawalVelocity {
+    fn name(&self) -> &str {
+        "withdrawal_velocity"
+    }
+
+    fn check(&self, check: &RiskCheck) -> Result<(), String> {
+        if check.direction != Direction::Outbound {
+            return Ok(());
+        }
+        let recent = check
+            .recent_withdrawals
+            .iter()
+            .filter(|at| at.after(self.window) > check.at)
+            .count();
+        if recent >= self.max_count {
+            return Err(format!(
+                "{recent} withdrawals within the last {} seconds already",
+                self.window
+            ));
+        }
+        Ok(())
+    }
+
+    fn lookback(&self) -> u64 {
+        self.window
+    }
+}
+
+/// A withdrawal takes at most `percent` of the available funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxWithdrawalPercent {
+    pub percent: Decimal,
+}
+
+impl RiskRule for MaxWithdrawalPercent {
+    fn name(&self) -> &str {
+        "max_withdrawal_percent"
+    }
+
+    fn check(&self, check: &RiskCheck) -> Result<(), String> {
+        if check.direction != Direction::Outbound {
+            return Ok(());
+        }
+        let available = check.account.available(check.amount.currency);
+        let max = available.amount.as_decimal() * self.percent / Decimal::ONE_HUNDRED;
+        if check.amount.amount.as_decimal() > max {
+            return Err(format!(
+                "{} exceeds {}% of the available {available}",
+                check.amount, self.percent
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Deposits over `threshold` are held for review: rejected until approved, see [crate::Event::Approve].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReviewThreshold {
+    pub threshold: Amount,
+}
+
+impl RiskRule for ReviewThreshold {
+    fn name(&self) -> &str {
+        "review_threshold"
+    }
+
+    fn check(&self, check: &RiskCheck) -> Result<(), String> {
+        if check.direction == Direction::Inbound
+            && check.amount.amount > self.threshold
+            && !check.reviewed
+        {
+            return Err(format!(
+                "{} exceeds {}, held for review",
+                check.amount, self.threshold
+            ));
+        }
+        Ok(())
+    }
+}
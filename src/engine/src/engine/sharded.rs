@@ -0,0 +1,563 @@
+//! Concurrent processing of independent clients.
+//!
+//! Clients don't share any state, so events are routed by `ClientId` to one of N shards.
+//! Each shard is a worker task owning its own [Engine] and processes its events in order,
+//! which keeps the per-client ordering of the input.
+//!
+//! Transaction ids are the only global constraint (a transaction belongs to a single client),
+//! but every shard only sees its own ledger. The router keeps track of which client claimed
+//! each transaction id, see [TransactionClaims].
+//!
+//! Events are applied asynchronously, so each event carries a caller defined context (eg. its
+//! position in the input) which is given back along with the event if it is rejected or flagged
+//! out of order, see [ShardReport].
+
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::engine::policy::Policies;
+use crate::engine::types::{Amount, ClientId, Currency, Timestamp, TransactionId};
+use crate::errors::{EngineError, SystemFault};
+use crate::ledger::Ledger;
+use crate::{ClientAccount, Engine, Event, TimedEvent};
+
+/// Events buffered per shard before the router waits for the worker.
+const SHARD_QUEUE_SIZE: usize = 1024;
+
+type OnReport<C> = dyn Fn(Event, C, ShardReport) + Send + Sync;
+
+/// What a shard reports about an event, along with the event and its context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardReport {
+    /// Rejected due to a partner error.
+    Rejected(EngineError),
+    /// Applied, but older than the latest event of its client, see [crate::Receipt::out_of_order].
+    OutOfOrder,
+}
+
+/// Result of [ShardedEngine::finish].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finished {
+    /// The client accounts of all shards, sorted by client ID.
+    pub accounts: Vec<ClientAccount>,
+    /// Balances of the house account per currency, the fees charged on all shards (see [Engine::revenue]).
+    pub revenue: BTreeMap<Currency, Amount>,
+}
+
+/// Engine processing clients concurrently, see the module docs.
+///
+/// The end state is identical to applying the same events sequentially on a single [Engine].
+pub struct ShardedEngine<L, C = ()> {
+    shards: Vec<mpsc::Sender<(TimedEvent, C)>>,
+    workers: Vec<JoinHandle<Result<Engine<L>, EngineError>>>,
+    claims: Arc<TransactionClaims>,
+    on_report: Arc<OnReport<C>>,
+    /// Latest timestamp given with an event, the shards expire their disputes at that time on finish.
+    latest_timestamp: Option<Timestamp>,
+}
+
+impl<L: Ledger + Send + Sync + 'static, C: Send + 'static> ShardedEngine<L, C> {
+    /// Spawn `shards` workers on the current tokio runtime, each with an engine on a ledger from `ledger`.
+    ///
+    /// Events rejected due to a partner error or flagged out of order are given to `on_report`
+    /// with their context, processing continues.
+    /// A system error stops the processing, it is returned by [Self::apply] or [Self::finish].
+    pub async fn new(
+        shards: NonZeroUsize,
+        ledger: impl FnMut() -> L,
+        on_report: impl Fn(Event, C, ShardReport) + Send + Sync + 'static,
+    ) -> Result<Self, EngineError> {
+        Self::with_policies(shards, ledger, Policies::default(), on_report).await
+    }
+
+    /// Same as [Self::new], each shard applies the given policies.
+    pub async fn with_policies(
+        shards: NonZeroUsize,
+        mut ledger: impl FnMut() -> L,
+        policies: Policies,
+        on_report: impl Fn(Event, C, ShardReport) + Send + Sync + 'static,
+    ) -> Result<Self, EngineError> {
+        let claims = Arc::new(TransactionClaims::default());
+        let on_report: Arc<OnReport<C>> = Arc::new(on_report);
+
+        let mut senders = Vec::with_capacity(shards.get());
+        let mut workers = Vec::with_capacity(shards.get());
+        for _ in 0..shards.get() {
+            let engine = Engine::new(ledger()).await?.with_policies(policies.clone());
+            let (sender, receiver) = mpsc::channel(SHARD_QUEUE_SIZE);
+            senders.push(sender);
+            workers.push(tokio::spawn(run_shard(
+                engine,
+                receiver,
+                claims.clone(),
+                on_report.clone(),
+            )));
+        }
+
+        Ok(Self {
+            shards: senders,
+            workers,
+            claims,
+            on_report,
+            latest_timestamp: None,
+        })
+    }
+
+    /// Route an event to the shard of its client.
+    ///
+    /// Returns once the event is queued, its outcome is only known by the shard.
+    pub async fn apply(
+        &mut self,
+        event: impl Into<TimedEvent>,
+        context: C,
+    ) -> Result<(), EngineError> {
+        let timed = event.into();
+        let event = timed.event;
+        self.latest_timestamp = self.latest_timestamp.max(timed.timestamp);
+        // Validated here as well, so invalid events don't claim a transaction id.
+        if let Err(err) = timed.validate() {
+            (self.on_report)(event, context, ShardReport::Rejected(err));
+            return Ok(());
+        }
+
+        if let Some(transaction_id) = claimed_transaction(&event) {
+            let claim = self.claims.claim(event.client_id(), transaction_id).await;
+            match claim {
+                Ok(()) => {}
+                Err(err) if err.is_partner_error() => {
+                    (self.on_report)(event, context, ShardReport::Rejected(err));
+                    return Ok(());
+                }
+                Err(_) => return Err(self.finish_with_error().await),
+            }
+        }
+
+        let shard = event.client_id().as_inner() as usize % self.shards.len();
+        if self.shards[shard].send((timed, context)).await.is_err() {
+            // the worker stopped on a system error
+            return Err(self.finish_with_error().await);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for all the queued events to be processed.
+    ///
+    /// Each shard then settles the disputes that expired by the latest timestamp of the events,
+    /// as a single engine does at the end of a batch (see [Engine::expire_disputes]).
+    ///
+    /// Returns the client accounts and the revenue of all shards.
+    pub async fn finish(self) -> Result<Finished, EngineError> {
+        drop(self.shards);
+
+        let mut accounts = vec![];
+        let mut revenue = BTreeMap::<_, Amount>::new();
+        for worker in self.workers {
+            let mut engine = worker
+                .await
+                .map_err(|_| EngineError::system(SystemFault::ShardPanicked))??;
+            if let Some(latest) = self.latest_timestamp {
+                engine.expire_disputes(latest).await?;
+            }
+            accounts.extend(engine.accounts().cloned());
+            for (currency, amount) in engine.revenue() {
+                *revenue.entry(*currency).or_default() += *amount;
+            }
+        }
+        accounts.sort_by_key(|account| account.client_id);
+
+        Ok(Finished { accounts, revenue })
+    }
+
+    async fn finish_with_error(&mut self) -> EngineError {
+        self.shards.clear();
+        for worker in self.workers.drain(..) {
+            match worker.await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return err,
+                Err(_) => return EngineError::system(SystemFault::ShardPanicked),
+            }
+        }
+
+        EngineError::system(SystemFault::ShardStopped)
+    }
+}
+
+async fn run_shard<L: Ledger, C>(
+    mut engine: Engine<L>,
+    mut events: mpsc::Receiver<(TimedEvent, C)>,
+    claims: Arc<TransactionClaims>,
+    on_report: Arc<OnReport<C>>,
+) -> Result<Engine<L>, EngineError> {
+    while let Some((timed, context)) = events.recv().await {
+        let result = engine.apply(timed).await;
+        let event = timed.event;
+
+        if let Some(transaction_id) = claimed_transaction(&event) {
+            claims.settle(event.client_id(), transaction_id, result.is_ok());
+        }
+
+        match result {
+            Ok(receipt) if receipt.out_of_order => {
+                on_report(event, context, ShardReport::OutOfOrder)
+            }
+            Ok(_) => {}
+            Err(err) if err.is_partner_error() => {
+                on_report(event, context, ShardReport::Rejected(err))
+            }
+            Err(err) => {
+                claims.close();
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(engine)
+}
+
+/// The transaction id an event creates, if any.
+fn claimed_transaction(event: &Event) -> Option<TransactionId> {
+    match event {
+        Event::Deposit { transaction_id, .. } | Event::Withdraw { transaction_id, .. } => {
+            Some(*transaction_id)
+        }
+        Event::Dispute { .. }
+        | Event::Resolve { .. }
+        | Event::Chargeback { .. }
+        | Event::Unlock { .. }
+        | Event::Freeze { .. }
+        | Event::Close { .. }
+        | Event::Approve { .. } => None,
+    }
+}
+
+/// Global index of transaction id -> client, shared by the router and the shards.
+///
+/// The router claims the transaction id of a deposit/withdrawal before routing it.
+/// The shard settles the claim once the event is processed: it is committed if the
+/// transaction was added, released otherwise.
+///
+/// An event claiming a transaction id which is pending for another client waits until that claim
+/// is settled. That way conflicts are resolved in input order, like in sequential processing.
+#[derive(Debug, Default)]
+struct TransactionClaims {
+    claims: Mutex<HashMap<TransactionId, Claim>>,
+    settled: Notify,
+    /// Set when a shard stopped, its pending claims will never be settled.
+    closed: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Claim {
+    /// Events of the client using this transaction id are still queued.
+    Pending { client_id: ClientId, events: usize },
+    /// The transaction is in the ledger of the client's shard.
+    Committed(ClientId),
+}
+
+impl TransactionClaims {
+    async fn claim(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), EngineError> {
+        loop {
+            // registered before checking, so a settle in between isn't missed
+            let settled = self.settled.notified();
+            tokio::pin!(settled);
+            settled.as_mut().enable();
+
+            if self.closed.load(Ordering::Acquire) {
+                return Err(EngineError::system(SystemFault::ShardStopped));
+            }
+
+            {
+                let mut claims = self.claims.lock().expect("claims lock poisoned");
+                match claims.get_mut(&transaction_id) {
+                    None => {
+                        claims.insert(
+                            transaction_id,
+                            Claim::Pending {
+                                client_id,
+                                events: 1,
+                            },
+                        );
+                        return Ok(());
+                    }
+                    Some(Claim::Pending {
+                        client_id: owner,
+                        events,
+                    }) if *owner == client_id => {
+                        *events += 1;
+                        return Ok(());
+                    }
+                    Some(Claim::Committed(owner)) if *owner == client_id => return Ok(()),
+                    // Same error as the ledger gives for a sequential engine.
+                    Some(Claim::Committed(_)) => {
+                        return Err(EngineError::TransactionOfDifferentClient {
+                            client_id,
+                            transaction_id,
+                        });
+                    }
+                    Some(Claim::Pending { .. }) => {}
+                }
+            }
+
+            settled.await;
+        }
+    }
+
+    fn settle(&self, client_id: ClientId, transaction_id: TransactionId, added: bool) {
+        let mut claims = self.claims.lock().expect("claims lock poisoned");
+        if let Some(Claim::Pending { events, .. }) = claims.get_mut(&transaction_id) {
+            if added {
+                claims.insert(transaction_id, Claim::Committed(client_id));
+            } else if *events > 1 {
+                *events -= 1;
+            } else {
+                claims.remove(&transaction_id);
+            }
+        }
+        drop(claims);
+
+        self.settled.notify_waiters();
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.settled.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::fees::{Fee, FeeEvent, FeeRule, FeeSchedule};
+    use crate::engine::policy::DisputeDeadlines;
+    use crate::engine::policy::{Policy, TimestampPolicy};
+    use crate::ledger::in_memory::InMemoryLedger;
+    use rust_decimal::Decimal;
+
+    fn deposit(client: u16, tx: u32, minor: u32) -> Event {
+        Event::Deposit {
+            client_id: ClientId::from(client),
+            transaction_id: TransactionId::from(tx),
+            amount: Amount::from_minor(minor).into(),
+        }
+    }
+
+    fn withdraw(client: u16, tx: u32, minor: u32) -> Event {
+        Event::Withdraw {
+            client_id: ClientId::from(client),
+            transaction_id: TransactionId::from(tx),
+            amount: Amount::from_minor(minor).into(),
+        }
+    }
+
+    /// Apply the events on a sharded and on a sequential engine, both must end in the same state
+    /// (accounts and revenue) and report the same events. Returns the accounts.
+    async fn assert_same_as_sequential(
+        events: Vec<TimedEvent>,
+        policies: Policies,
+    ) -> Vec<ClientAccount> {
+        let mut sequential = Engine::new(InMemoryLedger::new())
+            .await
+            .unwrap()
+            .with_policies(policies.clone());
+        let mut expected_reports = vec![];
+        for (index, event) in events.iter().enumerate() {
+            match sequential.apply(*event).await {
+                Ok(receipt) if receipt.out_of_order => {
+                    expected_reports.push((index, ShardReport::OutOfOrder))
+                }
+                Ok(_) => {}
+                Err(err) => expected_reports.push((index, ShardReport::Rejected(err))),
+            }
+        }
+        if let Some(latest) = sequential.latest_timestamp() {
+            sequential.expire_disputes(latest).await.unwrap();
+        }
+        let expected = Finished {
+            accounts: sequential.accounts_ordered().into_iter().cloned().collect(),
+            revenue: sequential.revenue().clone(),
+        };
+
+        let reports = Arc::new(Mutex::new(vec![]));
+        let on_report = {
+            let reports = reports.clone();
+            move |_: Event, index: usize, report: ShardReport| {
+                reports.lock().unwrap().push((index, report))
+            }
+        };
+        let shards = NonZeroUsize::new(4).unwrap();
+        let mut sharded =
+            ShardedEngine::with_policies(shards, InMemoryLedger::new, policies, on_report)
+                .await
+                .unwrap();
+        for (index, event) in events.into_iter().enumerate() {
+            sharded.apply(event, index).await.unwrap();
+        }
+
+        assert_eq!(sharded.finish().await.unwrap(), expected);
+        let mut reports = reports.lock().unwrap().clone();
+        reports.sort_by_key(|(index, _)| *index);
+        assert_eq!(reports, expected_reports);
+
+        expected.accounts
+    }
+
+    fn untimed(events: Vec<Event>) -> Vec<TimedEvent> {
+        events.into_iter().map(TimedEvent::from).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn many_clients() {
+        let mut events = vec![];
+        for tx in 0..2000u32 {
+            let client = (tx % 37) as u16;
+            events.push(deposit(client, tx * 2, 100 + tx));
+            events.push(withdraw(client, tx * 2 + 1, 250));
+            if tx % 5 == 0 {
+                events.push(Event::Dispute {
+                    client_id: ClientId::from(client),
+                    transaction_id: TransactionId::from(tx * 2),
+                    amount: None,
+                });
+            }
+        }
+
+        assert_same_as_sequential(untimed(events), Policies::default()).await;
+    }
+
+    /// The first client to use a transaction id owns it, whichever shard gets to it first.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn transaction_id_is_globally_unique() {
+        let events = vec![
+            deposit(1, 1, 100),
+            deposit(2, 1, 500),  // conflict
+            withdraw(3, 2, 100), // insufficient funds, doesn't claim tx 2
+            deposit(4, 2, 700),
+            deposit(5, 2, 700), // conflict
+        ];
+        assert_same_as_sequential(untimed(events), Policies::default()).await;
+    }
+
+    /// Events older than the latest one of their client are flagged by the shards as well.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn out_of_order_is_reported() {
+        let policies = Policies::new(Policy {
+            timestamps: TimestampPolicy::Flag,
+            ..Policy::default()
+        });
+        let at = |event: Event, secs: u64| event.at(Timestamp::from(secs));
+        let events = vec![
+            at(deposit(1, 1, 100), 1_000),
+            at(deposit(2, 2, 100), 1_000),
+            at(deposit(1, 3, 100), 900),  // out of order
+            at(withdraw(2, 4, 500), 800), // out of order, rejected for insufficient funds
+            at(deposit(2, 5, 100), 1_100),
+            deposit(1, 6, 100).into(),
+        ];
+        assert_same_as_sequential(events, policies).await;
+    }
+
+    /// The fees charged on every shard add up to the revenue.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn revenue_of_all_shards() {
+        let policies = Policies::new(Policy {
+            fees: FeeSchedule::new().with_rule(FeeRule {
+                event: FeeEvent::Deposit,
+                tier: None,
+                currency: None,
+                fee: Fee {
+                    fixed: Amount::from_minor(1),
+                    percent: Decimal::ZERO,
+                },
+            }),
+            ..Policy::default()
+        });
+        let events = (1..=8)
+            .map(|client| deposit(client, client.into(), 100).into())
+            .collect();
+        assert_same_as_sequential(events, policies).await;
+    }
+
+    /// Disputes of clients without later events expire on finish.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn disputes_expire_on_finish() {
+        let policies = Policies::new(Policy {
+            deadlines: DisputeDeadlines {
+                max_disputed: Some(50),
+                ..DisputeDeadlines::default()
+            },
+            ..Policy::default()
+        });
+        let at = |event: Event, secs: u64| event.at(Timestamp::from(secs));
+        let dispute = |client: u16, tx: u32| Event::Dispute {
+            client_id: ClientId::from(client),
+            transaction_id: TransactionId::from(tx),
+            amount: None,
+        };
+        let events = vec![
+            at(deposit(1, 1, 100), 1_000),
+            at(dispute(1, 1), 1_010),
+            at(deposit(2, 2, 100), 1_000),
+            at(dispute(2, 2), 1_050),
+            at(deposit(3, 3, 100), 1_060),
+        ];
+        let accounts = assert_same_as_sequential(events, policies).await;
+
+        let held = |client: usize| accounts[client].balance(Currency::NONE).held();
+        assert_eq!(held(0), Amount::default().into());
+        assert_eq!(held(1), Amount::from_minor(100).into());
+    }
+
+    #[tokio::test]
+    async fn rejections_are_reported() {
+        let rejected = Arc::new(Mutex::new(vec![]));
+        let on_report = {
+            let rejected = rejected.clone();
+            move |_: Event, line: u32, report: ShardReport| {
+                rejected.lock().unwrap().push((line, report))
+            }
+        };
+
+        let shards = NonZeroUsize::new(2).unwrap();
+        let mut sharded = ShardedEngine::new(shards, InMemoryLedger::new, on_report)
+            .await
+            .unwrap();
+        sharded.apply(deposit(1, 1, 100), 1).await.unwrap();
+        sharded.apply(deposit(2, 1, 100), 2).await.unwrap();
+        sharded.apply(withdraw(3, 2, 100), 3).await.unwrap();
+        sharded.finish().await.unwrap();
+
+        let mut rejected = rejected.lock().unwrap().clone();
+        rejected.sort_by_key(|(line, _)| *line);
+        assert_eq!(
+            rejected,
+            vec![
+                (
+                    2,
+                    ShardReport::Rejected(EngineError::TransactionOfDifferentClient {
+                        client_id: ClientId::from(2),
+                        transaction_id: TransactionId::from(1),
+                    })
+                ),
+                (
+                    3,
+                    ShardReport::Rejected(EngineError::InsufficientFunds {
+                        client_id: ClientId::from(3),
+                        transaction_id: TransactionId::from(2),
+                        requested: Amount::from_minor(100).into(),
+                        available: Amount::default().into(),
+                    })
+                ),
+            ]
+        );
+    }
+}
@@ -29,16 +29,27 @@ macro_rules! wrapper_type {
 
 wrapper_type!(ClientId, u16);
 wrapper_type!(TransactionId, u32);
+// Seconds since the Unix epoch, see [crate::clock::Clock].
+wrapper_type!(Timestamp, u64);
+
+impl Timestamp {
+    /// The time `secs` seconds later, saturating at the end of time.
+    pub fn after(self, secs: u64) -> Self {
+        Timestamp(self.0.saturating_add(secs))
+    }
+}
 
 /// Represents a monetary amount.
 ///
 /// Can be constructed from minor units (e.g., cents) using `from_minor`.
 /// Or from a [rust_decimal::Decimal] directly - which allows negative amount.
 ///
-/// Internaly uses `rust_decimal::Decimal` for precise decimal representation
-/// and stores upto 4 decimal places.
+/// Internaly uses `rust_decimal::Decimal` for precise decimal representation.
+/// The precision of an amount comes from its currency: a [Money] is rounded to the minor units of
+/// its [Currency] (eg. 0 decimals for JPY, 3 for BHD, 4 without currency). An amount on its own,
+/// eg. a partial dispute, keeps up to 4 decimal places until it is rounded to the currency of its transaction.
 ///
-/// Exposes `checked_sub` method to safely subtract another amount without going negative.
+/// Exposes `try_subtract` method to safely subtract another amount without going negative.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Amount(rust_decimal::Decimal);
 
@@ -96,6 +107,178 @@ impl std::ops::AddAssign for Amount {
     }
 }
 
+/// A balance that may go below zero, eg. when a dispute holds funds the client already withdrew
+/// (see [crate::policy::NegativeBalancePolicy]).
+///
+/// Transaction amounts are [Amount]s, only balances are signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct SignedAmount(rust_decimal::Decimal);
+
+impl Display for SignedAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.4}", self.0)
+    }
+}
+
+impl SignedAmount {
+    pub fn as_decimal(&self) -> rust_decimal::Decimal {
+        self.0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    /// How far the balance is below zero, zero if it isn't negative.
+    pub fn deficit(&self) -> Amount {
+        Amount(self.0.min(rust_decimal::Decimal::ZERO).abs())
+    }
+
+    /// Subtract an amount (in-place) only if the balance does not go below 0.
+    /// Returns None if the subtraction would result in a negative value.
+    pub fn try_subtract(&mut self, other: Amount) -> Option<()> {
+        let value = self.0.checked_sub(other.0)?;
+        if value.is_sign_negative() && !value.is_zero() {
+            return None;
+        }
+        self.0 = value;
+        Some(())
+    }
+}
+
+impl From<Amount> for SignedAmount {
+    fn from(amount: Amount) -> Self {
+        SignedAmount(amount.0)
+    }
+}
+
+impl From<rust_decimal::Decimal> for SignedAmount {
+    fn from(value: rust_decimal::Decimal) -> Self {
+        SignedAmount(value.round_dp(4))
+    }
+}
+
+impl std::ops::AddAssign<Amount> for SignedAmount {
+    fn add_assign(&mut self, other: Amount) {
+        self.0 += other.0;
+    }
+}
+
+/// May go below zero, see [SignedAmount::try_subtract] to keep it non-negative.
+impl std::ops::SubAssign<Amount> for SignedAmount {
+    fn sub_assign(&mut self, other: Amount) {
+        self.0 -= other.0;
+    }
+}
+
+/// ISO 4217 currency code, eg. `EUR`.
+///
+/// [Currency::NONE] (`XXX`, "no currency" in ISO 4217) is used for amounts without a currency,
+/// it keeps the engine's 4 decimal places precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const NONE: Currency = Currency(*b"XXX");
+
+    /// Parse a 3 letters currency code, case insensitive.
+    pub fn new(code: &str) -> Option<Self> {
+        let code: [u8; 3] = code.as_bytes().try_into().ok()?;
+        code.iter()
+            .all(u8::is_ascii_alphabetic)
+            .then(|| Currency(code.map(|c| c.to_ascii_uppercase())))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ascii letters are accepted by `new`
+        std::str::from_utf8(&self.0).expect("currency code is ascii")
+    }
+
+    /// Number of decimal places of the currency's minor unit (eg. cents of EUR).
+    /// Currencies not listed have 2.
+    pub fn minor_units(&self) -> u32 {
+        match self.as_str() {
+            "XXX" => 4,
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::NONE
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An [Amount] in a [Currency], rounded to the precision of the currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: Currency,
+}
+
+impl Money {
+    /// Rounds the value to the minor unit of the currency (eg. 0 decimals for JPY).
+    pub fn new(value: rust_decimal::Decimal, currency: Currency) -> Self {
+        Self {
+            amount: Amount(value.round_dp(currency.minor_units())),
+            currency,
+        }
+    }
+
+    /// Storage representation: `<amount>` for [Currency::NONE], `<amount> <currency>` otherwise.
+    pub(crate) fn encode(&self) -> String {
+        match self.currency {
+            Currency::NONE => self.amount.as_decimal().to_string(),
+            currency => format!("{} {currency}", self.amount.as_decimal()),
+        }
+    }
+
+    /// Parses the representation of [Money::encode].
+    pub(crate) fn decode(value: &str) -> Option<Self> {
+        let (amount, currency) = match value.split_once(' ') {
+            Some((amount, currency)) => (amount, Currency::new(currency)?),
+            None => (value, Currency::NONE),
+        };
+        let amount = amount.parse::<rust_decimal::Decimal>().ok()?;
+
+        Some(Money::new(amount, currency))
+    }
+}
+
+/// An amount without currency.
+impl From<Amount> for Money {
+    fn from(amount: Amount) -> Self {
+        Self {
+            amount,
+            currency: Currency::NONE,
+        }
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.currency {
+            Currency::NONE => write!(f, "{}", self.amount),
+            currency => write!(
+                f,
+                "{:.*} {currency}",
+                currency.minor_units() as usize,
+                self.amount.as_decimal()
+            ),
+        }
+    }
+}
+
 #[cfg(test)]
 mod amount_tests {
     use super::*;
@@ -125,10 +308,63 @@ mod amount_tests {
         assert_eq!(a1.in_minor().unwrap(), 1);
     }
 
+    #[test]
+    fn signed_amount_deficit() {
+        let mut balance = SignedAmount::from(Amount::from_minor(100));
+        assert!(balance.try_subtract(Amount::from_minor(150)).is_none());
+        assert_eq!(balance.deficit(), Amount::default());
+
+        balance -= Amount::from_minor(150);
+        assert!(balance.is_negative());
+        assert_eq!(balance.as_decimal(), Decimal::new(-50, 2));
+        assert_eq!(balance.deficit(), Amount::from_minor(50));
+
+        balance += Amount::from_minor(50);
+        assert!(!balance.is_negative());
+        assert_eq!(balance.deficit(), Amount::default());
+    }
+
     #[test]
     fn display_upto_4_decimals() {
         let a1 = Amount::from(rust_decimal::Decimal::new(123456, 4)); // 0.01
 
         assert_eq!(a1.to_string(), "12.3456");
     }
+
+    #[test]
+    fn currency_precision() {
+        let jpy = Currency::new("jpy").unwrap();
+        let bhd = Currency::new("BHD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+
+        assert_eq!(
+            Money::new(Decimal::new(12345, 1), jpy).to_string(),
+            "1234 JPY"
+        );
+        assert_eq!(
+            Money::new(Decimal::new(12345, 4), bhd).to_string(),
+            "1.234 BHD"
+        );
+        assert_eq!(
+            Money::new(Decimal::new(12345, 4), eur).to_string(),
+            "1.23 EUR"
+        );
+        assert_eq!(
+            Money::new(Decimal::new(12345, 4), Currency::NONE).to_string(),
+            "1.2345"
+        );
+        assert_eq!(Currency::new("EURO"), None);
+        assert_eq!(Currency::new("E1R"), None);
+    }
+
+    #[test]
+    fn money_encoding_round_trip() {
+        let eur = Money::new(Decimal::new(150, 2), Currency::new("EUR").unwrap());
+        let none = Money::from(Amount::from_minor(150));
+
+        assert_eq!(eur.encode(), "1.50 EUR");
+        assert_eq!(Money::decode(&eur.encode()), Some(eur));
+        assert_eq!(none.encode(), "1.50");
+        assert_eq!(Money::decode(&none.encode()), Some(none));
+    }
 }
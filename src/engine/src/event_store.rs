@@ -0,0 +1,72 @@
+//! Traits and implementations for the append-only store of events (event store).
+//!
+//! Every event given to [crate::Engine::apply] is recorded with a sequence number,
+//! together with the reason it was rejected (if it was).
+//! The accepted events are enough to rebuild the client accounts and the ledger,
+//! see [crate::Engine::rebuild_from].
+
+use std::fmt::Debug;
+
+use crate::engine::types::Timestamp;
+use crate::{Event, TimedEvent};
+
+pub mod file;
+pub mod in_memory;
+
+pub trait EventStore: Debug {
+    /// Append an event with its origin and outcome to the store.
+    /// Returns the sequence number assigned to it.
+    fn append(
+        &mut self,
+        event: TimedEvent,
+        origin: EventOrigin,
+        rejection: Option<String>,
+    ) -> impl Future<Output = Result<u64, EventStoreError>> + Send;
+
+    /// All recorded events, ordered by sequence number.
+    fn events(&self) -> impl Future<Output = Result<Vec<RecordedEvent>, EventStoreError>> + Send;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// Position of the event in the store, starting at 1.
+    pub sequence: u64,
+    pub event: Event,
+    /// Timestamp given with the event, `None` if it had none.
+    /// For a system event, the time the engine emitted it at.
+    pub timestamp: Option<Timestamp>,
+    pub origin: EventOrigin,
+    /// Why the engine rejected the event, `None` if it was applied.
+    pub rejection: Option<String>,
+}
+
+/// Who emitted an event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventOrigin {
+    /// Given to [crate::Engine::apply], eg. a row of the input.
+    #[default]
+    Partner,
+    /// Emitted by the engine itself, eg. to settle an expired dispute (see [crate::policy::DisputeDeadlines]).
+    System,
+}
+
+impl RecordedEvent {
+    /// The event as it was given to the engine.
+    pub fn timed(&self) -> TimedEvent {
+        TimedEvent {
+            event: self.event,
+            timestamp: self.timestamp,
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum EventStoreError {
+    /// The underlying storage failed (I/O error, corrupted data etc.)
+    #[error("Storage: {0}")]
+    Storage(String),
+}
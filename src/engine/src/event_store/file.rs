@@ -0,0 +1,294 @@
+//! Durable event store backed by a single append-only file.
+//!
+//! One event per line: `<sequence>,<type>,<client>,<tx>,<amount>,<rejection>`.
+//! The type is followed by `@<timestamp>` if the event had one (seconds since the Unix epoch),
+//! events written before timestamps have none. System events have a `system:` prefix (eg. `system:resolve`).
+//! The amount is empty for events without one (eg. a dispute of the whole transaction),
+//! account events (unlock, freeze, close) hold their reason code there, approvals leave it empty.
+//! The rejection is empty for accepted events.
+//! An amount with a currency is `<amount> <currency>`.
+//! The rejection is the last field, so it may contain commas.
+//!
+//! An event is committed once its line is written and fsync'ed.
+//! A trailing line without a newline is a write torn by a crash and is dropped on startup.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use super::*;
+use crate::ReasonCode;
+use crate::engine::types::{Amount, ClientId, Money, Timestamp, TransactionId};
+use crate::ledger::file::read_committed_lines;
+
+#[derive(Debug)]
+pub struct FileEventStore {
+    file: File,
+    /// All events of the file, loaded on [FileEventStore::open].
+    events: Vec<RecordedEvent>,
+}
+
+impl FileEventStore {
+    /// Open (or create) the event store at the given path and load its events.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EventStoreError> {
+        let path = path.as_ref();
+        let events = read_committed_lines(path)
+            .map_err(storage_error)?
+            .iter()
+            .map(|line| decode(line))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(storage_error)?;
+
+        Ok(Self { file, events })
+    }
+}
+
+// Note: file I/O is blocking. This is fine for the sequential CLI,
+// a server would move it to a blocking thread pool.
+impl EventStore for FileEventStore {
+    async fn append(
+        &mut self,
+        event: TimedEvent,
+        origin: EventOrigin,
+        rejection: Option<String>,
+    ) -> Result<u64, EventStoreError> {
+        let recorded = RecordedEvent {
+            sequence: self.events.len() as u64 + 1,
+            event: event.event,
+            timestamp: event.timestamp,
+            origin,
+            rejection,
+        };
+
+        self.file
+            .write_all(encode(&recorded).as_bytes())
+            .map_err(storage_error)?;
+        self.file.sync_data().map_err(storage_error)?;
+
+        let sequence = recorded.sequence;
+        self.events.push(recorded);
+        Ok(sequence)
+    }
+
+    async fn events(&self) -> Result<Vec<RecordedEvent>, EventStoreError> {
+        Ok(self.events.clone())
+    }
+}
+
+fn encode(recorded: &RecordedEvent) -> String {
+    let partial = |amount: Option<Amount>| {
+        amount
+            .map(|amount| Money::from(amount).encode())
+            .unwrap_or_default()
+    };
+    let (ty, details) = match recorded.event {
+        Event::Deposit { amount, .. } => ("deposit", amount.encode()),
+        Event::Withdraw { amount, .. } => ("withdrawal", amount.encode()),
+        Event::Dispute { amount, .. } => ("dispute", partial(amount)),
+        Event::Resolve { amount, .. } => ("resolve", partial(amount)),
+        Event::Chargeback { amount, .. } => ("chargeback", partial(amount)),
+        Event::Unlock { reason, .. } => ("unlock", reason.to_string()),
+        Event::Freeze { reason, .. } => ("freeze", reason.to_string()),
+        Event::Close { reason, .. } => ("close", reason.to_string()),
+        Event::Approve { .. } => ("approve", String::new()),
+    };
+    let ty = match recorded.origin {
+        EventOrigin::Partner => ty.to_string(),
+        EventOrigin::System => format!("system:{ty}"),
+    };
+    let ty = match recorded.timestamp {
+        Some(timestamp) => format!("{ty}@{timestamp}"),
+        None => ty,
+    };
+    let rejection = recorded.rejection.as_deref().unwrap_or_default();
+
+    format!(
+        "{},{ty},{},{},{details},{}\n",
+        recorded.sequence,
+        recorded.event.client_id(),
+        recorded.event.transaction_id(),
+        rejection.replace('\n', " ")
+    )
+}
+
+fn decode(line: &str) -> Result<RecordedEvent, EventStoreError> {
+    let corrupted = || EventStoreError::Storage(format!("corrupted event `{line}`"));
+
+    let fields = line.splitn(6, ',').collect::<Vec<_>>();
+    let [sequence, ty, client, tx, amount, rejection] = fields.as_slice() else {
+        return Err(corrupted());
+    };
+
+    let (ty, timestamp) = match ty.split_once('@') {
+        Some((ty, timestamp)) => {
+            let timestamp = timestamp.parse::<u64>().map_err(|_| corrupted())?;
+            (ty, Some(Timestamp::from(timestamp)))
+        }
+        None => (*ty, None),
+    };
+    let (ty, origin) = match ty.strip_prefix("system:") {
+        Some(ty) => (ty, EventOrigin::System),
+        None => (ty, EventOrigin::Partner),
+    };
+    let client_id = ClientId::from(client.parse::<u16>().map_err(|_| corrupted())?);
+    let transaction_id = TransactionId::from(tx.parse::<u32>().map_err(|_| corrupted())?);
+    let money = || Money::decode(amount).ok_or_else(corrupted);
+    let partial = || {
+        (!amount.is_empty())
+            .then(|| money().map(|money| money.amount))
+            .transpose()
+    };
+    let reason = || ReasonCode::new(amount).ok_or_else(corrupted);
+
+    let event = match ty {
+        "deposit" => Event::Deposit {
+            client_id,
+            transaction_id,
+            amount: money()?,
+        },
+        "withdrawal" => Event::Withdraw {
+            client_id,
+            transaction_id,
+            amount: money()?,
+        },
+        "dispute" => Event::Dispute {
+            client_id,
+            transaction_id,
+            amount: partial()?,
+        },
+        "resolve" => Event::Resolve {
+            client_id,
+            transaction_id,
+            amount: partial()?,
+        },
+        "chargeback" => Event::Chargeback {
+            client_id,
+            transaction_id,
+            amount: partial()?,
+        },
+        "unlock" => Event::Unlock {
+            client_id,
+            transaction_id,
+            reason: reason()?,
+        },
+        "freeze" => Event::Freeze {
+            client_id,
+            transaction_id,
+            reason: reason()?,
+        },
+        "close" => Event::Close {
+            client_id,
+            transaction_id,
+            reason: reason()?,
+        },
+        "approve" if amount.is_empty() => Event::Approve {
+            client_id,
+            transaction_id,
+        },
+        _ => return Err(corrupted()),
+    };
+
+    Ok(RecordedEvent {
+        sequence: sequence.parse::<u64>().map_err(|_| corrupted())?,
+        event,
+        timestamp,
+        origin,
+        rejection: (!rejection.is_empty()).then(|| rejection.to_string()),
+    })
+}
+
+fn storage_error(err: std::io::Error) -> EventStoreError {
+    EventStoreError::Storage(err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn events_survive_reopen() {
+        let path = std::env::temp_dir().join(format!(
+            "payment-engine-file-event-store-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let deposit = Event::Deposit {
+            client_id: ClientId::from(1),
+            transaction_id: TransactionId::from(1),
+            amount: Amount::from_minor(150).into(),
+        };
+        let dispute = Event::Dispute {
+            client_id: ClientId::from(1),
+            transaction_id: TransactionId::from(2),
+            amount: None,
+        };
+        let resolve = Event::Resolve {
+            client_id: ClientId::from(1),
+            transaction_id: TransactionId::from(1),
+            amount: None,
+        };
+        let freeze = Event::Freeze {
+            client_id: ClientId::from(1),
+            transaction_id: TransactionId::from(3),
+            reason: ReasonCode::Compliance,
+        };
+        let at = Timestamp::from(1_700_000_000);
+        let rejection = Some("Invalid event: transaction not found, really".to_string());
+        let recorded = vec![
+            RecordedEvent {
+                sequence: 1,
+                event: deposit,
+                timestamp: Some(at),
+                origin: EventOrigin::Partner,
+                rejection: None,
+            },
+            RecordedEvent {
+                sequence: 2,
+                event: dispute,
+                timestamp: None,
+                origin: EventOrigin::Partner,
+                rejection,
+            },
+            RecordedEvent {
+                sequence: 3,
+                event: resolve,
+                timestamp: Some(at),
+                origin: EventOrigin::System,
+                rejection: None,
+            },
+            RecordedEvent {
+                sequence: 4,
+                event: freeze,
+                timestamp: None,
+                origin: EventOrigin::Partner,
+                rejection: None,
+            },
+        ];
+
+        let mut store = FileEventStore::open(&path).unwrap();
+        for event in &recorded {
+            let sequence = store
+                .append(event.timed(), event.origin, event.rejection.clone())
+                .await
+                .unwrap();
+            assert_eq!(sequence, event.sequence);
+        }
+        drop(store);
+
+        let mut store = FileEventStore::open(&path).unwrap();
+        assert_eq!(store.events().await.unwrap(), recorded);
+        let sequence = store
+            .append(deposit.into(), EventOrigin::Partner, None)
+            .await
+            .unwrap();
+        assert_eq!(sequence, 5);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
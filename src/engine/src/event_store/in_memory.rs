@@ -0,0 +1,35 @@
+use super::*;
+
+#[derive(Debug, Default)]
+pub struct InMemoryEventStore {
+    events: Vec<RecordedEvent>,
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    async fn append(
+        &mut self,
+        event: TimedEvent,
+        origin: EventOrigin,
+        rejection: Option<String>,
+    ) -> Result<u64, EventStoreError> {
+        let sequence = self.events.len() as u64 + 1;
+        self.events.push(RecordedEvent {
+            sequence,
+            event: event.event,
+            timestamp: event.timestamp,
+            origin,
+            rejection,
+        });
+        Ok(sequence)
+    }
+
+    async fn events(&self) -> Result<Vec<RecordedEvent>, EventStoreError> {
+        Ok(self.events.clone())
+    }
+}
@@ -1,45 +1,120 @@
-//! Traits and implementations for transaction storage (ledger).
+//! Traits and implementations for transaction and client account storage (ledger).
 //!
 //!
 
 use std::fmt::Debug;
 
+use crate::ClientAccount;
 use crate::engine::types::{ClientId, TransactionId};
-use crate::ledger::transactions::Transaction;
+use crate::ledger::journal::JournalEntry;
+use crate::ledger::transactions::{Compensation, FeePosting, Transaction};
 
+pub mod file;
 pub mod in_memory;
+pub mod journal;
 pub mod transactions;
 
+/// Storage of transactions and the client accounts they affect.
+///
+/// The ledger is append-only: a transaction is never changed once added. Disputes, resolves and chargebacks
+/// append compensating entries to it (see [Compensation]), its current status is derived from the chain of its entries.
+///
+/// Every write carries the client account as it is after applying the transaction, the fee
+/// charged for it if any, and the journal entry of the funds it moved (see [journal]).
+/// Implementations must store them atomically, so the accounts never drift from the transactions,
+/// fees and journal.
+///
+/// The returned futures are `Send`, so an engine can be driven from a multi-threaded runtime.
 pub trait Ledger: Debug {
-    /// Add a new transaction to the ledger.
+    /// Add a new transaction (with its fee and journal entry) to the ledger and store the updated client account.
     /// Returns LedgerError::AlreadyExists if the transaction already exists.
-    /// Returns LedgerError::Conflict if the transaction exists but has different values or belong to different client..
+    /// Returns LedgerError::DifferentDetails if the transaction exists but has different values.
+    /// Returns LedgerError::DifferentClient if the transaction id belongs to a different client.
     fn add(
         &mut self,
-        client_id: ClientId,
+        account: &ClientAccount,
         transaction: Transaction,
-    ) -> impl Future<Output = Result<(), LedgerError>>;
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
+    ) -> impl Future<Output = Result<(), LedgerError>> + Send;
 
-    /// Update an existing transaction in the storage.
-    /// Returns LedgerError::NotFound if the transaction does not exist.
-    /// Returns LedgerError::Conflict if the transaction belongs to different client.
-    fn update(
+    /// Append a compensating entry (with its fee and journal entry) to an existing transaction and store the updated client account.
+    /// Returns LedgerError::NotFound if the transaction doesn't exist.
+    /// Returns LedgerError::DifferentClient if the transaction belongs to different client.
+    fn compensate(
         &mut self,
-        client_id: ClientId,
-        transaction: Transaction,
-    ) -> impl Future<Output = Result<(), LedgerError>>;
+        account: &ClientAccount,
+        compensation: Compensation,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
+    ) -> impl Future<Output = Result<(), LedgerError>> + Send;
 
+    /// Store the client account alone, for changes without a transaction (eg. a freeze).
+    fn update_account(
+        &mut self,
+        account: &ClientAccount,
+    ) -> impl Future<Output = Result<(), LedgerError>> + Send;
+
+    /// The transaction with its status derived from its compensating entries.
     fn find(
         &self,
         client_id: ClientId,
         transaction_id: TransactionId,
-    ) -> impl Future<Output = Result<Option<Transaction>, LedgerError>>;
+    ) -> impl Future<Output = Result<Option<Transaction>, LedgerError>> + Send;
+
+    /// The client the transaction id belongs to, `None` if no transaction has it.
+    fn client_of(
+        &self,
+        transaction_id: TransactionId,
+    ) -> impl Future<Output = Result<Option<ClientId>, LedgerError>> + Send;
+
+    /// The compensating entries of a transaction, in the order they were appended.
+    fn compensations(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> impl Future<Output = Result<Vec<Compensation>, LedgerError>> + Send;
+
+    /// All stored client accounts. Order is not guaranteed.
+    fn accounts(&self) -> impl Future<Output = Result<Vec<ClientAccount>, LedgerError>> + Send;
+
+    /// All transactions with their status derived from their compensating entries. Order is not guaranteed.
+    fn transactions(&self) -> impl Future<Output = Result<Vec<Transaction>, LedgerError>> + Send;
+
+    /// All transactions with a held dispute (`Disputed`), to track their deadlines. Order is not guaranteed.
+    fn disputed(&self) -> impl Future<Output = Result<Vec<Transaction>, LedgerError>> + Send;
+
+    /// All fee postings, in the order they were added.
+    fn fees(&self) -> impl Future<Output = Result<Vec<FeePosting>, LedgerError>> + Send;
+
+    /// All journal entries, in the order they were posted.
+    fn journal(&self) -> impl Future<Output = Result<Vec<JournalEntry>, LedgerError>> + Send;
 }
 
+/// Errors carry the client and transaction of the rejected write.
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
 pub enum LedgerError {
-    #[error("Already exists")]
-    AlreadyExists,
-    #[error("Conflict: {0}")]
-    Conflict(&'static str),
+    #[error("Transaction {transaction_id} already exists")]
+    AlreadyExists {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    #[error("Conflict: transaction {transaction_id} belong to a different client")]
+    DifferentClient {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    #[error("Transaction {transaction_id} not found")]
+    NotFound {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    #[error("Conflict: transaction {transaction_id} already exist but with different details")]
+    DifferentDetails {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// The underlying storage failed (I/O error, corrupted data etc.)
+    #[error("Storage: {0}")]
+    Storage(String),
 }
@@ -0,0 +1,1540 @@
+//! Durable ledger backed by an append-only write-ahead log (WAL) plus a snapshot file.
+//!
+//! Layout of the ledger directory:
+//! - `snapshot`: every transaction and client account at the time of the last compaction.
+//! - `wal`: every write since the last compaction.
+//!
+//! Both files use the same line format. A line holds one or more records separated by `;`:
+//! - `tx,<client>,<tx>,<state>,<amount>,<held>,<resolved>,<charged back>[,<timestamp>[,<disputed at>]]` where
+//!   state is one of `TransactionState`, an amount with a currency is `<amount> <currency>`, the next three
+//!   are the disputed portions (see `Disputes`). Timestamps are in seconds since the Unix epoch,
+//!   empty if unknown. The record holds the transaction as it was added, it is never written again.
+//! - `compensation,<index>,<client>,<tx>,<kind>,<amount>,<timestamp>` where index is the position of the entry
+//!   in the chain of the transaction (see `Ledger::compensations`), kind is `hold`, `release` or `reversal`
+//!   and the timestamp is empty if unknown.
+//!   It follows the record of its transaction.
+//! - `account,<client>,<status>` followed by `,<currency>,<available>,<total>` for each balance,
+//!   the status is `open`, `locked` or `closed` followed by `:<reason>` once it changed (see `ReasonCode`)
+//!   and `@<timestamp>` once the client had an event with a timestamp.
+//! - `fee,<index>,<client>,<tx>,<event>,<amount>,<timestamp>` where index is the position of the fee posting
+//!   (see `Ledger::fees`) and event is `deposit`, `withdrawal` or `chargeback`.
+//! - `journal,<index>,<client>,<tx>,<kind>,<timestamp>` followed by `,<side>:<account>:<amount>` for each line,
+//!   where index is the position of the entry (see `Ledger::journal`), kind is `deposit`, `withdrawal`,
+//!   `dispute`, `resolve` or `chargeback`, side is `dr` or `cr` and account is `available`, `held`
+//!   (of the client of the entry), `settlement` or `house`.
+//!
+//! The WAL is compacted into the snapshot on [FileLedger::open] and once it passes a size or line threshold
+//! (see [FileLedger::with_compaction_threshold]).
+//!
+//! A write to the WAL is a single line with the transaction (or its compensating entry), its fee, its journal entry
+//! and the client account it affected,
+//! so all are committed (or lost) together. Account changes without a transaction (eg. a freeze)
+//! are a line with the account alone.
+//!
+//! Each record holds the complete state of a transaction, compensating entry, fee posting, journal entry or account,
+//! so replaying a record is an upsert.
+//! That makes recovery idempotent: replaying the WAL on top of a snapshot which already
+//! contains some of its records (crash during compaction) yields the same ledger.
+//!
+//! A line is committed once it's written and fsync'ed. A trailing line without a newline is
+//! a write torn by a crash; it was never acknowledged, so it is dropped on startup.
+//!
+//! [FileLedger::open_read_only] loads the ledger without touching the directory (eg. to reconcile it):
+//! nothing is compacted and a torn write is skipped rather than truncated.
+//! A write or fsync that fails cuts the WAL back to its length before the line, so a rejected write
+//! is never replayed and the next line doesn't continue a partial one. If that fails too, the ledger
+//! refuses any further write until it is opened again.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::in_memory::InMemoryLedger;
+use super::*;
+use crate::engine::fees::FeeEvent;
+use crate::engine::types::{Amount, Currency, Money, SignedAmount, Timestamp};
+use crate::ledger::journal::{EntryKind, JournalAccount, JournalEntry, JournalLine, Side};
+use crate::ledger::transactions::{
+    Compensation, CompensationKind, Disputes, FeePosting, InboundTransaction, OutboundTransaction,
+    TransactionInfo,
+};
+use crate::{AccountStatus, Balance, ReasonCode};
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+const WAL_FILE: &str = "wal";
+/// The WAL is compacted into the snapshot once it holds this many bytes, see [FileLedger::with_compaction_threshold].
+const COMPACT_WAL_BYTES: u64 = 64 * 1024 * 1024;
+/// The WAL is compacted into the snapshot once it holds this many lines, see [FileLedger::with_compaction_threshold].
+const COMPACT_WAL_LINES: usize = 100_000;
+
+#[derive(Debug)]
+pub struct FileLedger {
+    dir: PathBuf,
+    /// Indexes are kept in memory and rebuilt from disk on [FileLedger::open].
+    inner: InMemoryLedger,
+    wal: File,
+    /// Flushes a line written to the WAL to disk, replaced by tests to inject failures.
+    sync: fn(&File) -> std::io::Result<()>,
+    /// Set when a failed write couldn't be cut from the WAL, further writes are refused.
+    poisoned: bool,
+    /// Lines written to the WAL since the last compaction.
+    wal_lines: usize,
+    /// The WAL is compacted once it holds this many bytes or lines.
+    compact_at: (u64, usize),
+}
+
+impl FileLedger {
+    /// Open (or create) a ledger in the given directory.
+    ///
+    /// Loads the snapshot, replays the WAL on top of it and compacts both into a fresh snapshot.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, LedgerError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage_error)?;
+
+        let inner = load(&dir, Torn::Truncate)?;
+        let wal = open_wal(&dir)?;
+        let mut ledger = Self {
+            dir,
+            inner,
+            wal,
+            sync: File::sync_data,
+            poisoned: false,
+            wal_lines: 0,
+            compact_at: (COMPACT_WAL_BYTES, COMPACT_WAL_LINES),
+        };
+        ledger.compact()?;
+
+        Ok(ledger)
+    }
+
+    /// Compact the WAL once it holds `bytes` bytes or `lines` lines instead of the defaults
+    /// (64 MiB or 100 000 lines), so a long-running process doesn't replay an ever growing WAL on its next start.
+    pub fn with_compaction_threshold(mut self, bytes: u64, lines: usize) -> Self {
+        self.compact_at = (bytes, lines);
+        self
+    }
+
+    /// Load the ledger in the given directory without changing anything on disk.
+    ///
+    /// The snapshot and the WAL are read into an in-memory ledger, writes to it aren't stored.
+    /// Fails if the directory doesn't exist.
+    pub fn open_read_only(dir: impl AsRef<Path>) -> Result<InMemoryLedger, LedgerError> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            return Err(LedgerError::Storage(format!(
+                "No ledger in {}",
+                dir.display()
+            )));
+        }
+
+        load(dir, Torn::Skip)
+    }
+
+    /// Write all transactions (each followed by its compensating entries), fees, journal entries and accounts
+    /// into a new snapshot and truncate the WAL.
+    ///
+    /// The snapshot is written to a temporary file and atomically renamed,
+    /// so a crash at any point leaves either the old or the new snapshot in place.
+    pub fn compact(&mut self) -> Result<(), LedgerError> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = File::create(&tmp_path).map_err(storage_error)?;
+        let transactions = self
+            .inner
+            .chains()
+            .flat_map(|(transaction, compensations)| {
+                let compensations = compensations
+                    .iter()
+                    .enumerate()
+                    .map(|(index, compensation)| Record::Compensation(index, *compensation));
+                std::iter::once(Record::Transaction(*transaction)).chain(compensations)
+            })
+            .collect::<Vec<_>>();
+        let fees = self
+            .inner
+            .fees_iter()
+            .map(|(index, fee)| Record::Fee(index, *fee));
+        let journal = self
+            .inner
+            .journal_iter()
+            .map(|(index, entry)| Record::Entry(index, entry.clone()));
+        let accounts = self.inner.accounts_iter().cloned().map(Record::Account);
+        for record in transactions
+            .into_iter()
+            .chain(fees)
+            .chain(journal)
+            .chain(accounts)
+        {
+            tmp.write_all(encode(&[record]).as_bytes())
+                .map_err(storage_error)?;
+        }
+        tmp.sync_all().map_err(storage_error)?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).map_err(storage_error)?;
+        sync_dir(&self.dir)?;
+
+        self.wal.set_len(0).map_err(storage_error)?;
+        self.wal.sync_all().map_err(storage_error)?;
+        self.wal_lines = 0;
+
+        Ok(())
+    }
+
+    /// Append the transaction (or its compensating entry), the fee and the journal entry (stored at `indexes`)
+    /// and the account as a single line to the WAL and fsync it.
+    fn commit(
+        &mut self,
+        account: &ClientAccount,
+        record: Record,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
+        indexes: Indexes,
+    ) -> Result<(), LedgerError> {
+        let mut records = vec![record];
+        records.extend(fee.map(|fee| Record::Fee(indexes.fee, fee)));
+        records.push(Record::Entry(indexes.entry, entry));
+        records.push(Record::Account(account.clone()));
+        self.append(&records)
+    }
+
+    /// Append the records as one line, the WAL is cut back to its previous length if that fails.
+    fn append(&mut self, records: &[Record]) -> Result<(), LedgerError> {
+        if self.poisoned {
+            return Err(LedgerError::Storage(
+                "a failed write is left in the WAL, reopen the ledger".to_string(),
+            ));
+        }
+
+        let len = self.wal.metadata().map_err(storage_error)?.len();
+        let line = encode(records);
+        let written = self
+            .wal
+            .write_all(line.as_bytes())
+            .and_then(|()| (self.sync)(&self.wal));
+        if let Err(err) = written {
+            // the WAL is opened in append mode, the next line is written at the new end
+            let cut = self.wal.set_len(len).and_then(|()| self.wal.sync_all());
+            self.poisoned = cut.is_err();
+            return Err(storage_error(err));
+        }
+
+        self.wal_lines += 1;
+        let (max_bytes, max_lines) = self.compact_at;
+        if len + line.len() as u64 >= max_bytes || self.wal_lines >= max_lines {
+            // The line is committed whatever happens to the compaction. One that fails leaves the WAL
+            // in place (replaying it on top of a newer snapshot is idempotent), it is retried on the next write.
+            let _ = self.compact();
+        }
+
+        Ok(())
+    }
+
+    /// Restore the fees, journal and account from before a write that couldn't be persisted,
+    /// the caller restores the transaction.
+    fn rollback(
+        &mut self,
+        client_id: ClientId,
+        previous_account: Option<ClientAccount>,
+        indexes: Indexes,
+    ) {
+        self.inner.truncate_fees(indexes.fee);
+        self.inner.truncate_journal(indexes.entry);
+        match previous_account {
+            Some(previous) => self.inner.put_account(previous),
+            None => self.inner.remove_account(client_id),
+        }
+    }
+}
+
+/// Where the fee and journal entry of a write go, see [Ledger::fees] and [Ledger::journal].
+#[derive(Debug, Clone, Copy)]
+struct Indexes {
+    fee: usize,
+    entry: usize,
+}
+
+impl Indexes {
+    fn next(ledger: &InMemoryLedger) -> Self {
+        Self {
+            fee: ledger.fee_count(),
+            entry: ledger.entry_count(),
+        }
+    }
+}
+
+// Note: file I/O is blocking. This is fine for the sequential CLI,
+// `EngineHandle` runs the engine on a thread of its own.
+impl Ledger for FileLedger {
+    async fn add(
+        &mut self,
+        account: &ClientAccount,
+        transaction: Transaction,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
+    ) -> Result<(), LedgerError> {
+        let client_id = account.client_id;
+        let previous_account = self.inner.find_account(client_id).cloned();
+        let indexes = Indexes::next(&self.inner);
+        self.inner
+            .add(account, transaction, fee, entry.clone())
+            .await?;
+
+        let record = Record::Transaction(transaction);
+        if let Err(err) = self.commit(account, record, fee, entry, indexes) {
+            self.inner.remove(client_id, transaction.info().id);
+            self.rollback(client_id, previous_account, indexes);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn compensate(
+        &mut self,
+        account: &ClientAccount,
+        compensation: Compensation,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
+    ) -> Result<(), LedgerError> {
+        let client_id = account.client_id;
+        let transaction_id = compensation.transaction_id;
+        let previous_account = self.inner.find_account(client_id).cloned();
+        let indexes = Indexes::next(&self.inner);
+        let index = self.inner.compensation_count(client_id, transaction_id);
+        self.inner
+            .compensate(account, compensation, fee, entry.clone())
+            .await?;
+
+        let record = Record::Compensation(index, compensation);
+        if let Err(err) = self.commit(account, record, fee, entry, indexes) {
+            self.inner
+                .truncate_compensations(client_id, transaction_id, index);
+            self.rollback(client_id, previous_account, indexes);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn update_account(&mut self, account: &ClientAccount) -> Result<(), LedgerError> {
+        let client_id = account.client_id;
+        let previous_account = self.inner.find_account(client_id).cloned();
+        self.inner.update_account(account).await?;
+
+        if let Err(err) = self.append(&[Record::Account(account.clone())]) {
+            match previous_account {
+                Some(previous) => self.inner.put_account(previous),
+                None => self.inner.remove_account(client_id),
+            }
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn find(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Option<Transaction>, LedgerError> {
+        self.inner.find(client_id, transaction_id).await
+    }
+
+    async fn client_of(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<ClientId>, LedgerError> {
+        self.inner.client_of(transaction_id).await
+    }
+
+    async fn compensations(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Vec<Compensation>, LedgerError> {
+        self.inner.compensations(client_id, transaction_id).await
+    }
+
+    async fn accounts(&self) -> Result<Vec<ClientAccount>, LedgerError> {
+        self.inner.accounts().await
+    }
+
+    async fn transactions(&self) -> Result<Vec<Transaction>, LedgerError> {
+        self.inner.transactions().await
+    }
+
+    async fn disputed(&self) -> Result<Vec<Transaction>, LedgerError> {
+        self.inner.disputed().await
+    }
+
+    async fn fees(&self) -> Result<Vec<FeePosting>, LedgerError> {
+        self.inner.fees().await
+    }
+
+    async fn journal(&self) -> Result<Vec<JournalEntry>, LedgerError> {
+        self.inner.journal().await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    Transaction(Transaction),
+    /// A compensating entry and its index in the chain of its transaction.
+    Compensation(usize, Compensation),
+    Account(ClientAccount),
+    /// A fee posting and its index.
+    Fee(usize, FeePosting),
+    /// A journal entry and its index.
+    Entry(usize, JournalEntry),
+}
+
+/// Serialized name of each transaction state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionState {
+    InboundSettled,
+    InboundDisputed,
+    InboundResolved,
+    InboundChargedBack,
+    OutboundSettled,
+    OutboundDisputed,
+    OutboundResolved,
+    OutboundReversed,
+}
+
+impl TransactionState {
+    fn of(transaction: &Transaction) -> Self {
+        match transaction {
+            Transaction::Inbound(InboundTransaction::Settled(_)) => Self::InboundSettled,
+            Transaction::Inbound(InboundTransaction::Disputed(_)) => Self::InboundDisputed,
+            Transaction::Inbound(InboundTransaction::Resolved(_)) => Self::InboundResolved,
+            Transaction::Inbound(InboundTransaction::ChargedBack(_)) => Self::InboundChargedBack,
+            Transaction::Outbound(OutboundTransaction::Settled(_)) => Self::OutboundSettled,
+            Transaction::Outbound(OutboundTransaction::Disputed(_)) => Self::OutboundDisputed,
+            Transaction::Outbound(OutboundTransaction::Resolved(_)) => Self::OutboundResolved,
+            Transaction::Outbound(OutboundTransaction::Reversed(_)) => Self::OutboundReversed,
+        }
+    }
+
+    fn with_info(self, info: TransactionInfo) -> Transaction {
+        match self {
+            Self::InboundSettled => Transaction::Inbound(InboundTransaction::Settled(info)),
+            Self::InboundDisputed => Transaction::Inbound(InboundTransaction::Disputed(info)),
+            Self::InboundResolved => Transaction::Inbound(InboundTransaction::Resolved(info)),
+            Self::InboundChargedBack => Transaction::Inbound(InboundTransaction::ChargedBack(info)),
+            Self::OutboundSettled => Transaction::Outbound(OutboundTransaction::Settled(info)),
+            Self::OutboundDisputed => Transaction::Outbound(OutboundTransaction::Disputed(info)),
+            Self::OutboundResolved => Transaction::Outbound(OutboundTransaction::Resolved(info)),
+            Self::OutboundReversed => Transaction::Outbound(OutboundTransaction::Reversed(info)),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::InboundSettled => "inbound-settled",
+            Self::InboundDisputed => "inbound-disputed",
+            Self::InboundResolved => "inbound-resolved",
+            Self::InboundChargedBack => "inbound-chargedback",
+            Self::OutboundSettled => "outbound-settled",
+            Self::OutboundDisputed => "outbound-disputed",
+            Self::OutboundResolved => "outbound-resolved",
+            Self::OutboundReversed => "outbound-reversed",
+        }
+    }
+}
+
+impl FromStr for TransactionState {
+    type Err = LedgerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "inbound-settled" => Self::InboundSettled,
+            "inbound-disputed" => Self::InboundDisputed,
+            "inbound-resolved" => Self::InboundResolved,
+            "inbound-chargedback" => Self::InboundChargedBack,
+            "outbound-settled" => Self::OutboundSettled,
+            "outbound-disputed" => Self::OutboundDisputed,
+            "outbound-resolved" => Self::OutboundResolved,
+            "outbound-reversed" => Self::OutboundReversed,
+            _ => return Err(LedgerError::Storage(format!("unknown state `{s}`"))),
+        })
+    }
+}
+
+fn encode(records: &[Record]) -> String {
+    let records = records
+        .iter()
+        .map(|record| match record {
+            Record::Transaction(transaction) => {
+                let info = transaction.info();
+                let mut record = format!(
+                    "tx,{},{},{},{},{},{},{}",
+                    info.client_id,
+                    info.id,
+                    TransactionState::of(transaction).as_str(),
+                    info.amount.encode(),
+                    info.disputes.held.as_decimal(),
+                    info.disputes.resolved.as_decimal(),
+                    info.disputes.charged_back.as_decimal(),
+                );
+                match (info.timestamp, info.disputed_at) {
+                    (None, None) => {}
+                    (timestamp, None) => {
+                        record.push_str(&format!(",{}", encode_optional_timestamp(timestamp)))
+                    }
+                    (timestamp, Some(disputed_at)) => record.push_str(&format!(
+                        ",{},{disputed_at}",
+                        encode_optional_timestamp(timestamp)
+                    )),
+                }
+                record
+            }
+            Record::Compensation(index, compensation) => format!(
+                "compensation,{index},{},{},{},{},{}",
+                compensation.client_id,
+                compensation.transaction_id,
+                encode_compensation_kind(compensation.kind),
+                compensation.amount.encode(),
+                encode_optional_timestamp(compensation.timestamp),
+            ),
+            Record::Account(account) => {
+                let mut record =
+                    format!("account,{},{}", account.client_id, encode_status(account));
+                for (currency, balance) in &account.balances {
+                    record.push_str(&format!(
+                        ",{currency},{},{}",
+                        balance.available.as_decimal(),
+                        balance.total.as_decimal()
+                    ));
+                }
+                record
+            }
+            Record::Fee(index, fee) => format!(
+                "fee,{index},{},{},{},{},{}",
+                fee.client_id,
+                fee.transaction_id,
+                encode_fee_event(fee.event),
+                fee.amount.encode(),
+                fee.timestamp,
+            ),
+            Record::Entry(index, entry) => {
+                let mut record = format!(
+                    "journal,{index},{},{},{},{}",
+                    entry.client_id,
+                    entry.transaction_id,
+                    encode_entry_kind(entry.kind),
+                    entry.timestamp,
+                );
+                for line in &entry.lines {
+                    record.push_str(&format!(
+                        ",{}:{}:{}",
+                        encode_side(line.side),
+                        encode_journal_account(line.account),
+                        line.amount.encode()
+                    ));
+                }
+                record
+            }
+        })
+        .collect::<Vec<_>>();
+
+    format!("{}\n", records.join(";"))
+}
+
+fn decode(line: &str) -> Result<Vec<Record>, LedgerError> {
+    line.split(';').map(decode_record).collect()
+}
+
+fn decode_record(record: &str) -> Result<Record, LedgerError> {
+    let corrupted = || LedgerError::Storage(format!("corrupted record `{record}`"));
+    let amount = |value: &str| {
+        rust_decimal::Decimal::from_str(value)
+            .map(Amount::from)
+            .map_err(|_| corrupted())
+    };
+    let signed = |value: &str| {
+        rust_decimal::Decimal::from_str(value)
+            .map(SignedAmount::from)
+            .map_err(|_| corrupted())
+    };
+    let client_id = |value: &str| {
+        value
+            .parse::<u16>()
+            .map(ClientId::from)
+            .map_err(|_| corrupted())
+    };
+
+    let fields = record.split(',').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["tx", client, id, state, value, disputes @ ..] => {
+            let state = state.parse::<TransactionState>()?;
+            let mut info = TransactionInfo {
+                id: TransactionId::from(id.parse::<u32>().map_err(|_| corrupted())?),
+                client_id: client_id(client)?,
+                amount: Money::decode(value).ok_or_else(corrupted)?,
+                disputes: Disputes::default(),
+                timestamp: None,
+                disputed_at: None,
+            };
+            info.disputes = match disputes {
+                [held, resolved, charged_back, timestamps @ ..] if timestamps.len() <= 2 => {
+                    let timestamp = |index: usize| match timestamps.get(index) {
+                        Some(timestamp) => {
+                            decode_optional_timestamp(timestamp).ok_or_else(corrupted)
+                        }
+                        None => Ok(None),
+                    };
+                    info.timestamp = timestamp(0)?;
+                    info.disputed_at = timestamp(1)?;
+                    Disputes {
+                        held: amount(held)?,
+                        resolved: amount(resolved)?,
+                        charged_back: amount(charged_back)?,
+                    }
+                }
+                _ => return Err(corrupted()),
+            };
+            if info.disputes.disputed() > info.amount.amount {
+                return Err(corrupted());
+            }
+            Ok(Record::Transaction(state.with_info(info)))
+        }
+        ["account", client, status, balances @ ..] if balances.len() % 3 == 0 => {
+            let balances = balances
+                .chunks(3)
+                .map(|balance| {
+                    let currency = Currency::new(balance[0]).ok_or_else(corrupted)?;
+                    let balance = Balance {
+                        available: signed(balance[1])?,
+                        total: signed(balance[2])?,
+                    };
+                    Ok((currency, balance))
+                })
+                .collect::<Result<_, LedgerError>>()?;
+
+            Ok(Record::Account(ClientAccount {
+                balances,
+                ..account(client_id(client)?, status).ok_or_else(corrupted)?
+            }))
+        }
+        ["fee", index, client, tx, event, value, timestamp] => Ok(Record::Fee(
+            index.parse::<usize>().map_err(|_| corrupted())?,
+            FeePosting {
+                client_id: client_id(client)?,
+                transaction_id: TransactionId::from(tx.parse::<u32>().map_err(|_| corrupted())?),
+                event: decode_fee_event(event).ok_or_else(corrupted)?,
+                amount: Money::decode(value).ok_or_else(corrupted)?,
+                timestamp: parse_timestamp(timestamp).ok_or_else(corrupted)?,
+            },
+        )),
+        ["compensation", index, client, tx, kind, value, timestamp] => Ok(Record::Compensation(
+            index.parse::<usize>().map_err(|_| corrupted())?,
+            Compensation {
+                client_id: client_id(client)?,
+                transaction_id: TransactionId::from(tx.parse::<u32>().map_err(|_| corrupted())?),
+                kind: decode_compensation_kind(kind).ok_or_else(corrupted)?,
+                amount: Money::decode(value).ok_or_else(corrupted)?,
+                timestamp: decode_optional_timestamp(timestamp).ok_or_else(corrupted)?,
+            },
+        )),
+        ["journal", index, client, tx, kind, timestamp, lines @ ..] => {
+            let client_id = client_id(client)?;
+            let lines = lines
+                .iter()
+                .map(|line| {
+                    let mut fields = line.splitn(3, ':');
+                    let (Some(side), Some(account), Some(value)) =
+                        (fields.next(), fields.next(), fields.next())
+                    else {
+                        return Err(corrupted());
+                    };
+                    Ok(JournalLine {
+                        account: decode_journal_account(account, client_id)
+                            .ok_or_else(corrupted)?,
+                        side: decode_side(side).ok_or_else(corrupted)?,
+                        amount: Money::decode(value).ok_or_else(corrupted)?,
+                    })
+                })
+                .collect::<Result<_, LedgerError>>()?;
+
+            Ok(Record::Entry(
+                index.parse::<usize>().map_err(|_| corrupted())?,
+                JournalEntry {
+                    client_id,
+                    transaction_id: TransactionId::from(
+                        tx.parse::<u32>().map_err(|_| corrupted())?,
+                    ),
+                    kind: decode_entry_kind(kind).ok_or_else(corrupted)?,
+                    timestamp: parse_timestamp(timestamp).ok_or_else(corrupted)?,
+                    lines,
+                },
+            ))
+        }
+        _ => Err(corrupted()),
+    }
+}
+
+fn encode_compensation_kind(kind: CompensationKind) -> &'static str {
+    match kind {
+        CompensationKind::Hold => "hold",
+        CompensationKind::Release => "release",
+        CompensationKind::Reversal => "reversal",
+    }
+}
+
+fn decode_compensation_kind(kind: &str) -> Option<CompensationKind> {
+    match kind {
+        "hold" => Some(CompensationKind::Hold),
+        "release" => Some(CompensationKind::Release),
+        "reversal" => Some(CompensationKind::Reversal),
+        _ => None,
+    }
+}
+
+fn encode_entry_kind(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Deposit => "deposit",
+        EntryKind::Withdrawal => "withdrawal",
+        EntryKind::Dispute => "dispute",
+        EntryKind::Resolve => "resolve",
+        EntryKind::Chargeback => "chargeback",
+    }
+}
+
+fn decode_entry_kind(kind: &str) -> Option<EntryKind> {
+    match kind {
+        "deposit" => Some(EntryKind::Deposit),
+        "withdrawal" => Some(EntryKind::Withdrawal),
+        "dispute" => Some(EntryKind::Dispute),
+        "resolve" => Some(EntryKind::Resolve),
+        "chargeback" => Some(EntryKind::Chargeback),
+        _ => None,
+    }
+}
+
+fn encode_side(side: Side) -> &'static str {
+    match side {
+        Side::Debit => "dr",
+        Side::Credit => "cr",
+    }
+}
+
+fn decode_side(side: &str) -> Option<Side> {
+    match side {
+        "dr" => Some(Side::Debit),
+        "cr" => Some(Side::Credit),
+        _ => None,
+    }
+}
+
+/// The accounts of a client are those of the client of the entry.
+fn encode_journal_account(account: JournalAccount) -> &'static str {
+    match account {
+        JournalAccount::Available(_) => "available",
+        JournalAccount::Held(_) => "held",
+        JournalAccount::Settlement => "settlement",
+        JournalAccount::House => "house",
+    }
+}
+
+fn decode_journal_account(account: &str, client_id: ClientId) -> Option<JournalAccount> {
+    match account {
+        "available" => Some(JournalAccount::Available(client_id)),
+        "held" => Some(JournalAccount::Held(client_id)),
+        "settlement" => Some(JournalAccount::Settlement),
+        "house" => Some(JournalAccount::House),
+        _ => None,
+    }
+}
+
+fn encode_fee_event(event: FeeEvent) -> &'static str {
+    match event {
+        FeeEvent::Deposit => "deposit",
+        FeeEvent::Withdrawal => "withdrawal",
+        FeeEvent::Chargeback => "chargeback",
+    }
+}
+
+fn decode_fee_event(event: &str) -> Option<FeeEvent> {
+    match event {
+        "deposit" => Some(FeeEvent::Deposit),
+        "withdrawal" => Some(FeeEvent::Withdrawal),
+        "chargeback" => Some(FeeEvent::Chargeback),
+        _ => None,
+    }
+}
+
+/// `<status>[:<reason>][@<last timestamp>]`, read by [account].
+fn encode_status(account: &ClientAccount) -> String {
+    let mut status = match account.status {
+        AccountStatus::Open => "open",
+        AccountStatus::Locked => "locked",
+        AccountStatus::Closed => "closed",
+    }
+    .to_string();
+    if let Some(reason) = account.reason {
+        status.push_str(&format!(":{reason}"));
+    }
+    if let Some(timestamp) = account.last_timestamp {
+        status.push_str(&format!("@{timestamp}"));
+    }
+    status
+}
+
+/// An empty account with the status of [encode_status].
+fn account(client_id: ClientId, status: &str) -> Option<ClientAccount> {
+    let (status, last_timestamp) = match status.split_once('@') {
+        Some((status, timestamp)) => (status, Some(parse_timestamp(timestamp)?)),
+        None => (status, None),
+    };
+    let (status, reason) = match status.split_once(':') {
+        Some((status, reason)) => (status, Some(ReasonCode::new(reason)?)),
+        None => (status, None),
+    };
+    let (status, reason) = match status {
+        "open" => (AccountStatus::Open, reason),
+        "locked" => (AccountStatus::Locked, reason),
+        "closed" => (AccountStatus::Closed, reason),
+        _ => return None,
+    };
+
+    Some(ClientAccount {
+        status,
+        reason,
+        last_timestamp,
+        ..ClientAccount::new(client_id)
+    })
+}
+
+/// A timestamp, empty if unknown, read by [decode_optional_timestamp].
+fn encode_optional_timestamp(timestamp: Option<Timestamp>) -> String {
+    timestamp
+        .map(|timestamp| timestamp.to_string())
+        .unwrap_or_default()
+}
+
+fn decode_optional_timestamp(value: &str) -> Option<Option<Timestamp>> {
+    match value {
+        "" => Some(None),
+        value => parse_timestamp(value).map(Some),
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<Timestamp> {
+    value.parse::<u64>().ok().map(Timestamp::from)
+}
+
+/// Load the snapshot and the WAL of the directory, the WAL on top of the snapshot.
+fn load(dir: &Path, torn: Torn) -> Result<InMemoryLedger, LedgerError> {
+    let mut inner = InMemoryLedger::new();
+    for path in [dir.join(SNAPSHOT_FILE), dir.join(WAL_FILE)] {
+        for record in read_records(&path, torn)? {
+            match record {
+                Record::Transaction(transaction) => inner.put(transaction)?,
+                Record::Compensation(index, compensation) => {
+                    inner.put_compensation(index, compensation)?
+                }
+                Record::Account(account) => inner.put_account(account),
+                Record::Fee(index, fee) => inner.put_fee(index, fee)?,
+                Record::Entry(index, entry) => inner.put_entry(index, entry)?,
+            }
+        }
+    }
+
+    Ok(inner)
+}
+
+fn read_records(path: &Path, torn: Torn) -> Result<Vec<Record>, LedgerError> {
+    let mut records = vec![];
+    for line in read_lines(path, torn).map_err(storage_error)? {
+        records.extend(decode(&line)?);
+    }
+
+    Ok(records)
+}
+
+/// What to do with a torn trailing write (no newline) of an append-only file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Torn {
+    /// Cut it from the file, so the next write doesn't continue it.
+    Truncate,
+    /// Leave the file as it is, for readers that don't write.
+    Skip,
+}
+
+/// Read all committed lines (without the newline) of an append-only file.
+/// A missing file has no lines.
+///
+/// A torn trailing write (no newline) is truncated away.
+pub(crate) fn read_committed_lines(path: &Path) -> std::io::Result<Vec<String>> {
+    read_lines(path, Torn::Truncate)
+}
+
+fn read_lines(path: &Path, torn: Torn) -> std::io::Result<Vec<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut lines = vec![];
+    let mut committed_len = 0u64;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        if !line.ends_with('\n') {
+            if torn == Torn::Truncate {
+                let file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(committed_len)?;
+                file.sync_all()?;
+            }
+            break;
+        }
+
+        committed_len += read as u64;
+        lines.push(line.trim_end().to_string());
+    }
+
+    Ok(lines)
+}
+
+fn open_wal(dir: &Path) -> Result<File, LedgerError> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(WAL_FILE))
+        .map_err(storage_error)
+}
+
+/// Persist a rename in the directory (no-op on platforms where directories can't be opened).
+fn sync_dir(dir: &Path) -> Result<(), LedgerError> {
+    match File::open(dir) {
+        Ok(dir) => dir.sync_all().or(Ok(())),
+        Err(_) => Ok(()),
+    }
+}
+
+fn storage_error(err: std::io::Error) -> LedgerError {
+    LedgerError::Storage(err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_store::file::FileEventStore;
+    use crate::event_store::{EventOrigin, EventStore};
+    use crate::{Applied, Engine, Event};
+
+    /// A fresh, empty directory under the system temp dir.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payment-engine-file-ledger-{}-{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn balance(available: u32, total: u32) -> Balance {
+        Balance {
+            available: Amount::from_minor(available).into(),
+            total: Amount::from_minor(total).into(),
+        }
+    }
+
+    fn deposit(client: u16, tx: u32) -> Transaction {
+        Transaction::new_settled_inbound(
+            TransactionId::from(tx),
+            ClientId::from(client),
+            Amount::from_minor(150).into(),
+            Some(Timestamp::from(1_700_000_000)),
+        )
+    }
+
+    /// The journal entry of a deposit.
+    fn deposited(transaction: &Transaction) -> JournalEntry {
+        let info = transaction.info();
+        let mut entry = JournalEntry::new(EntryKind::Deposit, transaction, Timestamp::from(0));
+        entry.transfer(
+            JournalAccount::Settlement,
+            JournalAccount::Available(info.client_id),
+            info.amount,
+        );
+        entry
+    }
+
+    #[tokio::test]
+    async fn state_survives_reopen() {
+        let dir = temp_dir("reopen");
+        let account = ClientAccount::new(ClientId::from(1));
+        let client = account.client_id;
+
+        let fee = FeePosting {
+            client_id: client,
+            transaction_id: TransactionId::from(2),
+            event: FeeEvent::Deposit,
+            amount: Amount::from_minor(5).into(),
+            timestamp: Timestamp::from(1_700_000_000),
+        };
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        ledger
+            .add(
+                &account,
+                deposit(1, 2),
+                Some(fee),
+                deposited(&deposit(1, 2)),
+            )
+            .await
+            .unwrap();
+        let at = Timestamp::from(1_700_000_100);
+        let mut disputed = deposit(1, 2);
+        let held = disputed
+            .dispute(&Default::default(), None, Some(at))
+            .unwrap();
+        let hold = disputed.compensation(CompensationKind::Hold, held, Some(at));
+        let mut dispute = JournalEntry::new(EntryKind::Dispute, &disputed, at);
+        dispute.transfer(
+            JournalAccount::Available(client),
+            JournalAccount::Held(client),
+            held,
+        );
+        ledger
+            .compensate(&account, hold, None, dispute.clone())
+            .await
+            .unwrap();
+        drop(ledger);
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        assert_eq!(
+            ledger.find(client, TransactionId::from(1)).await.unwrap(),
+            Some(deposit(1, 1))
+        );
+        assert_eq!(
+            ledger.find(client, TransactionId::from(2)).await.unwrap(),
+            Some(disputed)
+        );
+        assert_eq!(
+            ledger
+                .compensations(client, TransactionId::from(2))
+                .await
+                .unwrap(),
+            vec![hold]
+        );
+        assert_eq!(ledger.fees().await.unwrap(), vec![fee]);
+        assert_eq!(
+            ledger.journal().await.unwrap(),
+            vec![
+                deposited(&deposit(1, 1)),
+                deposited(&deposit(1, 2)),
+                dispute
+            ]
+        );
+
+        // the transaction id -> client index is rebuilt as well
+        assert_eq!(
+            ledger
+                .add(
+                    &ClientAccount::new(ClientId::from(2)),
+                    deposit(2, 1),
+                    None,
+                    deposited(&deposit(2, 1))
+                )
+                .await,
+            Err(LedgerError::DifferentClient {
+                client_id: ClientId::from(2),
+                transaction_id: TransactionId::from(1),
+            })
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A write that failed to be fsync'ed is cut from the WAL, it isn't replayed on open.
+    #[tokio::test]
+    async fn failed_write_is_not_replayed() {
+        let dir = temp_dir("failed-write");
+        let account = ClientAccount::new(ClientId::from(1));
+        let client = account.client_id;
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        ledger.sync = |_| Err(std::io::Error::other("injected failure"));
+        assert_eq!(
+            ledger
+                .add(&account, deposit(1, 2), None, deposited(&deposit(1, 2)))
+                .await,
+            Err(LedgerError::Storage("injected failure".to_string()))
+        );
+        ledger.sync = File::sync_data;
+        ledger
+            .add(&account, deposit(1, 3), None, deposited(&deposit(1, 3)))
+            .await
+            .unwrap();
+        assert_eq!(read_committed_lines(&dir.join(WAL_FILE)).unwrap().len(), 2);
+        drop(ledger);
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        for (tx, stored) in [(1, true), (2, false), (3, true)] {
+            assert_eq!(
+                ledger.find(client, TransactionId::from(tx)).await.unwrap(),
+                stored.then(|| deposit(1, tx))
+            );
+        }
+        assert_eq!(ledger.journal().await.unwrap().len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The WAL is compacted once it reaches the threshold, without a reopen.
+    #[tokio::test]
+    async fn wal_is_compacted_at_threshold() {
+        let dir = temp_dir("threshold");
+        let account = ClientAccount::new(ClientId::from(1));
+        let client = account.client_id;
+        let wal_lines = || read_committed_lines(&dir.join(WAL_FILE)).unwrap().len();
+
+        let mut ledger = FileLedger::open(&dir)
+            .unwrap()
+            .with_compaction_threshold(u64::MAX, 2);
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        assert_eq!(wal_lines(), 1);
+        ledger
+            .add(&account, deposit(1, 2), None, deposited(&deposit(1, 2)))
+            .await
+            .unwrap();
+        assert_eq!(wal_lines(), 0);
+        ledger
+            .add(&account, deposit(1, 3), None, deposited(&deposit(1, 3)))
+            .await
+            .unwrap();
+        assert_eq!(wal_lines(), 1);
+
+        // a single line over the size threshold is compacted right away
+        let mut ledger = ledger.with_compaction_threshold(1, usize::MAX);
+        ledger
+            .add(&account, deposit(1, 4), None, deposited(&deposit(1, 4)))
+            .await
+            .unwrap();
+        assert_eq!(wal_lines(), 0);
+        drop(ledger);
+
+        let ledger = FileLedger::open_read_only(&dir).unwrap();
+        for tx in 1..=4 {
+            assert_eq!(
+                ledger.find(client, TransactionId::from(tx)).await.unwrap(),
+                Some(deposit(1, tx))
+            );
+        }
+        assert_eq!(ledger.journal().await.unwrap().len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// An event recorded before a crash cut its ledger write is applied on the next start.
+    #[tokio::test]
+    async fn recorded_event_is_applied_on_start() {
+        let dir = temp_dir("recover");
+        let events = dir.join("events");
+        let client_id = ClientId::from(1);
+        let deposit = |tx: u32| Event::Deposit {
+            client_id,
+            transaction_id: TransactionId::from(tx),
+            amount: Amount::from_minor(150).into(),
+        };
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        let mut engine = Engine::with_event_store(ledger, FileEventStore::open(&events).unwrap())
+            .await
+            .unwrap();
+        engine.apply(deposit(1)).await.unwrap();
+        drop(engine);
+        // the process stopped between recording the event and writing the ledger
+        FileEventStore::open(&events)
+            .unwrap()
+            .append(deposit(2).into(), EventOrigin::Partner, None)
+            .await
+            .unwrap();
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        let mut engine = Engine::with_event_store(ledger, FileEventStore::open(&events).unwrap())
+            .await
+            .unwrap();
+        let receipt = engine.recover().await.unwrap().unwrap();
+        assert_eq!(receipt.applied, Applied::New);
+        assert_eq!(
+            engine
+                .account(client_id)
+                .map(|account| account.available(Currency::NONE)),
+            Some(Amount::from_minor(300).into())
+        );
+        assert_eq!(engine.event_store().events().await.unwrap().len(), 2);
+        drop(engine);
+
+        // once applied, the event is a replay
+        let ledger = FileLedger::open(&dir).unwrap();
+        let mut engine = Engine::with_event_store(ledger, FileEventStore::open(&events).unwrap())
+            .await
+            .unwrap();
+        let receipt = engine.recover().await.unwrap().unwrap();
+        assert_eq!(receipt.applied, Applied::AlreadyApplied);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Balances and lock flags are restored together with the transactions.
+    #[tokio::test]
+    async fn engine_resumes_from_stored_state() {
+        let dir = temp_dir("engine");
+        let client_id = ClientId::from(1);
+
+        let mut engine = Engine::new(FileLedger::open(&dir).unwrap()).await.unwrap();
+        for (tx, minor) in [(1, 300), (2, 100)] {
+            engine
+                .apply(Event::Deposit {
+                    client_id,
+                    transaction_id: TransactionId::from(tx),
+                    amount: Amount::from_minor(minor).into(),
+                })
+                .await
+                .unwrap();
+        }
+        engine
+            .apply(Event::Dispute {
+                client_id,
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            })
+            .await
+            .unwrap();
+        drop(engine);
+
+        let mut engine = Engine::new(FileLedger::open(&dir).unwrap()).await.unwrap();
+        let expected = ClientAccount {
+            client_id,
+            balances: [(Currency::NONE, balance(100, 400))].into(),
+            ..ClientAccount::new(client_id)
+        };
+        assert_eq!(engine.accounts_ordered(), vec![&expected]);
+
+        // yesterday's dispute can be charged back today
+        engine
+            .apply(Event::Chargeback {
+                client_id,
+                transaction_id: TransactionId::from(1),
+                amount: None,
+            })
+            .await
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::new(FileLedger::open(&dir).unwrap()).await.unwrap();
+        let expected = ClientAccount {
+            client_id,
+            balances: [(Currency::NONE, balance(100, 100))].into(),
+            status: AccountStatus::Locked,
+            reason: Some(ReasonCode::Chargeback),
+            last_timestamp: None,
+        };
+        assert_eq!(engine.accounts_ordered(), vec![&expected]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torn_write_is_dropped() {
+        let dir = temp_dir("torn");
+        let account = ClientAccount::new(ClientId::from(1));
+        let client = account.client_id;
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        ledger
+            .wal
+            .write_all(b"tx,1,2,inbound-settled,1.5;acc")
+            .unwrap(); // crash mid-write
+        drop(ledger);
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        assert!(
+            ledger
+                .find(client, TransactionId::from(1))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            ledger
+                .find(client, TransactionId::from(2))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        ledger
+            .add(&account, deposit(1, 2), None, deposited(&deposit(1, 2)))
+            .await
+            .unwrap();
+        drop(ledger);
+        let ledger = FileLedger::open(&dir).unwrap();
+        assert!(
+            ledger
+                .find(client, TransactionId::from(2))
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A read-only open loads the WAL on top of the snapshot but leaves both files as they are.
+    #[tokio::test]
+    async fn read_only_open_leaves_files_untouched() {
+        let dir = temp_dir("read-only");
+        let account = ClientAccount::new(ClientId::from(1));
+        assert!(FileLedger::open_read_only(&dir).is_err());
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        drop(ledger);
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 2), None, deposited(&deposit(1, 2)))
+            .await
+            .unwrap();
+        ledger.wal.write_all(b"tx,1,3,inbound-settled").unwrap(); // crash mid-write
+        drop(ledger);
+        let files = || {
+            [SNAPSHOT_FILE, WAL_FILE, SNAPSHOT_TMP_FILE].map(|file| fs::read(dir.join(file)).ok())
+        };
+        let before = files();
+
+        let ledger = FileLedger::open_read_only(&dir).unwrap();
+        let mut ids = ledger
+            .transactions()
+            .await
+            .unwrap()
+            .iter()
+            .map(|transaction| transaction.info().id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![TransactionId::from(1), TransactionId::from(2)]);
+        assert_eq!(files(), before);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Crash after the snapshot was renamed but before the WAL was truncated.
+    #[tokio::test]
+    async fn replay_on_top_of_snapshot_is_idempotent() {
+        let dir = temp_dir("replay");
+        let account = ClientAccount::new(ClientId::from(1));
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        ledger
+            .add(&account, deposit(1, 1), None, deposited(&deposit(1, 1)))
+            .await
+            .unwrap();
+        drop(ledger);
+        let wal = fs::read(dir.join(WAL_FILE)).unwrap();
+
+        let ledger = FileLedger::open(&dir).unwrap(); // compacts
+        drop(ledger);
+        fs::write(dir.join(WAL_FILE), wal).unwrap();
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        assert_eq!(ledger.inner.chains().count(), 1);
+        assert_eq!(ledger.accounts().await.unwrap(), vec![account]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Unknown records fail, so do a transaction record written again with other details
+    /// and one disputed over its amount.
+    #[test]
+    fn corrupted_record_is_an_error() {
+        let dir = temp_dir("corrupted");
+        fs::create_dir_all(&dir).unwrap();
+        for wal in [
+            "tx,1,1,unknown,1.5,0,0,0\n",
+            "tx,1,1,inbound-settled,1.5\n",
+            "account,1,1.5,1.5,false\n",
+            "tx,1,1,inbound-settled,1.5,0,0,0\ntx,1,1,inbound-disputed,1.5,1.5,0,0\n",
+            "tx,1,1,inbound-disputed,1.5,1,0.5,0.5\n",
+        ] {
+            fs::write(dir.join(WAL_FILE), wal).unwrap();
+            assert!(
+                matches!(FileLedger::open(&dir), Err(LedgerError::Storage(_))),
+                "{wal}"
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The status, its reason and the last timestamp survive a reopen.
+    #[tokio::test]
+    async fn account_status_survives_reopen() {
+        let dir = temp_dir("account-status");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(SNAPSHOT_FILE),
+            "account,1,locked:chargeback,XXX,0,0
+",
+        )
+        .unwrap();
+
+        let mut ledger = FileLedger::open(&dir).unwrap();
+        let locked = ClientAccount {
+            status: AccountStatus::Locked,
+            reason: Some(ReasonCode::Chargeback),
+            balances: [(Currency::NONE, balance(0, 0))].into(),
+            ..ClientAccount::new(ClientId::from(1))
+        };
+        assert_eq!(ledger.accounts().await.unwrap(), vec![locked.clone()]);
+
+        let closed = ClientAccount {
+            status: AccountStatus::Closed,
+            reason: Some(ReasonCode::ClientRequest),
+            last_timestamp: Some(Timestamp::from(1_700_000_000)),
+            ..locked
+        };
+        ledger.update_account(&closed).await.unwrap();
+        drop(ledger);
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        assert_eq!(ledger.accounts().await.unwrap(), vec![closed]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn balances_of_each_currency_survive_reopen() {
+        let dir = temp_dir("multi-currency");
+        let client_id = ClientId::from(1);
+        let jpy = Currency::new("JPY").unwrap();
+
+        let mut engine = Engine::new(FileLedger::open(&dir).unwrap()).await.unwrap();
+        engine
+            .apply(Event::Deposit {
+                client_id,
+                transaction_id: TransactionId::from(1),
+                amount: Amount::from_minor(150).into(),
+            })
+            .await
+            .unwrap();
+        engine
+            .apply(Event::Deposit {
+                client_id,
+                transaction_id: TransactionId::from(2),
+                amount: Money::new(rust_decimal::Decimal::from(500), jpy),
+            })
+            .await
+            .unwrap();
+        let expected = engine
+            .accounts_ordered()
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        drop(engine);
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        assert_eq!(ledger.accounts().await.unwrap(), expected);
+        assert_eq!(
+            ledger
+                .find(client_id, TransactionId::from(2))
+                .await
+                .unwrap()
+                .map(|transaction| transaction.info().amount),
+            Some(Money::new(rust_decimal::Decimal::from(500), jpy))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The disputed portions are read from the chain of the transaction, partial disputes survive a reopen.
+    #[tokio::test]
+    async fn disputed_portions_are_read() {
+        let dir = temp_dir("disputes");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(SNAPSHOT_FILE),
+            "tx,1,1,inbound-settled,1.5,0,0,0,1700000000
+compensation,0,1,1,hold,1.5,1700000050
+account,1,open@1700000050,XXX,0,1.5
+",
+        )
+        .unwrap();
+
+        let (client_id, transaction_id) = (ClientId::from(1), TransactionId::from(1));
+        let ledger = FileLedger::open(&dir).unwrap();
+        let transaction = ledger
+            .find(client_id, transaction_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            transaction.info().disputes,
+            Disputes {
+                held: Amount::from_minor(150),
+                ..Disputes::default()
+            }
+        );
+
+        let mut engine = Engine::new(ledger).await.unwrap();
+        engine
+            .apply(
+                Event::Resolve {
+                    client_id,
+                    transaction_id,
+                    amount: Some(Amount::from_minor(50)),
+                }
+                .at(Timestamp::from(1_700_000_100)),
+            )
+            .await
+            .unwrap();
+        drop(engine);
+
+        let ledger = FileLedger::open(&dir).unwrap();
+        let transaction = ledger
+            .find(client_id, transaction_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            transaction.status(),
+            crate::ledger::transactions::TransactionStatus::Disputed
+        );
+        assert_eq!(
+            transaction.info().disputes,
+            Disputes {
+                held: Amount::from_minor(100),
+                resolved: Amount::from_minor(50),
+                charged_back: Amount::default(),
+            }
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,55 @@
 use std::collections::HashMap;
 
 use super::*;
+use crate::ledger::journal::JournalEntry;
+use crate::ledger::transactions::{Compensation, FeePosting, TransactionStatus};
 
 #[derive(Debug)]
 pub struct InMemoryLedger {
-    transactions: HashMap<(ClientId, TransactionId), Transaction>,
+    transactions: HashMap<(ClientId, TransactionId), Chain>,
     transaction_id_client_id: HashMap<TransactionId, ClientId>,
+    accounts: HashMap<ClientId, ClientAccount>,
+    fees: Vec<FeePosting>,
+    journal: Vec<JournalEntry>,
+}
+
+/// A transaction as it was added and the compensating entries appended to it since.
+#[derive(Debug, Clone)]
+struct Chain {
+    original: Transaction,
+    compensations: Vec<Compensation>,
+    /// The original with the compensations applied, see [Transaction::compensate].
+    current: Transaction,
+}
+
+impl Chain {
+    fn new(original: Transaction) -> Self {
+        Self {
+            original,
+            compensations: vec![],
+            current: original,
+        }
+    }
+
+    /// Apply the entry on the current state and append it, fails if it doesn't apply.
+    fn push(&mut self, compensation: Compensation) -> Result<(), LedgerError> {
+        let mut current = self.current;
+        current
+            .compensate(&compensation)
+            .map_err(|err| LedgerError::Storage(format!("invalid compensating entry: {err}")))?;
+        self.current = current;
+        self.compensations.push(compensation);
+        Ok(())
+    }
+
+    /// Derive the current state from the original and the compensations again.
+    fn refold(&mut self) -> Result<(), LedgerError> {
+        let compensations = std::mem::take(&mut self.compensations);
+        self.current = self.original;
+        compensations
+            .into_iter()
+            .try_for_each(|compensation| self.push(compensation))
+    }
 }
 
 impl Default for InMemoryLedger {
@@ -19,16 +63,194 @@ impl InMemoryLedger {
         Self {
             transactions: <_>::default(),
             transaction_id_client_id: <_>::default(),
+            accounts: <_>::default(),
+            fees: vec![],
+            journal: vec![],
+        }
+    }
+
+    /// Insert the original of a transaction without any checks, its compensating entries are kept.
+    /// Used by durable ledgers to restore state that was already validated.
+    ///
+    /// The original is never changed: putting it again is a no-op, a different one fails.
+    pub(crate) fn put(&mut self, transaction: Transaction) -> Result<(), LedgerError> {
+        let info = *transaction.info();
+        match self.transactions.get(&(info.client_id, info.id)) {
+            Some(chain) if chain.original == transaction => Ok(()),
+            Some(_) => Err(LedgerError::Storage(format!(
+                "transaction {} written again with other details",
+                info.id
+            ))),
+            None => {
+                self.transaction_id_client_id
+                    .insert(info.id, info.client_id);
+                self.transactions
+                    .insert((info.client_id, info.id), Chain::new(transaction));
+                Ok(())
+            }
+        }
+    }
+
+    /// Insert or overwrite the compensating entry at `index` of the chain of its transaction (see [Ledger::compensations]).
+    /// Fails if the transaction or the entries before it are missing.
+    pub(crate) fn put_compensation(
+        &mut self,
+        index: usize,
+        compensation: Compensation,
+    ) -> Result<(), LedgerError> {
+        let key = (compensation.client_id, compensation.transaction_id);
+        let chain = self.transactions.get_mut(&key).ok_or_else(|| {
+            LedgerError::Storage(format!(
+                "compensating entry of unknown transaction {}",
+                compensation.transaction_id
+            ))
+        })?;
+        let count = chain.compensations.len();
+        match chain.compensations.get_mut(index) {
+            Some(existing) => {
+                *existing = compensation;
+                chain.refold()
+            }
+            None if index == count => chain.push(compensation),
+            None => Err(LedgerError::Storage(format!(
+                "compensating entry {index} of transaction {} follows {count} entries",
+                compensation.transaction_id
+            ))),
+        }
+    }
+
+    /// Number of compensating entries of a transaction, the index of the next one.
+    pub(crate) fn compensation_count(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> usize {
+        self.transactions
+            .get(&(client_id, transaction_id))
+            .map_or(0, |chain| chain.compensations.len())
+    }
+
+    /// Remove the compensating entries of a transaction from `index` on,
+    /// used to roll back a write that couldn't be persisted.
+    pub(crate) fn truncate_compensations(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        index: usize,
+    ) {
+        if let Some(chain) = self.transactions.get_mut(&(client_id, transaction_id)) {
+            chain.compensations.truncate(index);
+            chain
+                .refold()
+                .expect("the remaining entries applied before");
+        }
+    }
+
+    /// Insert or overwrite a client account.
+    pub(crate) fn put_account(&mut self, account: ClientAccount) {
+        self.accounts.insert(account.client_id, account);
+    }
+
+    /// Insert or overwrite the fee posting at `index` (see [Ledger::fees]).
+    /// Fails if the postings before it are missing.
+    pub(crate) fn put_fee(&mut self, index: usize, fee: FeePosting) -> Result<(), LedgerError> {
+        let count = self.fees.len();
+        match self.fees.get_mut(index) {
+            Some(existing) => *existing = fee,
+            None if index == count => self.fees.push(fee),
+            None => {
+                return Err(LedgerError::Storage(format!(
+                    "fee posting {index} follows {count} postings"
+                )));
+            }
         }
+        Ok(())
+    }
+
+    /// Number of fee postings, the index of the next one.
+    pub(crate) fn fee_count(&self) -> usize {
+        self.fees.len()
+    }
+
+    /// Remove the fee postings from `index` on, used to roll back a write that couldn't be persisted.
+    pub(crate) fn truncate_fees(&mut self, index: usize) {
+        self.fees.truncate(index);
+    }
+
+    /// Iterate over all fee postings with their index.
+    pub(crate) fn fees_iter(&self) -> impl Iterator<Item = (usize, &FeePosting)> {
+        self.fees.iter().enumerate()
+    }
+
+    /// Insert or overwrite the journal entry at `index` (see [Ledger::journal]).
+    /// Fails if the entries before it are missing.
+    pub(crate) fn put_entry(
+        &mut self,
+        index: usize,
+        entry: JournalEntry,
+    ) -> Result<(), LedgerError> {
+        let count = self.journal.len();
+        match self.journal.get_mut(index) {
+            Some(existing) => *existing = entry,
+            None if index == count => self.journal.push(entry),
+            None => {
+                return Err(LedgerError::Storage(format!(
+                    "journal entry {index} follows {count} entries"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of journal entries, the index of the next one.
+    pub(crate) fn entry_count(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Remove the journal entries from `index` on, used to roll back a write that couldn't be persisted.
+    pub(crate) fn truncate_journal(&mut self, index: usize) {
+        self.journal.truncate(index);
+    }
+
+    /// Iterate over all journal entries with their index.
+    pub(crate) fn journal_iter(&self) -> impl Iterator<Item = (usize, &JournalEntry)> {
+        self.journal.iter().enumerate()
+    }
+
+    pub(crate) fn find_account(&self, client_id: ClientId) -> Option<&ClientAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    /// Remove a client account, used to roll back a write that couldn't be persisted.
+    pub(crate) fn remove_account(&mut self, client_id: ClientId) {
+        self.accounts.remove(&client_id);
+    }
+
+    /// Remove a transaction, used to roll back a write that couldn't be persisted.
+    pub(crate) fn remove(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        self.transaction_id_client_id.remove(&transaction_id);
+        self.transactions.remove(&(client_id, transaction_id));
+    }
+
+    /// Iterate over the original of all transactions with their compensating entries. Order is not guaranteed.
+    pub(crate) fn chains(&self) -> impl Iterator<Item = (&Transaction, &[Compensation])> {
+        self.transactions
+            .values()
+            .map(|chain| (&chain.original, chain.compensations.as_slice()))
+    }
+
+    /// Iterate over all client accounts. Order is not guaranteed.
+    pub(crate) fn accounts_iter(&self) -> impl Iterator<Item = &ClientAccount> {
+        self.accounts.values()
     }
 
     fn transaction_belong_to_different_client(
         &self,
-        transaction: Transaction,
+        transaction_id: TransactionId,
         client_id: ClientId,
     ) -> bool {
         self.transaction_id_client_id
-            .get(&transaction.info().id)
+            .get(&transaction_id)
             .map(|c| client_id != *c)
             .unwrap_or_default()
     }
@@ -37,55 +259,84 @@ impl InMemoryLedger {
 impl Ledger for InMemoryLedger {
     async fn add(
         &mut self,
-        client_id: ClientId,
+        account: &ClientAccount,
         transaction: Transaction,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
     ) -> Result<(), LedgerError> {
-        let existing = self.transactions.get(&(client_id, transaction.info().id));
+        let client_id = account.client_id;
+        let transaction_id = transaction.info().id;
+        let existing = self
+            .transactions
+            .get(&(client_id, transaction_id))
+            .map(|chain| &chain.current);
 
         match existing {
-            None if self.transaction_belong_to_different_client(transaction, client_id) => Err(
-                LedgerError::Conflict("Transaction belong to a different client"),
-            ),
+            None if self.transaction_belong_to_different_client(transaction_id, client_id) => {
+                Err(LedgerError::DifferentClient {
+                    client_id,
+                    transaction_id,
+                })
+            }
             Some(existing) => {
                 if existing == &transaction {
-                    Err(LedgerError::AlreadyExists)
+                    Err(LedgerError::AlreadyExists {
+                        client_id,
+                        transaction_id,
+                    })
                 } else {
-                    Err(LedgerError::Conflict(
-                        "Transaction already exist but with different details",
-                    ))
+                    Err(LedgerError::DifferentDetails {
+                        client_id,
+                        transaction_id,
+                    })
                 }
             }
             None => {
                 self.transaction_id_client_id
                     .insert(transaction.info().id, client_id);
                 self.transactions
-                    .insert((client_id, transaction.info().id), transaction);
+                    .insert((client_id, transaction_id), Chain::new(transaction));
+                self.put_account(account.clone());
+                self.fees.extend(fee);
+                self.journal.push(entry);
                 Ok(())
             }
         }
     }
 
-    async fn update(
+    async fn compensate(
         &mut self,
-        client_id: ClientId,
-        transaction: Transaction,
+        account: &ClientAccount,
+        compensation: Compensation,
+        fee: Option<FeePosting>,
+        entry: JournalEntry,
     ) -> Result<(), LedgerError> {
-        let transaction_id = transaction.info().id;
-        let existing = self.transactions.get(&(client_id, transaction_id));
-
-        match existing {
-            Some(existing) if existing.info().client_id != client_id => Err(LedgerError::Conflict(
-                "Transaction belong to a different client",
-            )),
-            Some(existing) if existing == &transaction => {
-                Ok(()) // exist and identical
-            }
-            Some(_) | None => {
-                self.transactions
-                    .insert((client_id, transaction.info().id), transaction);
-                Ok(())
-            }
+        let client_id = account.client_id;
+        let transaction_id = compensation.transaction_id;
+        if self.transaction_belong_to_different_client(transaction_id, client_id) {
+            return Err(LedgerError::DifferentClient {
+                client_id,
+                transaction_id,
+            });
         }
+        let chain = self
+            .transactions
+            .get_mut(&(client_id, transaction_id))
+            .ok_or(LedgerError::NotFound {
+                client_id,
+                transaction_id,
+            })?;
+
+        chain.push(compensation)?;
+        self.put_account(account.clone());
+        self.fees.extend(fee);
+        self.journal.push(entry);
+        Ok(())
+    }
+
+    async fn update_account(&mut self, account: &ClientAccount) -> Result<(), LedgerError> {
+        self.put_account(account.clone());
+        Ok(())
     }
 
     async fn find(
@@ -93,13 +344,65 @@ impl Ledger for InMemoryLedger {
         client_id: ClientId,
         transaction_id: TransactionId,
     ) -> Result<Option<Transaction>, LedgerError> {
-        Ok(self.transactions.get(&(client_id, transaction_id)).cloned())
+        Ok(self
+            .transactions
+            .get(&(client_id, transaction_id))
+            .map(|chain| chain.current))
+    }
+
+    async fn client_of(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<ClientId>, LedgerError> {
+        Ok(self.transaction_id_client_id.get(&transaction_id).copied())
+    }
+
+    async fn compensations(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Vec<Compensation>, LedgerError> {
+        Ok(self
+            .transactions
+            .get(&(client_id, transaction_id))
+            .map(|chain| chain.compensations.clone())
+            .unwrap_or_default())
+    }
+
+    async fn accounts(&self) -> Result<Vec<ClientAccount>, LedgerError> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+
+    async fn transactions(&self) -> Result<Vec<Transaction>, LedgerError> {
+        Ok(self
+            .transactions
+            .values()
+            .map(|chain| chain.current)
+            .collect())
+    }
+
+    async fn disputed(&self) -> Result<Vec<Transaction>, LedgerError> {
+        Ok(self
+            .transactions
+            .values()
+            .map(|chain| chain.current)
+            .filter(|transaction| transaction.status() == TransactionStatus::Disputed)
+            .collect())
+    }
+
+    async fn fees(&self) -> Result<Vec<FeePosting>, LedgerError> {
+        Ok(self.fees.clone())
+    }
+
+    async fn journal(&self) -> Result<Vec<JournalEntry>, LedgerError> {
+        Ok(self.journal.clone())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::engine::types::Amount;
+    use crate::engine::types::{Amount, Timestamp};
+    use crate::ledger::journal::{EntryKind, JournalEntry};
 
     use super::*;
 
@@ -108,26 +411,36 @@ mod test {
     async fn transaction_id_is_globally_unique() {
         let mut ledger = InMemoryLedger::new();
 
-        let client_a = ClientId::from(1);
-        let client_b = ClientId::from(2);
+        let client_a = ClientAccount::new(ClientId::from(1));
+        let client_b = ClientAccount::new(ClientId::from(2));
 
         // Test insert
         let transaction = Transaction::new_settled_inbound(
             TransactionId::from(1),
-            client_a,
-            Amount::from_minor(100),
+            client_a.client_id,
+            Amount::from_minor(100).into(),
+            None,
+        );
+        let entry = JournalEntry::new(EntryKind::Deposit, &transaction, Timestamp::from(0));
+        assert!(
+            ledger
+                .add(&client_a, transaction, None, entry.clone())
+                .await
+                .is_ok()
         );
-        assert!(ledger.add(client_a, transaction).await.is_ok());
 
         // same transaction for different client
         let err = ledger
-            .add(client_b, transaction) //<- same transaction
+            .add(&client_b, transaction, None, entry) //<- same transaction
             .await
             .expect_err("same transaction to different client must fail");
 
         assert_eq!(
             err,
-            LedgerError::Conflict("Transaction belong to a different client")
+            LedgerError::DifferentClient {
+                client_id: client_b.client_id,
+                transaction_id: TransactionId::from(1),
+            }
         );
     }
 }
@@ -0,0 +1,236 @@
+//! Double-entry journal underneath the ledger.
+//!
+//! Every write of the engine posts a [JournalEntry]: balanced debit and credit lines moving funds between
+//! the available and held funds of the client, the settlement account (funds coming in and going out of the
+//! engine) and the house account (fees). The balances of a [crate::ClientAccount] are the sum of its lines,
+//! see [TrialBalance::balance].
+//!
+//! Balances are credit-normal: credits minus debits. The client and house accounts are what the engine owes,
+//! the settlement account is their counterpart and goes negative by the funds that came in.
+
+use std::collections::BTreeMap;
+
+use crate::Balance;
+use crate::engine::types::{
+    Amount, ClientId, Currency, Money, SignedAmount, Timestamp, TransactionId,
+};
+use crate::ledger::transactions::Transaction;
+
+/// An account of the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JournalAccount {
+    /// Funds the client can withdraw.
+    Available(ClientId),
+    /// Funds of the client held by disputes.
+    Held(ClientId),
+    /// Funds deposited into or withdrawn out of the engine.
+    Settlement,
+    /// Fees charged to the clients, see [crate::Engine::revenue].
+    House,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+/// The event a journal entry was posted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    /// Disputed funds are held.
+    Dispute,
+    /// Held funds are released.
+    Resolve,
+    /// Held funds are charged back, or credited back for a withdrawal.
+    Chargeback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalLine {
+    pub account: JournalAccount,
+    pub side: Side,
+    pub amount: Money,
+}
+
+/// Lines posted together for one event on a transaction, debits and credits balance in each currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub client_id: ClientId,
+    pub transaction_id: TransactionId,
+    pub kind: EntryKind,
+    /// Time of the event.
+    pub timestamp: Timestamp,
+    pub lines: Vec<JournalLine>,
+}
+
+impl JournalEntry {
+    /// An entry without lines for `kind` on the transaction.
+    pub fn new(kind: EntryKind, transaction: &Transaction, timestamp: Timestamp) -> Self {
+        let info = transaction.info();
+        Self {
+            client_id: info.client_id,
+            transaction_id: info.id,
+            kind,
+            timestamp,
+            lines: vec![],
+        }
+    }
+
+    /// Move `amount` from the `debit` account to the `credit` account. Returns the two lines added.
+    pub fn transfer(
+        &mut self,
+        debit: JournalAccount,
+        credit: JournalAccount,
+        amount: Money,
+    ) -> [JournalLine; 2] {
+        let lines = [
+            JournalLine {
+                account: debit,
+                side: Side::Debit,
+                amount,
+            },
+            JournalLine {
+                account: credit,
+                side: Side::Credit,
+                amount,
+            },
+        ];
+        self.lines.extend(lines);
+        lines
+    }
+
+    /// Whether the debits equal the credits in each currency.
+    pub fn is_balanced(&self) -> bool {
+        TrialBalance::of([self]).is_balanced()
+    }
+}
+
+/// Debits and credits of an account in one currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Totals {
+    pub debits: Amount,
+    pub credits: Amount,
+}
+
+impl Totals {
+    /// Credits minus debits.
+    pub fn balance(&self) -> SignedAmount {
+        SignedAmount::from(self.credits.as_decimal() - self.debits.as_decimal())
+    }
+}
+
+/// Totals of every account of the journal per currency, see [crate::Engine::trial_balance].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrialBalance {
+    totals: BTreeMap<(Currency, JournalAccount), Totals>,
+}
+
+impl TrialBalance {
+    pub fn of<'a>(entries: impl IntoIterator<Item = &'a JournalEntry>) -> Self {
+        let mut totals = BTreeMap::<_, Totals>::new();
+        for line in entries.into_iter().flat_map(|entry| &entry.lines) {
+            let totals = totals
+                .entry((line.amount.currency, line.account))
+                .or_default();
+            match line.side {
+                Side::Debit => totals.debits += line.amount.amount,
+                Side::Credit => totals.credits += line.amount.amount,
+            }
+        }
+        Self { totals }
+    }
+
+    /// Totals of each account that has lines, ordered by currency then account.
+    pub fn accounts(&self) -> impl Iterator<Item = (Currency, JournalAccount, Totals)> + '_ {
+        self.totals
+            .iter()
+            .map(|((currency, account), totals)| (*currency, *account, *totals))
+    }
+
+    /// Totals of an account in the currency, zero if it has no lines.
+    pub fn totals(&self, account: JournalAccount, currency: Currency) -> Totals {
+        self.totals
+            .get(&(currency, account))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether the debits equal the credits in each currency: the books sum to zero.
+    pub fn is_balanced(&self) -> bool {
+        let mut sums = BTreeMap::<Currency, rust_decimal::Decimal>::new();
+        for (currency, _, totals) in self.accounts() {
+            *sums.entry(currency).or_default() += totals.balance().as_decimal();
+        }
+        sums.values().all(|sum| sum.is_zero())
+    }
+
+    /// Balance of a client in the currency, derived from its journal accounts.
+    pub fn balance(&self, client_id: ClientId, currency: Currency) -> Balance {
+        let available = self
+            .totals(JournalAccount::Available(client_id), currency)
+            .balance();
+        let held = self
+            .totals(JournalAccount::Held(client_id), currency)
+            .balance();
+        Balance {
+            available,
+            total: SignedAmount::from(available.as_decimal() + held.as_decimal()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trial_balance() {
+        let client_id = ClientId::from(1);
+        let transaction = Transaction::new_settled_inbound(
+            TransactionId::from(1),
+            client_id,
+            Amount::from_minor(1_000).into(),
+            None,
+        );
+        let amount = |minor| Money::from(Amount::from_minor(minor));
+        let available = JournalAccount::Available(client_id);
+        let held = JournalAccount::Held(client_id);
+
+        let mut deposit = JournalEntry::new(EntryKind::Deposit, &transaction, Timestamp::from(0));
+        deposit.transfer(JournalAccount::Settlement, available, amount(1_000));
+        deposit.transfer(available, JournalAccount::House, amount(10));
+        let mut dispute = JournalEntry::new(EntryKind::Dispute, &transaction, Timestamp::from(1));
+        dispute.transfer(available, held, amount(400));
+        assert!(deposit.is_balanced() && dispute.is_balanced());
+
+        let trial = TrialBalance::of([&deposit, &dispute]);
+        assert!(trial.is_balanced());
+        assert_eq!(
+            trial.balance(client_id, Currency::NONE),
+            Balance {
+                available: Amount::from_minor(590).into(),
+                total: Amount::from_minor(990).into(),
+            }
+        );
+        assert_eq!(
+            trial.totals(JournalAccount::Settlement, Currency::NONE),
+            Totals {
+                debits: Amount::from_minor(1_000),
+                credits: Amount::default(),
+            }
+        );
+        assert_eq!(
+            trial
+                .totals(JournalAccount::House, Currency::NONE)
+                .balance(),
+            Amount::from_minor(10).into()
+        );
+
+        let mut unbalanced = dispute.clone();
+        unbalanced.lines.pop();
+        assert!(!unbalanced.is_balanced());
+    }
+}
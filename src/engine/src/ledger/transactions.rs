@@ -1,4 +1,6 @@
-use crate::engine::types::{Amount, ClientId, TransactionId};
+use crate::engine::fees::FeeEvent;
+use crate::engine::policy::TransitionPolicy;
+use crate::engine::types::{Amount, ClientId, Money, Timestamp, TransactionId};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Transaction {
@@ -17,35 +19,136 @@ pub enum InboundTransaction {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutboundTransaction {
     Settled(TransactionInfo),
+    Disputed(TransactionInfo),
+    Resolved(TransactionInfo),
+    Reversed(TransactionInfo),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TransactionInfo {
     pub id: TransactionId,
     pub client_id: ClientId,
-    pub amount: Amount,
+    pub amount: Money,
+    /// Portions of the amount that were disputed, see [Transaction::dispute].
+    pub disputes: Disputes,
+    /// When the transaction happened, `None` if its event had no timestamp.
+    pub timestamp: Option<Timestamp>,
+    /// When the first of the held disputes was opened, `None` while nothing is held or if that dispute
+    /// had no timestamp (it doesn't expire then, see [crate::policy::DisputeDeadlines]).
+    pub disputed_at: Option<Timestamp>,
 }
 
+/// A fee booked against a client and credited to the house account, see [crate::fees::FeeSchedule].
+///
+/// Fees are stored as their own ledger entries, next to the transaction they were charged for.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeePosting {
+    pub client_id: ClientId,
+    /// The transaction the fee was charged for.
+    pub transaction_id: TransactionId,
+    pub event: FeeEvent,
+    pub amount: Money,
+    /// Time of the event that was charged.
+    pub timestamp: Timestamp,
+}
+
+/// An entry appended to a transaction by a dispute, resolve or chargeback, see [Transaction::compensate].
+///
+/// The original transaction is never changed: its current status and disputed portions are derived
+/// from the chain of its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compensation {
+    pub client_id: ClientId,
+    /// The original transaction.
+    pub transaction_id: TransactionId,
+    pub kind: CompensationKind,
+    /// In the currency of the transaction.
+    pub amount: Money,
+    /// Time of the event, `None` if it had no timestamp.
+    pub timestamp: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompensationKind {
+    /// A dispute holds the amount.
+    Hold,
+    /// A resolve releases the held amount, the transaction stands.
+    Release,
+    /// A chargeback reverses the held amount (of a deposit or a withdrawal).
+    Reversal,
+}
+
+/// Disputed portions of a transaction, in the currency of the transaction.
+///
+/// A dispute without an amount covers the whole undisputed amount, so without partial disputes
+/// the whole amount moves from one field to the next along with the status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Disputes {
+    /// Under dispute, held on the account until resolved or charged back.
+    pub held: Amount,
+    /// Disputed, then resolved: the transaction stands.
+    pub resolved: Amount,
+    /// Disputed, then charged back (reversed for a withdrawal).
+    pub charged_back: Amount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Direction {
     Inbound,
     Outbound,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TransactionStatus {
     Settled,
     Disputed,
     Resolved,
     ChargedBack,
+    /// A disputed withdrawal was reversed, the funds are credited back.
+    Reversed,
 }
 
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransitionError {
-    #[error("Invalid transition from {0} to {1}")]
-    InvalidTransition(TransactionStatus, TransactionStatus),
-    #[error("must be an inbound to transition")]
-    InvalidDirection,
+    #[error("Invalid transition of transaction {transaction_id} from {current} to {desired}")]
+    InvalidTransition {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        current: TransactionStatus,
+        desired: TransactionStatus,
+    },
+    #[error("Transaction {transaction_id} is {direction:?}, it can't take this transition")]
+    InvalidDirection {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        direction: Direction,
+    },
+    #[error(
+        "Dispute of {requested} exceeds the undisputed {undisputed} of transaction {transaction_id}"
+    )]
+    ExceedsUndisputed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        requested: Money,
+        undisputed: Money,
+    },
+    #[error("{requested} exceeds the held {held} of transaction {transaction_id}")]
+    ExceedsHeld {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        requested: Money,
+        held: Money,
+    },
+}
+
+impl Disputes {
+    /// Everything that was ever disputed.
+    pub fn disputed(&self) -> Amount {
+        let mut disputed = self.held;
+        disputed += self.resolved;
+        disputed += self.charged_back;
+        disputed
+    }
 }
 
 impl std::fmt::Display for TransactionStatus {
@@ -55,24 +158,41 @@ impl std::fmt::Display for TransactionStatus {
             TransactionStatus::Disputed => write!(f, "Disputed"),
             TransactionStatus::Resolved => write!(f, "Resolved"),
             TransactionStatus::ChargedBack => write!(f, "ChargedBack"),
+            TransactionStatus::Reversed => write!(f, "Reversed"),
         }
     }
 }
 
 impl Transaction {
-    pub fn new_settled_inbound(id: TransactionId, client_id: ClientId, amount: Amount) -> Self {
+    pub fn new_settled_inbound(
+        id: TransactionId,
+        client_id: ClientId,
+        amount: Money,
+        timestamp: Option<Timestamp>,
+    ) -> Self {
         Transaction::Inbound(InboundTransaction::Settled(TransactionInfo {
             id,
             client_id,
             amount,
+            disputes: Disputes::default(),
+            timestamp,
+            disputed_at: None,
         }))
     }
 
-    pub fn new_settled_outbound(id: TransactionId, client_id: ClientId, amount: Amount) -> Self {
+    pub fn new_settled_outbound(
+        id: TransactionId,
+        client_id: ClientId,
+        amount: Money,
+        timestamp: Option<Timestamp>,
+    ) -> Self {
         Transaction::Outbound(OutboundTransaction::Settled(TransactionInfo {
             id,
             client_id,
             amount,
+            disputes: Disputes::default(),
+            timestamp,
+            disputed_at: None,
         }))
     }
 
@@ -85,11 +205,255 @@ impl Transaction {
                 | InboundTransaction::ChargedBack(info) => info,
             },
             Transaction::Outbound(outbound) => match outbound {
-                OutboundTransaction::Settled(info) => info,
+                OutboundTransaction::Settled(info)
+                | OutboundTransaction::Disputed(info)
+                | OutboundTransaction::Resolved(info)
+                | OutboundTransaction::Reversed(info) => info,
             },
         }
     }
 
+    fn info_mut(&mut self) -> &mut TransactionInfo {
+        match self {
+            Transaction::Inbound(inbound) => match inbound {
+                InboundTransaction::Settled(info)
+                | InboundTransaction::Disputed(info)
+                | InboundTransaction::Resolved(info)
+                | InboundTransaction::ChargedBack(info) => info,
+            },
+            Transaction::Outbound(outbound) => match outbound {
+                OutboundTransaction::Settled(info)
+                | OutboundTransaction::Disputed(info)
+                | OutboundTransaction::Resolved(info)
+                | OutboundTransaction::Reversed(info) => info,
+            },
+        }
+    }
+
+    /// Whether the transaction has the same details (client, amount), whatever its status, disputes and timestamp.
+    pub fn same_details(&self, other: &Transaction) -> bool {
+        let (info, other_info) = (self.info(), other.info());
+        self.direction() == other.direction()
+            && info.id == other_info.id
+            && info.client_id == other_info.client_id
+            && info.amount == other_info.amount
+    }
+
+    /// The part of the amount that was never disputed.
+    ///
+    /// Nothing if the disputes exceed the amount, which the ledger never stores (a durable one rejects such a record).
+    pub fn undisputed(&self) -> Money {
+        let info = self.info();
+        let mut undisputed = info.amount;
+        if undisputed
+            .amount
+            .try_subtract(info.disputes.disputed())
+            .is_none()
+        {
+            undisputed.amount = Amount::default();
+        }
+        undisputed
+    }
+
+    /// The part of the amount that can be disputed under the policy: the undisputed part,
+    /// plus the resolved part if the policy allows re-disputes.
+    pub fn disputable(&self, policy: &TransitionPolicy) -> Money {
+        let mut disputable = self.undisputed();
+        if policy.redispute_resolved() {
+            disputable.amount += self.info().disputes.resolved;
+        }
+        disputable
+    }
+
+    /// The part of the amount under dispute.
+    pub fn held(&self) -> Money {
+        let info = self.info();
+        Money {
+            amount: info.disputes.held,
+            currency: info.amount.currency,
+        }
+    }
+
+    /// Dispute `amount` of the transaction at time `at` (if known), the whole disputable amount if `None` (see [Self::disputable]).
+    ///
+    /// Several partial disputes can be open at the same time, and the undisputed rest of a resolved
+    /// transaction can still be disputed (with the default policy). Returns the disputed amount.
+    pub fn dispute(
+        &mut self,
+        policy: &TransitionPolicy,
+        amount: Option<Amount>,
+        at: Option<Timestamp>,
+    ) -> Result<Money, TransitionError> {
+        let mut disputed = *self;
+        disputed.transition(policy, TransactionStatus::Disputed)?;
+        let requested = disputed.hold(policy, amount)?;
+        let info = disputed.info_mut();
+        info.disputed_at = info.disputed_at.or(at);
+
+        *self = disputed;
+        Ok(requested)
+    }
+
+    /// Hold `amount` of the disputable part (all of it if `None`), whatever the status.
+    fn hold(
+        &mut self,
+        policy: &TransitionPolicy,
+        amount: Option<Amount>,
+    ) -> Result<Money, TransitionError> {
+        let disputable = self.disputable(policy);
+        let requested = self.in_currency(amount.unwrap_or(disputable.amount));
+        if requested.amount > disputable.amount {
+            let info = self.info();
+            return Err(TransitionError::ExceedsUndisputed {
+                client_id: info.client_id,
+                transaction_id: info.id,
+                requested,
+                undisputed: disputable,
+            });
+        }
+
+        self.hold_disputed(requested)?;
+        Ok(requested)
+    }
+
+    /// Hold `amount`: the never disputed part first, then the resolved part (re-dispute).
+    fn hold_disputed(&mut self, amount: Money) -> Result<(), TransitionError> {
+        let undisputed = self.undisputed();
+        let info = self.info_mut();
+        let mut redisputed = amount.amount;
+        if redisputed.try_subtract(undisputed.amount).is_some() {
+            info.disputes.resolved.try_subtract(redisputed).ok_or(
+                TransitionError::ExceedsUndisputed {
+                    client_id: info.client_id,
+                    transaction_id: info.id,
+                    requested: amount,
+                    undisputed,
+                },
+            )?;
+        }
+        info.disputes.held += amount.amount;
+
+        Ok(())
+    }
+
+    /// The entry to append for `kind` of `amount` at `timestamp`, see [Self::compensate].
+    pub fn compensation(
+        &self,
+        kind: CompensationKind,
+        amount: Money,
+        timestamp: Option<Timestamp>,
+    ) -> Compensation {
+        let info = self.info();
+        Compensation {
+            client_id: info.client_id,
+            transaction_id: info.id,
+            kind,
+            amount,
+            timestamp,
+        }
+    }
+
+    /// Apply an entry of the chain of the transaction, as [Self::dispute] and [Self::settle_dispute] did
+    /// when it was appended. The entries were validated against the policy then, they aren't again.
+    ///
+    /// A release or reversal of a transaction that isn't disputed was settled straight away:
+    /// the amount is held first.
+    pub fn compensate(&mut self, compensation: &Compensation) -> Result<(), TransitionError> {
+        let amount = compensation.amount;
+        let status = match compensation.kind {
+            CompensationKind::Hold => {
+                self.set_status(TransactionStatus::Disputed);
+                self.hold_disputed(amount)?;
+                let info = self.info_mut();
+                info.disputed_at = info.disputed_at.or(compensation.timestamp);
+                return Ok(());
+            }
+            CompensationKind::Release => TransactionStatus::Resolved,
+            CompensationKind::Reversal => match self.direction() {
+                Direction::Inbound => TransactionStatus::ChargedBack,
+                Direction::Outbound => TransactionStatus::Reversed,
+            },
+        };
+        if self.status() != TransactionStatus::Disputed {
+            self.hold_disputed(amount)?;
+        }
+        self.settle_held(status, Some(amount.amount))?;
+
+        Ok(())
+    }
+
+    /// Settle `amount` of the held disputes (all of them if `None`) with the given status:
+    /// `Resolved`, or `ChargedBack`/`Reversed` depending on the direction.
+    ///
+    /// The transaction stays `Disputed` while a part of it is still held.
+    ///
+    /// If the policy allows to settle a transaction that is not disputed (eg. a chargeback straight
+    /// from `Settled`), `amount` of the disputable part (all of it if `None`) is settled without
+    /// being held first, see [Settlement::was_held].
+    pub fn settle_dispute(
+        &mut self,
+        policy: &TransitionPolicy,
+        status: TransactionStatus,
+        amount: Option<Amount>,
+    ) -> Result<Settlement, TransitionError> {
+        // validate the transition, it's only taken once nothing is held anymore
+        let mut settled = *self;
+        settled.transition(policy, status)?;
+
+        if self.status() != TransactionStatus::Disputed {
+            // settled without a dispute: it's held and settled right away
+            let amount = settled.hold(policy, amount)?;
+            settled.settle_held(status, Some(amount.amount))?;
+            *self = settled;
+            return Ok(Settlement {
+                amount,
+                was_held: false,
+            });
+        }
+
+        let amount = self.settle_held(status, amount)?;
+        Ok(Settlement {
+            amount,
+            was_held: true,
+        })
+    }
+
+    /// Move `amount` (all of it if `None`) from held to settled with the given status,
+    /// the status is taken once nothing is held anymore.
+    fn settle_held(
+        &mut self,
+        status: TransactionStatus,
+        amount: Option<Amount>,
+    ) -> Result<Money, TransitionError> {
+        let held = self.held();
+        let requested = self.in_currency(amount.unwrap_or(held.amount));
+        let info = self.info_mut();
+        info.disputes
+            .held
+            .try_subtract(requested.amount)
+            .ok_or(TransitionError::ExceedsHeld {
+                client_id: info.client_id,
+                transaction_id: info.id,
+                requested,
+                held,
+            })?;
+        match status {
+            TransactionStatus::Resolved => info.disputes.resolved += requested.amount,
+            _ => info.disputes.charged_back += requested.amount,
+        }
+
+        if info.disputes.held == Amount::default() {
+            info.disputed_at = None;
+            self.set_status(status);
+        }
+        Ok(requested)
+    }
+
+    /// The amount in the currency of the transaction, rounded to its minor units.
+    pub fn in_currency(&self, amount: Amount) -> Money {
+        Money::new(amount.as_decimal(), self.info().amount.currency)
+    }
+
     pub fn status(&self) -> TransactionStatus {
         match self {
             Transaction::Inbound(inbound) => match inbound {
@@ -100,6 +464,9 @@ impl Transaction {
             },
             Transaction::Outbound(outbound) => match outbound {
                 OutboundTransaction::Settled(_) => TransactionStatus::Settled,
+                OutboundTransaction::Disputed(_) => TransactionStatus::Disputed,
+                OutboundTransaction::Resolved(_) => TransactionStatus::Resolved,
+                OutboundTransaction::Reversed(_) => TransactionStatus::Reversed,
             },
         }
     }
@@ -111,57 +478,92 @@ impl Transaction {
         }
     }
 
-    /// Transitions an inbound transaction through its state machine.
-    ///
-    /// State Machine:
-    /// ```text
-    /// ┌─────────┐
-    /// │ Settled │
-    /// └────┬────┘
-    ///      │ dispute
-    ///      ▼
-    /// ┌──────────┐
-    /// │ Disputed │
-    /// └────┬─────┘
-    ///      │
-    ///      ├─────── resolve ────────┐
-    ///      │                        ▼
-    ///      │                   ┌──────────┐
-    ///      │                   │ Resolved │
-    ///      │                   └──────────┘
-    ///      │
-    ///      └──── chargeback ───────┐
-    ///                              ▼
-    ///                         ┌────────────┐
-    ///                         │ ChargedBack│
-    ///                         └────────────┘
-    /// ```
+    /// Whether the transaction is in, or already went past, the given status.
     ///
-    /// Valid transitions:
-    /// - Settled → Disputed
-    /// - Disputed → Resolved
-    /// - Disputed → ChargedBack
+    /// Eg. a `Resolved` transaction went through `Disputed`, so a replayed dispute is already applied.
+    pub fn has_reached(&self, status: TransactionStatus) -> bool {
+        match (self.status(), status) {
+            (_, TransactionStatus::Settled) => true,
+            (
+                TransactionStatus::Disputed
+                | TransactionStatus::Resolved
+                | TransactionStatus::ChargedBack
+                | TransactionStatus::Reversed,
+                TransactionStatus::Disputed,
+            ) => true,
+            (current, status) => current == status,
+        }
+    }
+
+    /// Transitions the transaction to `status`, if the policy allows it for its direction.
     ///
-    /// Asumption: A Resolved dispute can't be charged back.
-    pub fn transition_inbound(&mut self, status: TransactionStatus) -> Result<(), TransitionError> {
-        if self.direction() != Direction::Inbound {
-            return Err(TransitionError::InvalidDirection);
+    /// See [TransitionPolicy::default] for the built-in state machines.
+    pub fn transition(
+        &mut self,
+        policy: &TransitionPolicy,
+        status: TransactionStatus,
+    ) -> Result<(), TransitionError> {
+        let info = *self.info();
+        let direction = self.direction();
+        if !policy.has_transitions(direction) {
+            return Err(TransitionError::InvalidDirection {
+                client_id: info.client_id,
+                transaction_id: info.id,
+                direction,
+            });
         }
 
         let old_status = self.status();
-        match (old_status, status) {
-            (TransactionStatus::Settled, TransactionStatus::Disputed) => {
-                *self = Transaction::Inbound(InboundTransaction::Disputed(*self.info()));
-            }
-            (TransactionStatus::Disputed, TransactionStatus::Resolved) => {
-                *self = Transaction::Inbound(InboundTransaction::Resolved(*self.info()));
-            }
-            (TransactionStatus::Disputed, TransactionStatus::ChargedBack) => {
-                *self = Transaction::Inbound(InboundTransaction::ChargedBack(*self.info()));
-            }
-            _ => return Err(TransitionError::InvalidTransition(old_status, status)),
+        if !policy.allows(direction, old_status, status) {
+            return Err(TransitionError::InvalidTransition {
+                client_id: info.client_id,
+                transaction_id: info.id,
+                current: old_status,
+                desired: status,
+            });
         }
 
+        self.set_status(status);
         Ok(())
     }
+
+    /// Move the transaction to `status`, keeping its details.
+    fn set_status(&mut self, status: TransactionStatus) {
+        let info = *self.info();
+        *self = match (self.direction(), status) {
+            (Direction::Inbound, TransactionStatus::Settled) => {
+                Transaction::Inbound(InboundTransaction::Settled(info))
+            }
+            (Direction::Inbound, TransactionStatus::Disputed) => {
+                Transaction::Inbound(InboundTransaction::Disputed(info))
+            }
+            (Direction::Inbound, TransactionStatus::Resolved) => {
+                Transaction::Inbound(InboundTransaction::Resolved(info))
+            }
+            (Direction::Inbound, TransactionStatus::ChargedBack | TransactionStatus::Reversed) => {
+                Transaction::Inbound(InboundTransaction::ChargedBack(info))
+            }
+            (Direction::Outbound, TransactionStatus::Settled) => {
+                Transaction::Outbound(OutboundTransaction::Settled(info))
+            }
+            (Direction::Outbound, TransactionStatus::Disputed) => {
+                Transaction::Outbound(OutboundTransaction::Disputed(info))
+            }
+            (Direction::Outbound, TransactionStatus::Resolved) => {
+                Transaction::Outbound(OutboundTransaction::Resolved(info))
+            }
+            (Direction::Outbound, TransactionStatus::ChargedBack | TransactionStatus::Reversed) => {
+                Transaction::Outbound(OutboundTransaction::Reversed(info))
+            }
+        };
+    }
+}
+
+/// Outcome of [Transaction::settle_dispute].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settlement {
+    /// The settled amount.
+    pub amount: Money,
+    /// Whether the amount was held by a dispute, `false` if the policy allowed to settle it straight away.
+    pub was_held: bool,
 }
@@ -11,6 +11,8 @@
 //! - **Disputes**: Freeze funds pending investigation
 //! - **Resolutions**: Release disputed funds back to available balance
 //! - **Chargebacks**: Reverse transactions and lock accounts
+//! - **Account events**: Admins unlock, freeze or close accounts (`Event::Unlock`, `Event::Freeze`, `Event::Close`),
+//!   the reason code is recorded on the account
 //!
 //! ## Architecture
 //!
@@ -32,14 +34,41 @@
 //! ## Key Features
 //!
 //! - **Type Safety**: Wrapper types prevent mixing of IDs (`ClientId`, `TransactionId`)
-//! - **Precise Arithmetic**: Uses `rust_decimal` for exact financial calculations (4 decimal places)
+//! - **Precise Arithmetic**: Uses `rust_decimal` for exact financial calculations, to the minor units of each currency
+//! - **Multi-currency**: Amounts are tagged with a currency (`Money`) and rounded to its minor unit
+//!   (eg. 0 decimals for JPY, 3 for BHD). Clients hold one balance per currency, disputes act on
+//!   the currency of the original transaction. Amounts without currency use `Currency::NONE` (`XXX`).
 //! - **State Machine**: Enforces valid transaction state transitions
+//! - **Negative Balances**: Rejected by default, a [policy::NegativeBalancePolicy] lets disputes and chargebacks
+//!   drive balances (`SignedAmount`) below zero, the client owes the `Balance::deficit`
+//! - **Timestamps**: Events may carry a `Timestamp` (`Event::at`, [TimedEvent]), stored with the transaction.
+//!   Events without one are booked at the time of the engine [clock::Clock] (injectable with `Engine::with_clock`),
+//!   their transactions have no timestamp and no dispute deadlines.
+//!   Events older than the latest event of their client are rejected or flagged ([policy::TimestampPolicy])
+//! - **Dispute Deadlines**: Disputes can be limited to a window after the transaction, and to a maximum time
+//!   in `Disputed` ([policy::DisputeDeadlines]). The engine settles expired disputes itself (`Engine::expire_disputes`),
+//!   the settlements are recorded as system events ([event_store::EventOrigin])
+//! - **Fees**: A [fees::FeeSchedule] per policy charges deposits, withdrawals and chargebacks. Fees are credited
+//!   to the house account (`Engine::revenue`) and stored as their own ledger entries (`FeePosting`)
+//! - **Risk Rules**: [risk::RiskRules] per policy are checked before deposits and withdrawals, a rule that fires
+//!   rejects the transaction (`EngineError::RiskRejected`). Rules implement [risk::RiskRule].
+//!   Admins approve transactions held for review (`Event::Approve`)
+//! - **Double-Entry Journal**: Every write posts balanced debit/credit lines between the available and held
+//!   funds of the client, the settlement and the house accounts ([ledger::journal]). Account balances are the sum
+//!   of the lines, `Engine::trial_balance` proves the books balance
+//! - **Compensating Entries**: Disputes, resolves and chargebacks append hold, release and reversal entries to
+//!   the original transaction instead of updating it, the status is derived from the chain
+//! - **Reconciliation**: `Engine::reconcile` recomputes the balances of every client from the transactions and
+//!   fees of the ledger and reports those that don't match ([reconciliation::Discrepancy])
 //! - **Async Ready**: Ledger trait uses async/await for future I/O operations
+//! - **Shareable**: `EngineHandle` is a cloneable, `Send + Sync` handle to an engine running in its own task
 //! - **Error Classification**: Distinguishes between partner errors (logged) and system errors (fatal)
 //!
 //! ## Transaction State Machine
 //!
-//! Inbound transactions (deposits) follow this state machine:
+//! The transitions are data, see [policy::TransitionPolicy]. `Engine::with_policies` selects the
+//! policy of an engine, or of the clients of a partner ([policy::Policies]).
+//! By default, inbound transactions (deposits) follow this state machine:
 //!
 //! ```text
 //! Settled → Disputed → Resolved
@@ -47,30 +76,57 @@
 //!         ChargedBack
 //! ```
 //!
-//! Outbound transactions (withdrawals) remain in `Settled` state (terminal).
+//! Outbound transactions (withdrawals) can be disputed too, a chargeback reverses them:
+//!
+//! ```text
+//! Settled → Disputed → Resolved
+//!             ↓
+//!          Reversed
+//! ```
+//!
+//! Disputing a withdrawal holds the withdrawn amount. Resolving drops it (the withdrawal stands),
+//! reversing credits it back to the available funds. Unlike a chargeback, a reversal doesn't lock the account.
+//!
+//! Dispute, resolve and chargeback events take an optional amount to act on a part of the transaction only.
+//! Several partial disputes can be open on one transaction, it stays `Disputed` while a part is held.
+//! The disputed portions are tracked per transaction (`Disputes`), a dispute of more than the undisputed
+//! rest is rejected (`EngineError::OverDispute`).
+//!
+//! ## Event Store
+//!
+//! Every event given to `Engine::apply` is appended to an [event_store::EventStore] with a
+//! sequence number and the rejection reason (if any). `Engine::rebuild_from` replays the accepted
+//! events into a fresh ledger and reports accounts that differ from the current state.
+//!
+//! An event that writes the ledger is recorded as accepted right before the write. If the process stops
+//! in between, `Engine::recover` applies the last recorded event again on startup (a replay if the ledger
+//! has it already), so the ledger and the event store agree.
 //!
 //! ## Usage Example
 //!
 //! ```rust,no_run
-//! use payment_engine::{Engine, Event, ledger::in_memory::InMemoryLedger, types::{ClientId, TransactionId, Amount}};
+//! use payment_engine::{Engine, Event, ledger::in_memory::InMemoryLedger, types::{ClientId, TransactionId, Currency, Money}};
+//! use rust_decimal::Decimal;
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     // Create engine with in-memory storage
-//!     let mut engine = Engine::new(InMemoryLedger::new());
+//!     let mut engine = Engine::new(InMemoryLedger::new()).await?;
 //!
 //!     // Process a deposit
 //!     let event = Event::Deposit {
 //!         client_id: ClientId::from(1),
 //!         transaction_id: TransactionId::from(1001),
-//!         amount: Amount::from_minor(10000), // $100.00
+//!         amount: Money::new(Decimal::new(10000, 2), Currency::new("USD").unwrap()), // $100.00
 //!     };
 //!
 //!     engine.apply(event).await?;
 //!
 //!     // Get account state
 //!     for account in engine.accounts() {
-//!         println!("Client {} balance: ${}", account.client_id, account.total);
+//!         for (currency, balance) in &account.balances {
+//!             println!("Client {} balance: {} {currency}", account.client_id, balance.total);
+//!         }
 //!     }
 //!
 //!     Ok(())
@@ -84,23 +140,34 @@
 //! - **Partner Errors**: Invalid data from external sources (continue processing)
 //!   - `InsufficientFunds`: Withdrawal exceeds available balance
 //!   - `DuplicateEvent`: Transaction ID already exists
-//!   - `AccountLocked`: Activity on frozen account
+//!   - `AccountLocked`: Activity on a locked account (by a chargeback or a freeze, see the reason)
+//!   - `AccountClosed`: Activity on a closed account
+//!   - `RiskRejected`: A risk rule rejected the transaction
+//!
+//!   Each error carries its context (client, transaction, statuses, amounts) as fields
+//!   and a stable code (`EngineError::code`), so callers don't need to parse the message.
 //!
 //! - **System Errors**: Internal invariant violations (halt processing)
 //!   - Should never occur in normal operation
 //!   - Indicates data corruption or logic errors
 //!
-//! ## Limitations
-//!
-//! Current implementation has known limitations (documented for transparency):
+//! ## Ledger Writes
 //!
-//! 1. **Non-Atomic Updates**: Ledger and account updates are separate operations
-//! 2. **In-Memory Only**: Default implementation doesn't persist to disk
-//! 3. **No Compensation**: Real systems would use compensating transactions for reversals
+//! `Engine::apply` is all-or-nothing: account changes are staged and only stored
+//! once the ledger write succeeded. The ledger stores the updated account along with
+//! each transaction, so a durable ledger (`FileLedger`) restores balances and lock flags
+//! on `Engine::new`.
 //!
-//! These are acceptable for the current use case but should be addressed for production systems.
+//! The ledger is append-only: a transaction is stored once and never rewritten. Disputes,
+//! resolves and chargebacks append compensating entries referencing it ([ledger::transactions::Compensation]),
+//! its current status is derived from that chain (`Engine::compensations`).
 
 mod engine;
+pub mod event_store;
 pub mod ledger;
 
-pub use engine::{ClientAccount, Engine, Event, errors, types};
+pub use engine::{
+    AccountMismatch, AccountStatus, Applied, Balance, Balances, ClientAccount, Engine,
+    EngineHandle, Event, Finished, ReasonCode, Rebuild, Receipt, ShardReport, ShardedEngine,
+    TimedEvent, clock, errors, fees, policy, reconciliation, risk, types,
+};
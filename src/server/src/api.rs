@@ -0,0 +1,196 @@
+//! HTTP/JSON API on top of the engine.
+//!
+//! - `POST /events`: apply an event, returns the receipt.
+//! - `GET /accounts`: all client accounts.
+//! - `GET /accounts/{client}`: a single client account.
+//! - `GET /accounts/{client}/transactions/{tx}`: a transaction of the client and its status.
+//! - `GET /health`: 200 while the engine accepts events, 503 once a system error stopped it.
+//!
+//! Partner errors (invalid events) are returned as 4xx with the error code and message.
+//! System errors are reported to the [Health] of the server and returned as 5xx without details.
+
+use axum::extract::{FromRef, Path, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use payment_engine::errors::{EngineError, SystemFault};
+use payment_engine::{EngineHandle, TimedEvent};
+
+use crate::api::models::{
+    AccountResponse, ErrorResponse, EventRequest, ReceiptResponse, TransactionResponse,
+};
+use crate::health::Health;
+
+pub mod models;
+
+pub fn router(engine: EngineHandle, health: Health) -> Router {
+    Router::new()
+        .route("/events", post(submit_event))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .route("/accounts/{client}/transactions/{tx}", get(get_transaction))
+        .route("/health", get(get_health))
+        .layer(middleware::map_response_with_state(
+            health.clone(),
+            report_system_errors,
+        ))
+        .with_state(AppState { engine, health })
+}
+
+#[derive(Debug, Clone)]
+struct AppState {
+    engine: EngineHandle,
+    health: Health,
+}
+
+impl FromRef<AppState> for EngineHandle {
+    fn from_ref(state: &AppState) -> Self {
+        state.engine.clone()
+    }
+}
+
+impl FromRef<AppState> for Health {
+    fn from_ref(state: &AppState) -> Self {
+        state.health.clone()
+    }
+}
+
+async fn submit_event(
+    State(engine): State<EngineHandle>,
+    Json(request): Json<EventRequest>,
+) -> Result<Json<ReceiptResponse>, ApiError> {
+    let event = TimedEvent::try_from(request)?;
+    let receipt = engine.apply(event).await?;
+
+    Ok(Json(ReceiptResponse::from(receipt)))
+}
+
+async fn list_accounts(
+    State(engine): State<EngineHandle>,
+) -> Result<Json<Vec<AccountResponse>>, ApiError> {
+    let accounts = engine.accounts().await?;
+
+    Ok(Json(accounts.iter().map(AccountResponse::from).collect()))
+}
+
+async fn get_account(
+    State(engine): State<EngineHandle>,
+    Path(client): Path<u16>,
+) -> Result<Json<AccountResponse>, ApiError> {
+    let account = engine
+        .account(client.into())
+        .await?
+        .ok_or(ApiError::NotFound("account not found"))?;
+
+    Ok(Json(AccountResponse::from(&account)))
+}
+
+async fn get_transaction(
+    State(engine): State<EngineHandle>,
+    Path((client, tx)): Path<(u16, u32)>,
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let transaction = engine
+        .transaction(client.into(), tx.into())
+        .await?
+        .ok_or(ApiError::NotFound("transaction not found"))?;
+
+    Ok(Json(TransactionResponse::from(&transaction)))
+}
+
+async fn get_health(State(health): State<Health>) -> Result<StatusCode, ApiError> {
+    match health.check().await {
+        Some(fault) => Err(ApiError::Stopped(fault)),
+        None => Ok(StatusCode::OK),
+    }
+}
+
+/// The system error behind a response is attached to it by [ApiError::into_response], report it here
+/// where the health of the server is at hand.
+async fn report_system_errors(State(health): State<Health>, response: Response) -> Response {
+    if let Some(err) = response.extensions().get::<EngineError>() {
+        health.report(err).await;
+    }
+
+    response
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is well-formed JSON but isn't a valid event.
+    InvalidRequest(&'static str),
+    NotFound(&'static str),
+    Engine(EngineError),
+    /// The engine stopped on a system error, see [Health].
+    Stopped(SystemFault),
+}
+
+impl From<EngineError> for ApiError {
+    fn from(err: EngineError) -> Self {
+        ApiError::Engine(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code, error) = match self {
+            ApiError::InvalidRequest(message) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                message.to_string(),
+            ),
+            ApiError::NotFound(message) => {
+                (StatusCode::NOT_FOUND, "not_found", message.to_string())
+            }
+            ApiError::Engine(err) if err.is_partner_error() => {
+                (engine_status(&err), err.code(), err.to_string())
+            }
+            ApiError::Engine(err) => {
+                let mut response = (
+                    engine_status(&err),
+                    Json(ErrorResponse {
+                        code: err.code(),
+                        error: "internal error".to_string(),
+                    }),
+                )
+                    .into_response();
+                response.extensions_mut().insert(err);
+                return response;
+            }
+            ApiError::Stopped(fault) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                EngineError::system(fault).code(),
+                fault.to_string(),
+            ),
+        };
+
+        (status, Json(ErrorResponse { code, error })).into_response()
+    }
+}
+
+fn engine_status(err: &EngineError) -> StatusCode {
+    match err {
+        EngineError::NonPositiveAmount { .. } | EngineError::PartialWithoutTimestamp { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        EngineError::AccountLocked { .. }
+        | EngineError::AccountClosed { .. }
+        | EngineError::RiskRejected { .. } => StatusCode::FORBIDDEN,
+        EngineError::InvalidTransactionStatus { .. }
+        | EngineError::NonZeroBalance { .. }
+        | EngineError::TimestampOutOfOrder { .. }
+        | EngineError::DisputeWindowClosed { .. }
+        | EngineError::DuplicateEvent { .. }
+        | EngineError::TransactionOfDifferentClient { .. }
+        | EngineError::ConflictingTransaction { .. } => StatusCode::CONFLICT,
+        EngineError::InvalidAssociatedTransaction { .. }
+        | EngineError::InsufficientFunds { .. }
+        | EngineError::OverDispute { .. }
+        | EngineError::ExceedsHeldAmount { .. }
+        | EngineError::TransactionNotFound { .. }
+        | EngineError::AccountNotFound { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        EngineError::SystemError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        EngineError::StorageError(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
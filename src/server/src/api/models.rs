@@ -0,0 +1,278 @@
+use payment_engine::ledger::transactions::{Direction, Transaction};
+use payment_engine::types::{Amount, Currency, Money, Timestamp};
+use payment_engine::{
+    AccountStatus, Applied, Balance, Balances, ClientAccount, Event, ReasonCode, Receipt,
+    TimedEvent,
+};
+use rust_decimal::Decimal;
+
+use crate::api::ApiError;
+
+/// An event, with the same fields as a row of the CLI input csv.
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct EventRequest {
+    #[serde(rename = "type")]
+    pub ty: EventType,
+    pub client: u16,
+    pub tx: u32,
+    /// Required for deposit/withdrawal, optional for a partial dispute/resolve/chargeback.
+    pub amount: Option<Decimal>,
+    /// ISO 4217 code of the amount, [Currency::NONE] if not provided.
+    pub currency: Option<String>,
+    /// Required for unlock/freeze/close, see [ReasonCode].
+    pub reason: Option<String>,
+    /// When the event happened, in seconds since the Unix epoch. The engine clock applies if not provided.
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Unlock,
+    Freeze,
+    Close,
+    /// Approve a transaction held for review, `tx` is the held transaction.
+    Approve,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct AccountResponse {
+    pub client: u16,
+    pub locked: bool,
+    /// `open`, `locked` or `closed`.
+    pub status: &'static str,
+    /// Reason of the last status change, see [ReasonCode].
+    pub reason: Option<&'static str>,
+    /// One entry per currency held by the client, ordered by currency.
+    pub balances: Vec<CurrencyBalanceResponse>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CurrencyBalanceResponse {
+    pub currency: String,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// What the client owes if a dispute drove the available funds negative.
+    pub deficit: Decimal,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct BalancesResponse {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub deficit: Decimal,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ReceiptResponse {
+    pub client: u16,
+    pub tx: u32,
+    /// `false` if the event was an exact replay of an already applied event.
+    pub applied: bool,
+    /// Currency of the transaction, the balances are in this currency.
+    pub currency: String,
+    pub before: BalancesResponse,
+    pub after: BalancesResponse,
+    /// Status of the transaction, absent for account events (unlock/freeze/close).
+    pub status: Option<String>,
+    pub locked: bool,
+    /// The event is older than the latest event of the client, applied as the policy flags it instead of rejecting it.
+    pub out_of_order: bool,
+    /// Fee charged for the event, in the currency of the transaction.
+    pub fee: Option<Decimal>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TransactionResponse {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub amount: Decimal,
+    pub currency: String,
+    pub status: String,
+    /// Seconds since the Unix epoch, absent for transactions stored before timestamps.
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorResponse {
+    /// Stable error code, see [payment_engine::errors::EngineError::code].
+    pub code: &'static str,
+    pub error: String,
+}
+
+impl TryFrom<EventRequest> for Event {
+    type Error = ApiError;
+
+    fn try_from(request: EventRequest) -> Result<Self, ApiError> {
+        let client_id = request.client.into();
+        let transaction_id = request.tx.into();
+        let currency = match &request.currency {
+            Some(code) => {
+                Currency::new(code).ok_or(ApiError::InvalidRequest("Invalid currency code"))?
+            }
+            None => Currency::NONE,
+        };
+        let reason = |missing| match &request.reason {
+            Some(code) => {
+                ReasonCode::new(code).ok_or(ApiError::InvalidRequest("Invalid reason code"))
+            }
+            None => Err(ApiError::InvalidRequest(missing)),
+        };
+
+        Ok(match request.ty {
+            EventType::Deposit => Event::Deposit {
+                client_id,
+                transaction_id,
+                amount: Money::new(
+                    request
+                        .amount
+                        .ok_or(ApiError::InvalidRequest("Amount is required for Deposit"))?,
+                    currency,
+                ),
+            },
+            EventType::Withdrawal => Event::Withdraw {
+                client_id,
+                transaction_id,
+                amount: Money::new(
+                    request.amount.ok_or(ApiError::InvalidRequest(
+                        "Amount is required for Withdrawal",
+                    ))?,
+                    currency,
+                ),
+            },
+            EventType::Dispute => Event::Dispute {
+                client_id,
+                transaction_id,
+                amount: request.amount.map(Amount::from),
+            },
+            EventType::Resolve => Event::Resolve {
+                client_id,
+                transaction_id,
+                amount: request.amount.map(Amount::from),
+            },
+            EventType::Chargeback => Event::Chargeback {
+                client_id,
+                transaction_id,
+                amount: request.amount.map(Amount::from),
+            },
+            EventType::Unlock => Event::Unlock {
+                client_id,
+                transaction_id,
+                reason: reason("Reason is required for Unlock")?,
+            },
+            EventType::Freeze => Event::Freeze {
+                client_id,
+                transaction_id,
+                reason: reason("Reason is required for Freeze")?,
+            },
+            EventType::Close => Event::Close {
+                client_id,
+                transaction_id,
+                reason: reason("Reason is required for Close")?,
+            },
+            EventType::Approve => Event::Approve {
+                client_id,
+                transaction_id,
+            },
+        })
+    }
+}
+
+impl TryFrom<EventRequest> for TimedEvent {
+    type Error = ApiError;
+
+    fn try_from(request: EventRequest) -> Result<Self, ApiError> {
+        let timestamp = request.timestamp.map(Timestamp::from);
+        Ok(TimedEvent {
+            event: Event::try_from(request)?,
+            timestamp,
+        })
+    }
+}
+
+impl From<&ClientAccount> for AccountResponse {
+    fn from(account: &ClientAccount) -> Self {
+        Self {
+            client: account.client_id.as_inner(),
+            locked: account.is_locked(),
+            status: match account.status {
+                AccountStatus::Open => "open",
+                AccountStatus::Locked => "locked",
+                AccountStatus::Closed => "closed",
+            },
+            reason: account.reason.map(|reason| reason.as_str()),
+            balances: account
+                .balances
+                .iter()
+                .map(|(currency, balance)| CurrencyBalanceResponse::new(currency, balance))
+                .collect(),
+        }
+    }
+}
+
+impl CurrencyBalanceResponse {
+    fn new(currency: &Currency, balance: &Balance) -> Self {
+        Self {
+            currency: currency.to_string(),
+            available: balance.available.as_decimal(),
+            held: balance.held().as_decimal(),
+            total: balance.total.as_decimal(),
+            deficit: balance.deficit().as_decimal(),
+        }
+    }
+}
+
+impl From<Balances> for BalancesResponse {
+    fn from(balances: Balances) -> Self {
+        Self {
+            available: balances.available.as_decimal(),
+            held: balances.held.as_decimal(),
+            total: balances.total.as_decimal(),
+            deficit: balances.deficit.as_decimal(),
+        }
+    }
+}
+
+impl From<Receipt> for ReceiptResponse {
+    fn from(receipt: Receipt) -> Self {
+        Self {
+            client: receipt.client_id.as_inner(),
+            tx: receipt.transaction_id.as_inner(),
+            applied: receipt.applied == Applied::New,
+            currency: receipt.currency.to_string(),
+            before: receipt.before.into(),
+            after: receipt.after.into(),
+            status: receipt.status.map(|status| status.to_string()),
+            locked: receipt.locked,
+            out_of_order: receipt.out_of_order,
+            fee: receipt.fee.map(|fee| fee.amount.as_decimal()),
+        }
+    }
+}
+
+impl From<&Transaction> for TransactionResponse {
+    fn from(transaction: &Transaction) -> Self {
+        let info = transaction.info();
+        Self {
+            client: info.client_id.as_inner(),
+            tx: info.id.as_inner(),
+            ty: match transaction.direction() {
+                Direction::Inbound => "deposit",
+                Direction::Outbound => "withdrawal",
+            },
+            amount: info.amount.amount.as_decimal(),
+            currency: info.amount.currency.to_string(),
+            status: transaction.status().to_string(),
+            timestamp: info.timestamp.map(|timestamp| timestamp.as_inner()),
+        }
+    }
+}
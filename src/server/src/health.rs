@@ -0,0 +1,56 @@
+//! Health of the server, shared by the API and the background tasks.
+//!
+//! System errors are reported here rather than where they happen. Once the engine stopped
+//! (see [payment_engine::Engine::fault]) the server is unhealthy: `GET /health` fails and [Health::stopped]
+//! resolves, so that the server shuts down instead of answering every further event with a 5xx.
+
+use std::sync::Arc;
+
+use payment_engine::EngineHandle;
+use payment_engine::errors::{EngineError, SystemFault};
+use tokio::sync::watch;
+
+/// Cloneable view of the engine's health, remembers the fault that stopped it.
+#[derive(Debug, Clone)]
+pub struct Health {
+    engine: EngineHandle,
+    fault: Arc<watch::Sender<Option<SystemFault>>>,
+}
+
+impl Health {
+    pub fn new(engine: EngineHandle) -> Self {
+        Self {
+            engine,
+            fault: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    /// Log a system error on stderr, then check whether it stopped the engine.
+    pub async fn report(&self, err: &EngineError) -> Option<SystemFault> {
+        eprintln!("System error: {err}");
+        self.check().await
+    }
+
+    /// The fault that stopped the engine, `None` while it accepts events.
+    pub async fn check(&self) -> Option<SystemFault> {
+        if let Some(fault) = *self.fault.borrow() {
+            return Some(fault);
+        }
+
+        let fault = self.engine.fault().await?;
+        self.fault.send_replace(Some(fault));
+        Some(fault)
+    }
+
+    /// Resolves with the fault that stopped the engine, once [Health::report] or [Health::check] saw it.
+    pub async fn stopped(&self) -> SystemFault {
+        let mut fault = self.fault.subscribe();
+        loop {
+            if let Some(fault) = *fault.borrow_and_update() {
+                return fault;
+            }
+            // Can't fail, `self` keeps the sender alive.
+            let _ = fault.changed().await;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use payment_engine::clock::{Clock, SystemClock};
+use payment_engine::event_store::file::FileEventStore;
+use payment_engine::ledger::{file::FileLedger, in_memory::InMemoryLedger};
+use payment_engine::{Engine, EngineHandle};
+use payment_engine_server::api;
+use payment_engine_server::health::Health;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Payment Engine HTTP/JSON API")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Directory of a durable ledger and event store. The server continues on top of the state stored there.
+    /// Transactions and events are kept in memory only if not provided.
+    #[arg(long)]
+    ledger_dir: Option<PathBuf>,
+
+    /// Seconds between two sweeps settling the disputes held for too long, see `Engine::expire_disputes`.
+    /// Disputes of a client are also settled before its next event.
+    #[arg(long, default_value_t = 60)]
+    expiry_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let engine = match args.ledger_dir {
+        Some(dir) => {
+            let ledger = FileLedger::open(&dir)?;
+            let events = FileEventStore::open(dir.join("events"))?;
+            let mut engine = Engine::with_event_store(ledger, events).await?;
+            engine.recover().await?;
+            EngineHandle::spawn(engine)
+        }
+        None => EngineHandle::spawn(Engine::new(InMemoryLedger::new()).await?),
+    };
+
+    let health = Health::new(engine.clone());
+    tokio::spawn(sweep_expired_disputes(
+        engine.clone(),
+        health.clone(),
+        Duration::from_secs(args.expiry_interval.max(1)),
+    ));
+
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let stopped = health.clone();
+    axum::serve(listener, api::router(engine, health.clone()))
+        .with_graceful_shutdown(async move {
+            stopped.stopped().await;
+        })
+        .await?;
+
+    // The engine refuses every further event, exit with an error so that the server is restarted
+    // (recovering the last recorded event, see `Engine::recover`).
+    match health.check().await {
+        Some(fault) => Err(fault.into()),
+        None => Ok(()),
+    }
+}
+
+/// Settle the expired disputes of all clients every `period`, at the system time.
+/// Stops once a system error stopped the engine.
+async fn sweep_expired_disputes(engine: EngineHandle, health: Health, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(err) = engine.expire_disputes(SystemClock.now()).await
+            && health.report(&err).await.is_some()
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use payment_engine::errors::SystemFault;
+use payment_engine::event_store::{EventOrigin, EventStore, EventStoreError, RecordedEvent};
+use payment_engine::ledger::in_memory::InMemoryLedger;
+use payment_engine::{Engine, EngineHandle, TimedEvent};
+use payment_engine_server::api;
+use payment_engine_server::health::Health;
+
+async fn spawn_server() -> SocketAddr {
+    let engine = EngineHandle::spawn(Engine::new(InMemoryLedger::new()).await.unwrap());
+    serve(engine).await.0
+}
+
+/// Serve the engine until it stops, as the server binary does.
+async fn serve(engine: EngineHandle) -> (SocketAddr, Health, JoinHandle<std::io::Result<()>>) {
+    let health = Health::new(engine.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = api::router(engine, health.clone());
+    let stopped = health.clone();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, router)
+            .with_graceful_shutdown(async move {
+                stopped.stopped().await;
+            })
+            .await
+    });
+
+    (addr, health, server)
+}
+
+/// Event store whose appends fail.
+#[derive(Debug)]
+struct FailingEventStore;
+
+impl EventStore for FailingEventStore {
+    async fn append(
+        &mut self,
+        _event: TimedEvent,
+        _origin: EventOrigin,
+        _rejection: Option<String>,
+    ) -> Result<u64, EventStoreError> {
+        Err(EventStoreError::Storage("injected failure".to_string()))
+    }
+
+    async fn events(&self) -> Result<Vec<RecordedEvent>, EventStoreError> {
+        Ok(vec![])
+    }
+}
+
+/// Minimal HTTP/1.1 client, returns the status code and the JSON body.
+async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).unwrap()
+    };
+
+    (status, body)
+}
+
+#[tokio::test]
+async fn submit_and_query() {
+    let addr = spawn_server().await;
+
+    let (status, receipt) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(receipt["applied"], json!(true));
+    assert_eq!(receipt["after"]["available"], json!("2.5"));
+
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "dispute", "client": 1, "tx": 1})),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let (status, account) = request(addr, "GET", "/accounts/1", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        account,
+        json!({
+            "client": 1,
+            "locked": false,
+            "status": "open",
+            "reason": null,
+            "balances": [{"currency": "XXX", "available": "0.0", "held": "2.5", "total": "2.5", "deficit": "0.0"}]
+        })
+    );
+
+    let (status, accounts) = request(addr, "GET", "/accounts", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(accounts.as_array().unwrap().len(), 1);
+
+    let (status, transaction) = request(addr, "GET", "/accounts/1/transactions/1", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(transaction["type"], json!("deposit"));
+    assert_eq!(transaction["status"], json!("Disputed"));
+
+    let (status, receipt) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "freeze", "client": 1, "tx": 2, "reason": "compliance"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(receipt["status"], json!(null));
+    assert_eq!(receipt["locked"], json!(true));
+
+    let (status, account) = request(addr, "GET", "/accounts/1", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(account["status"], json!("locked"));
+    assert_eq!(account["reason"], json!("compliance"));
+}
+
+#[tokio::test]
+async fn unknown_resources_are_not_found() {
+    let addr = spawn_server().await;
+
+    let (status, _) = request(addr, "GET", "/accounts/7", None).await;
+    assert_eq!(status, 404);
+
+    let (status, _) = request(addr, "GET", "/accounts/7/transactions/1", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn partner_errors_are_client_errors() {
+    let addr = spawn_server().await;
+
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "deposit", "client": 1, "tx": 1})),
+    )
+    .await;
+    assert_eq!(status, 400);
+
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "deposit", "client": 1, "tx": 1, "amount": "-1"})),
+    )
+    .await;
+    assert_eq!(status, 400);
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "1"})),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(body["code"], json!("insufficient_funds"));
+
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "deposit", "client": 1, "tx": 3, "amount": "1"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    // Resolving an undisputed transaction
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "resolve", "client": 1, "tx": 3})),
+    )
+    .await;
+    assert_eq!(status, 409);
+}
+
+#[tokio::test]
+async fn stopped_engine_shuts_the_server_down() {
+    let engine = Engine::with_event_store(InMemoryLedger::new(), FailingEventStore)
+        .await
+        .unwrap();
+    let (addr, health, server) = serve(EngineHandle::spawn(engine)).await;
+
+    let (status, _) = request(addr, "GET", "/health", None).await;
+    assert_eq!(status, 200);
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/events",
+        Some(json!({"type": "deposit", "client": 1, "tx": 1, "amount": "1"})),
+    )
+    .await;
+    assert_eq!(status, 503);
+    assert_eq!(
+        body,
+        json!({"code": "storage_error", "error": "internal error"})
+    );
+
+    server.await.unwrap().unwrap();
+    assert_eq!(health.check().await, Some(SystemFault::Unrecorded));
+    assert!(TcpStream::connect(addr).await.is_err());
+}